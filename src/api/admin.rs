@@ -1,34 +1,59 @@
+use crate::api::audit::AuditContext;
+use crate::api::middleware;
+use crate::domain::{
+    HistoryRange, LogFilter, LogQuery, LogsResponse, NewAccessRule, NewAlertRule,
+    NewAnnouncement, NewVpnPeer, NewWebhookSubscription,
+};
 use crate::state::AppState;
 use axum::{
     body::Body,
-    extract::{Query, State},
+    extract::{Path, Query, State},
     http::{HeaderMap, StatusCode, header},
     response::{IntoResponse, Json, Response},
 };
+use serde::Deserialize;
 use serde_json::json;
 use std::env;
 
 pub async fn get_system_status(State(state): State<AppState>) -> impl IntoResponse {
-    let mut sys = state.system.lock().unwrap();
-    sys.refresh_cpu_all();
-    sys.refresh_memory();
-
-    let uptime = state.start_time.elapsed().as_secs();
-    let total_mem = sys.total_memory() / 1024 / 1024; // MB
-    let used_mem = sys.used_memory() / 1024 / 1024; // MB
-    let cpu_usage = sys.global_cpu_usage();
+    let system = state.system_snapshot();
+    let total_mem = system.ram_total / 1024 / 1024; // MB
+    let used_mem = system.ram_used / 1024 / 1024; // MB
+    let rejections: serde_json::Map<String, serde_json::Value> = state
+        .connection_limiter
+        .rejection_counts()
+        .into_iter()
+        .map(|(reason, count)| (reason.to_string(), json!(count)))
+        .collect();
 
     Json(json!({
-        "uptime_seconds": uptime,
+        "uptime_seconds": system.uptime_secs,
         "memory_used_mb": used_mem,
         "memory_total_mb": total_mem,
-        "cpu_usage_percent": cpu_usage
+        "cpu_usage_percent": system.cpu,
+        "connection_rejections": rejections
     }))
     .into_response()
 }
 
-// Helper to get log path
-use crate::domain::{LogQuery, LogsResponse};
+#[derive(Deserialize)]
+pub struct HistoryParams {
+    pub range: Option<String>,
+}
+
+pub async fn get_metrics_history(
+    State(state): State<AppState>,
+    Query(params): Query<HistoryParams>,
+) -> impl IntoResponse {
+    match HistoryRange::parse(params.range.as_deref().unwrap_or("1h")) {
+        Some(range) => Json(state.get_metrics_history(range)).into_response(),
+        None => (
+            StatusCode::BAD_REQUEST,
+            Json(json!({"error": "range must be one of 15m, 1h, 6h, 24h, 7d, 30d"})),
+        )
+            .into_response(),
+    }
+}
 
 pub async fn get_logs(
     State(state): State<AppState>,
@@ -39,6 +64,180 @@ pub async fn get_logs(
     Json(LogsResponse { data, meta, stats }).into_response()
 }
 
+pub async fn list_log_archives(State(state): State<AppState>) -> impl IntoResponse {
+    Json(state.log_repository.list_archives()).into_response()
+}
+
+pub async fn download_log_archive(
+    State(state): State<AppState>,
+    audit: AuditContext,
+    Path(name): Path<String>,
+) -> impl IntoResponse {
+    match state.log_repository.read_archive(&name) {
+        Ok(content) => {
+            audit.record(&state, "archive.download", json!({"archive": name}));
+            (
+                [
+                    (header::CONTENT_TYPE, "text/csv".to_string()),
+                    (
+                        header::CONTENT_DISPOSITION,
+                        format!("attachment; filename=\"{}\"", name),
+                    ),
+                ],
+                content,
+            )
+                .into_response()
+        }
+        Err(e) => (StatusCode::NOT_FOUND, Json(json!({"error": e.to_string()}))).into_response(),
+    }
+}
+
+pub async fn restore_log_archive(
+    State(state): State<AppState>,
+    audit: AuditContext,
+    Path(name): Path<String>,
+) -> impl IntoResponse {
+    match state.log_repository.restore_archive(&name) {
+        Ok(records) => {
+            audit.record(
+                &state,
+                "archive.restore",
+                json!({"archive": name, "records": records}),
+            );
+            (
+                StatusCode::OK,
+                Json(json!({"status": "restored", "records": records})),
+            )
+                .into_response()
+        }
+        Err(e) => (
+            StatusCode::BAD_REQUEST,
+            Json(json!({"error": e.to_string()})),
+        )
+            .into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct PurgeParams {
+    // Must repeat the archive name, so a stray DELETE can't destroy data
+    pub confirm: Option<String>,
+}
+
+pub async fn purge_log_archive(
+    State(state): State<AppState>,
+    audit: AuditContext,
+    Path(name): Path<String>,
+    Query(params): Query<PurgeParams>,
+) -> impl IntoResponse {
+    if params.confirm.as_deref() != Some(name.as_str()) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({"error": "Purging requires ?confirm=<archive name>"})),
+        )
+            .into_response();
+    }
+
+    match state.log_repository.purge_archive(&name) {
+        Ok(true) => {
+            audit.record(&state, "archive.purge", json!({"archive": name}));
+            (StatusCode::OK, Json(json!({"status": "purged"}))).into_response()
+        }
+        Ok(false) => (StatusCode::NOT_FOUND, Json(json!({"error": "Not found"}))).into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({"error": e.to_string()})),
+        )
+            .into_response(),
+    }
+}
+
+pub async fn erase_log_events(
+    State(state): State<AppState>,
+    audit: AuditContext,
+    Json(filter): Json<LogFilter>,
+) -> impl IntoResponse {
+    let filter = state.subject_filter(filter);
+    match state.log_repository.delete_matching(&filter) {
+        Ok(removed) => {
+            audit.record(
+                &state,
+                "logs.erase",
+                json!({"device_id": filter.device_id, "ip": filter.ip, "removed": removed}),
+            );
+            (StatusCode::OK, Json(json!({"removed": removed}))).into_response()
+        }
+        Err(e) => (
+            StatusCode::BAD_REQUEST,
+            Json(json!({"error": e.to_string()})),
+        )
+            .into_response(),
+    }
+}
+
+pub async fn find_subject_events(
+    State(state): State<AppState>,
+    audit: AuditContext,
+    Query(filter): Query<LogFilter>,
+) -> impl IntoResponse {
+    let filter = state.subject_filter(filter);
+    if filter.is_empty() {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(json!({"error": "Filter must name a device_id or ip"})),
+        )
+            .into_response();
+    }
+
+    let events = state.log_repository.find_matching(&filter);
+    audit.record(
+        &state,
+        "subject.search",
+        json!({"device_id": filter.device_id, "ip": filter.ip, "found": events.len()}),
+    );
+    Json(events).into_response()
+}
+
+pub async fn export_subject_events(
+    State(state): State<AppState>,
+    audit: AuditContext,
+    Query(filter): Query<LogFilter>,
+) -> impl IntoResponse {
+    let filter = state.subject_filter(filter);
+    if filter.is_empty() {
+        return (StatusCode::BAD_REQUEST, "Filter must name a device_id or ip").into_response();
+    }
+
+    let events = state.log_repository.find_matching(&filter);
+    audit.record(
+        &state,
+        "subject.export",
+        json!({"device_id": filter.device_id, "ip": filter.ip, "found": events.len()}),
+    );
+
+    let export = json!({
+        "subject": {"device_id": filter.device_id, "ip": filter.ip},
+        "ip_privacy": state.ip_privacy.as_str(),
+        "exported_at": chrono::Local::now().format("%Y-%m-%d %H:%M:%S %z").to_string(),
+        "events": events,
+    });
+    (
+        [
+            (header::CONTENT_TYPE, "application/json"),
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"subject_export.json\"",
+            ),
+        ],
+        serde_json::to_string_pretty(&export).unwrap(),
+    )
+        .into_response()
+}
+
+pub async fn verify_logs(State(state): State<AppState>) -> impl IntoResponse {
+    Json(state.log_repository.verify_integrity()).into_response()
+}
+
 fn check_auth(headers: &HeaderMap) -> bool {
     let expected = match env::var("ADMIN_PASSWORD") {
         Ok(v) => v,
@@ -48,11 +247,14 @@ fn check_auth(headers: &HeaderMap) -> bool {
     headers
         .get("x-admin-password")
         .and_then(|h| h.to_str().ok())
-        .map(|pwd| pwd == expected)
-        .unwrap_or(false)
+        .is_some_and(|pwd| middleware::secret_matches(pwd, &expected))
 }
 
-pub async fn clear_logs(State(state): State<AppState>, headers: HeaderMap) -> impl IntoResponse {
+pub async fn clear_logs(
+    State(state): State<AppState>,
+    audit: AuditContext,
+    headers: HeaderMap,
+) -> impl IntoResponse {
     if !check_auth(&headers) {
         return (
             StatusCode::UNAUTHORIZED,
@@ -61,8 +263,17 @@ pub async fn clear_logs(State(state): State<AppState>, headers: HeaderMap) -> im
             .into_response();
     }
 
-    match state.log_repository.clear() {
-        Ok(_) => (StatusCode::OK, Json(json!({"status": "cleared"}))).into_response(),
+    // Clearing archives the log; only `purge_archive` deletes data for good
+    match state.log_repository.archive() {
+        Ok(archive) => {
+            let name = archive.map(|a| a.name);
+            audit.record(&state, "logs.archive", json!({"archive": name}));
+            (
+                StatusCode::OK,
+                Json(json!({"status": "archived", "archive": name})),
+            )
+                .into_response()
+        }
         Err(e) => (
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(json!({"error": e.to_string()})),
@@ -71,13 +282,18 @@ pub async fn clear_logs(State(state): State<AppState>, headers: HeaderMap) -> im
     }
 }
 
-pub async fn download_logs(State(state): State<AppState>, headers: HeaderMap) -> impl IntoResponse {
+pub async fn download_logs(
+    State(state): State<AppState>,
+    audit: AuditContext,
+    headers: HeaderMap,
+) -> impl IntoResponse {
     if !check_auth(&headers) {
         return (StatusCode::UNAUTHORIZED, "Unauthorized").into_response();
     }
 
     match state.log_repository.get_raw_content() {
         Ok(content) => {
+            audit.record(&state, "logs.export", json!({"bytes": content.len()}));
             // Create csv filename with today date?
             // Simple "server_logs.csv" is fine or maybe "logs_TIMESTAMP.csv".
             // Let's stick to simple "server_logs.csv".
@@ -97,8 +313,315 @@ pub async fn download_logs(State(state): State<AppState>, headers: HeaderMap) ->
     }
 }
 
+pub async fn list_access_rules(State(state): State<AppState>) -> impl IntoResponse {
+    Json(state.access_list.list()).into_response()
+}
+
+pub async fn create_access_rule(
+    State(state): State<AppState>,
+    audit: AuditContext,
+    Json(rule): Json<NewAccessRule>,
+) -> impl IntoResponse {
+    match state.access_list.add(rule) {
+        Ok(rule) => {
+            audit.record(&state, "access_rule.add", json!(rule));
+            (StatusCode::CREATED, Json(json!(rule))).into_response()
+        }
+        Err(e) => (
+            StatusCode::BAD_REQUEST,
+            Json(json!({"error": e.to_string()})),
+        )
+            .into_response(),
+    }
+}
+
+pub async fn delete_access_rule(
+    State(state): State<AppState>,
+    audit: AuditContext,
+    Path(id): Path<u64>,
+) -> impl IntoResponse {
+    match state.access_list.remove(id) {
+        Ok(true) => {
+            audit.record(&state, "access_rule.remove", json!({"id": id}));
+            (StatusCode::OK, Json(json!({"status": "removed"}))).into_response()
+        }
+        Ok(false) => (StatusCode::NOT_FOUND, Json(json!({"error": "Not found"}))).into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({"error": e.to_string()})),
+        )
+            .into_response(),
+    }
+}
+
+pub async fn get_alerts(State(state): State<AppState>) -> impl IntoResponse {
+    Json(state.get_alerts()).into_response()
+}
+
+pub async fn list_alert_rules(State(state): State<AppState>) -> impl IntoResponse {
+    Json(state.alert_rules.list()).into_response()
+}
+
+pub async fn create_alert_rule(
+    State(state): State<AppState>,
+    audit: AuditContext,
+    Json(rule): Json<NewAlertRule>,
+) -> impl IntoResponse {
+    match state.alert_rules.add(rule) {
+        Ok(rule) => {
+            audit.record(&state, "alert_rule.add", json!(rule));
+            (StatusCode::CREATED, Json(json!(rule))).into_response()
+        }
+        Err(e) => (
+            StatusCode::BAD_REQUEST,
+            Json(json!({"error": e.to_string()})),
+        )
+            .into_response(),
+    }
+}
+
+pub async fn delete_alert_rule(
+    State(state): State<AppState>,
+    audit: AuditContext,
+    Path(id): Path<u64>,
+) -> impl IntoResponse {
+    match state.alert_rules.remove(id) {
+        Ok(true) => {
+            audit.record(&state, "alert_rule.remove", json!({"id": id}));
+            (StatusCode::OK, Json(json!({"status": "removed"}))).into_response()
+        }
+        Ok(false) => (StatusCode::NOT_FOUND, Json(json!({"error": "Not found"}))).into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({"error": e.to_string()})),
+        )
+            .into_response(),
+    }
+}
+
+pub async fn list_webhooks(State(state): State<AppState>) -> impl IntoResponse {
+    let subscriptions: Vec<_> = state
+        .webhook_subscriptions
+        .list()
+        .into_iter()
+        .map(|s| s.without_secret())
+        .collect();
+    Json(subscriptions).into_response()
+}
+
+/// The response is the only place the subscription's secret is shown.
+pub async fn create_webhook(
+    State(state): State<AppState>,
+    audit: AuditContext,
+    Json(subscription): Json<NewWebhookSubscription>,
+) -> impl IntoResponse {
+    match state.webhook_subscriptions.add(subscription) {
+        Ok(subscription) => {
+            audit.record(
+                &state,
+                "webhook.add",
+                json!(subscription.clone().without_secret()),
+            );
+            (StatusCode::CREATED, Json(json!(subscription))).into_response()
+        }
+        Err(e) => (
+            StatusCode::BAD_REQUEST,
+            Json(json!({"error": e.to_string()})),
+        )
+            .into_response(),
+    }
+}
+
+pub async fn delete_webhook(
+    State(state): State<AppState>,
+    audit: AuditContext,
+    Path(id): Path<u64>,
+) -> impl IntoResponse {
+    match state.webhook_subscriptions.remove(id) {
+        Ok(true) => {
+            audit.record(&state, "webhook.remove", json!({"id": id}));
+            (StatusCode::OK, Json(json!({"status": "removed"}))).into_response()
+        }
+        Ok(false) => (StatusCode::NOT_FOUND, Json(json!({"error": "Not found"}))).into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({"error": e.to_string()})),
+        )
+            .into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct DeliveriesQuery {
+    #[serde(default = "default_deliveries_limit")]
+    pub limit: usize,
+}
+
+fn default_deliveries_limit() -> usize {
+    100
+}
+
+pub async fn list_webhook_deliveries(
+    State(state): State<AppState>,
+    Query(params): Query<DeliveriesQuery>,
+) -> impl IntoResponse {
+    Json(state.webhook_deliveries.recent(params.limit)).into_response()
+}
+
+pub async fn list_announcements(State(state): State<AppState>) -> impl IntoResponse {
+    Json(state.announcements.history()).into_response()
+}
+
+pub async fn create_announcement(
+    State(state): State<AppState>,
+    audit: AuditContext,
+    Json(announcement): Json<NewAnnouncement>,
+) -> impl IntoResponse {
+    match state.announce(announcement) {
+        Ok(announcement) => {
+            audit.record(&state, "announcement.create", json!(announcement));
+            (StatusCode::CREATED, Json(json!(announcement))).into_response()
+        }
+        Err(e) => (
+            StatusCode::BAD_REQUEST,
+            Json(json!({"error": e.to_string()})),
+        )
+            .into_response(),
+    }
+}
+
+pub async fn end_announcement(
+    State(state): State<AppState>,
+    audit: AuditContext,
+    Path(id): Path<u64>,
+) -> impl IntoResponse {
+    match state.end_announcement(id) {
+        Ok(Some(announcement)) => {
+            audit.record(&state, "announcement.end", json!({"id": id}));
+            (StatusCode::OK, Json(json!(announcement))).into_response()
+        }
+        Ok(None) => (StatusCode::NOT_FOUND, Json(json!({"error": "Not found"}))).into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({"error": e.to_string()})),
+        )
+            .into_response(),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct AuditQuery {
+    #[serde(default = "default_audit_limit")]
+    pub limit: usize,
+}
+
+fn default_audit_limit() -> usize {
+    100
+}
+
+pub async fn get_audit_log(
+    State(state): State<AppState>,
+    Query(params): Query<AuditQuery>,
+) -> impl IntoResponse {
+    Json(state.audit_log.recent(params.limit)).into_response()
+}
+
+pub async fn download_audit_log(
+    State(state): State<AppState>,
+    audit: AuditContext,
+) -> impl IntoResponse {
+    // Exporting the trail is itself recorded, before the content is read
+    audit.record(&state, "audit.export", json!({}));
+
+    match state.audit_log.get_raw_content() {
+        Ok(content) => (
+            [
+                (header::CONTENT_TYPE, "application/x-ndjson"),
+                (
+                    header::CONTENT_DISPOSITION,
+                    "attachment; filename=\"audit_log.jsonl\"",
+                ),
+            ],
+            content,
+        )
+            .into_response(),
+        Err(_) => (StatusCode::NOT_FOUND, "No audit log found").into_response(),
+    }
+}
+
 pub async fn logout_handler() -> impl IntoResponse {
     let mut response = Response::new(Body::empty());
     *response.status_mut() = StatusCode::OK;
     response
 }
+
+pub async fn list_vpn_peers(State(state): State<AppState>) -> impl IntoResponse {
+    Json(state.get_vpn_peers()).into_response()
+}
+
+pub async fn get_vpn_peer(
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> impl IntoResponse {
+    match state.get_vpn_peers().into_iter().find(|p| p.peer.id == id) {
+        Some(peer) => Json(json!(peer)).into_response(),
+        None => (StatusCode::NOT_FOUND, Json(json!({"error": "Not found"}))).into_response(),
+    }
+}
+
+pub async fn create_vpn_peer(
+    State(state): State<AppState>,
+    audit: AuditContext,
+    Json(peer): Json<NewVpnPeer>,
+) -> impl IntoResponse {
+    match state.vpn_peers.add(peer) {
+        Ok(peer) => {
+            audit.record(&state, "vpn_peer.add", json!(peer));
+            (StatusCode::CREATED, Json(json!(state.vpn_peer_status(peer)))).into_response()
+        }
+        Err(e) => (
+            StatusCode::BAD_REQUEST,
+            Json(json!({"error": e.to_string()})),
+        )
+            .into_response(),
+    }
+}
+
+pub async fn update_vpn_peer(
+    State(state): State<AppState>,
+    audit: AuditContext,
+    Path(id): Path<u64>,
+    Json(peer): Json<NewVpnPeer>,
+) -> impl IntoResponse {
+    match state.vpn_peers.update(id, peer) {
+        Ok(Some(peer)) => {
+            audit.record(&state, "vpn_peer.update", json!(peer));
+            (StatusCode::OK, Json(json!(state.vpn_peer_status(peer)))).into_response()
+        }
+        Ok(None) => (StatusCode::NOT_FOUND, Json(json!({"error": "Not found"}))).into_response(),
+        Err(e) => (
+            StatusCode::BAD_REQUEST,
+            Json(json!({"error": e.to_string()})),
+        )
+            .into_response(),
+    }
+}
+
+pub async fn delete_vpn_peer(
+    State(state): State<AppState>,
+    audit: AuditContext,
+    Path(id): Path<u64>,
+) -> impl IntoResponse {
+    match state.vpn_peers.remove(id) {
+        Ok(true) => {
+            audit.record(&state, "vpn_peer.remove", json!({"id": id}));
+            (StatusCode::OK, Json(json!({"status": "removed"}))).into_response()
+        }
+        Ok(false) => (StatusCode::NOT_FOUND, Json(json!({"error": "Not found"}))).into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(json!({"error": e.to_string()})),
+        )
+            .into_response(),
+    }
+}
@@ -0,0 +1,50 @@
+use crate::api::auth;
+use crate::api::client_ip::ClientIp;
+use crate::api::csrf;
+use crate::state::AppState;
+use axum::{
+    extract::FromRequestParts,
+    http::{StatusCode, header, request::Parts},
+};
+use axum_extra::extract::cookie::{Key, SignedCookieJar};
+
+/// Who is making an admin request, for the audit trail.
+///
+/// Extract it in any handler that changes state, then call `record`.
+#[derive(Debug, Clone)]
+pub struct AuditContext {
+    pub actor: String,
+    pub ip: String,
+}
+
+impl FromRequestParts<AppState> for AuditContext {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        let ClientIp(ip) = ClientIp::from_request_parts(parts, state).await?;
+        let jar = SignedCookieJar::<Key>::from_request_parts(parts, state)
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+        let actor = if jar.get(csrf::SESSION_COOKIE).is_some() {
+            jar.get(auth::USER_COOKIE)
+                .map(|c| c.value().to_string())
+                .unwrap_or_else(|| "admin".to_string())
+        } else if parts.headers.contains_key(header::AUTHORIZATION) {
+            "api-token".to_string()
+        } else {
+            "anonymous".to_string()
+        };
+
+        Ok(AuditContext {
+            actor,
+            ip: ip.to_string(),
+        })
+    }
+}
+
+impl AuditContext {
+    pub fn record(&self, state: &AppState, action: &str, details: serde_json::Value) {
+        state.audit(&self.actor, &self.ip, action, details);
+    }
+}
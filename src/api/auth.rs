@@ -1,17 +1,27 @@
+use crate::api::audit::AuditContext;
+use crate::api::client_ip::ClientIp;
+use crate::api::csrf;
 use crate::api::htmx::HtmlTemplate;
+use crate::api::middleware;
+use crate::state::AppState;
 use askama::Template;
 use axum::{
-    extract::Form,
+    extract::{Form, State},
     response::{IntoResponse, Redirect},
 };
 use axum_extra::extract::cookie::{Cookie, SameSite, SignedCookieJar};
 use serde::Deserialize;
+use serde_json::json;
 use std::env;
 
+// Signed cookie naming the logged-in admin, for the audit trail
+pub const USER_COOKIE: &str = "auth_user";
+
 #[derive(Template)]
 #[template(path = "login.htmx", escape = "html")]
 pub struct LoginTemplate {
     pub error: Option<String>,
+    pub csrf_token: String,
 }
 
 #[derive(Deserialize)]
@@ -20,43 +30,100 @@ pub struct LoginPayload {
     pub password: String,
 }
 
-pub async fn login_page(jar: SignedCookieJar) -> impl IntoResponse {
+pub async fn login_page(State(state): State<AppState>, jar: SignedCookieJar) -> impl IntoResponse {
     // If already logged in, redirect to admin
     if jar.get("auth_token").is_some() {
         return Redirect::to("/admin").into_response();
     }
-    HtmlTemplate(LoginTemplate { error: None }).into_response()
+
+    // Bind the login form to a pre-login session so it can carry a CSRF token
+    let (jar, session_id) = match csrf::session_id(&jar) {
+        Some(id) => (jar, id),
+        None => {
+            let id = csrf::new_session_id();
+            let cookie = Cookie::build((csrf::PRE_SESSION_COOKIE, id.clone()))
+                .path("/")
+                .http_only(true)
+                .same_site(SameSite::Strict)
+                .build();
+            (jar.add(cookie), id)
+        }
+    };
+
+    let csrf_token = csrf::token_for(&state.key, &session_id);
+    (
+        jar,
+        HtmlTemplate(LoginTemplate {
+            error: None,
+            csrf_token,
+        }),
+    )
+        .into_response()
 }
 
 pub async fn login_submit(
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
     jar: SignedCookieJar,
     Form(payload): Form<LoginPayload>,
 ) -> impl IntoResponse {
     let expected_username = env::var("ADMIN_USERNAME").unwrap_or_else(|_| "admin".to_string());
     let expected_password = env::var("ADMIN_PASSWORD").unwrap_or_else(|_| "admin".to_string());
 
-    if payload.username == expected_username && payload.password == expected_password {
-        let cookie = Cookie::build(("auth_token", "true"))
+    // Both compared in full, so timing doesn't reveal which one was wrong
+    let username_ok = middleware::secret_matches(&payload.username, &expected_username);
+    let password_ok = middleware::secret_matches(&payload.password, &expected_password);
+    if username_ok & password_ok {
+        // Fresh session id on login; the CSRF token is derived from it
+        let cookie = Cookie::build(("auth_token", csrf::new_session_id()))
             .path("/")
             .http_only(true)
             .same_site(SameSite::Lax)
             .secure(false) // Set to true in prod with HTTPS
             .build();
+        let user = Cookie::build((USER_COOKIE, payload.username.clone()))
+            .path("/")
+            .http_only(true)
+            .same_site(SameSite::Lax)
+            .build();
+        let pre_session = Cookie::build((csrf::PRE_SESSION_COOKIE, "")).path("/").build();
+
+        state.audit(&payload.username, &ip.to_string(), "login", json!({}));
 
-        return (jar.add(cookie), Redirect::to("/admin")).into_response();
+        return (
+            jar.remove(pre_session).add(cookie).add(user),
+            Redirect::to("/admin"),
+        )
+            .into_response();
     }
 
+    state.audit(&payload.username, &ip.to_string(), "login_failed", json!({}));
+
+    let csrf_token = csrf::session_id(&jar)
+        .map(|id| csrf::token_for(&state.key, &id))
+        .unwrap_or_default();
+
     HtmlTemplate(LoginTemplate {
         error: Some("Invalid username or password".to_string()),
+        csrf_token,
     })
     .into_response()
 }
 
-pub async fn logout(jar: SignedCookieJar) -> impl IntoResponse {
+pub async fn logout(
+    State(state): State<AppState>,
+    audit: AuditContext,
+    jar: SignedCookieJar,
+) -> impl IntoResponse {
+    if jar.get("auth_token").is_some() {
+        audit.record(&state, "logout", json!({}));
+    }
+
     let cookie = Cookie::build(("auth_token", ""))
         .path("/")
         .max_age(time::Duration::seconds(0))
         .build();
+    let user = Cookie::build((USER_COOKIE, "")).path("/").build();
 
-    (jar.remove(cookie), Redirect::to("/login"))
+    (jar.remove(cookie).remove(user), Redirect::to("/login"))
 }
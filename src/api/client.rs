@@ -0,0 +1,126 @@
+use crate::api::client_ip::ClientIp;
+use crate::api::events::reject_over_limit;
+use crate::api::middleware::is_admin;
+use crate::api::websocket::admit_client;
+use crate::services::client_token::{ClientAuthMode, ClientTokenService};
+use crate::state::AppState;
+use axum::{
+    extract::{Query, State},
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Json, Response},
+};
+use axum_extra::extract::cookie::SignedCookieJar;
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::HashMap;
+
+#[derive(Deserialize)]
+pub struct TokenRequest {
+    pub device_id: Option<String>,
+}
+
+// Issues a short-lived token for /client/ws. Anyone may obtain a token for a
+// freshly generated device_id; tokens for a chosen device_id need admin auth,
+// so a client can't mint its way into someone else's identity.
+pub async fn issue_token(
+    State(state): State<AppState>,
+    jar: SignedCookieJar,
+    headers: HeaderMap,
+    Query(params): Query<TokenRequest>,
+) -> impl IntoResponse {
+    let device_id = match params.device_id.filter(|id| !id.is_empty()) {
+        Some(id) => {
+            if !is_admin(&jar, &headers) {
+                return (
+                    StatusCode::UNAUTHORIZED,
+                    Json(json!({"error": "Unauthorized"})),
+                )
+                    .into_response();
+            }
+            id
+        }
+        None => ClientTokenService::new_device_id(),
+    };
+
+    let (token, expires_at) = state.client_tokens.issue(&device_id);
+
+    Json(json!({
+        "token": token,
+        "device_id": device_id,
+        "expires_at": expires_at
+    }))
+    .into_response()
+}
+
+#[derive(Deserialize)]
+pub struct HeartbeatRequest {
+    pub device_id: Option<String>,
+    pub token: Option<String>,
+    pub room: Option<String>,
+}
+
+// Presence for clients that can hold neither a WebSocket nor an event stream.
+// Each beat keeps the device online for `ttl_secs`; the reply carries the
+// current counts and any admin messages queued since the previous beat.
+pub async fn heartbeat(
+    State(state): State<AppState>,
+    client_ip: ClientIp,
+    headers: HeaderMap,
+    Json(request): Json<HeartbeatRequest>,
+) -> Response {
+    let has_token = request.token.is_some() || headers.contains_key(header::AUTHORIZATION);
+    // Every beat must name the same device, so no anonymous ids here
+    match state.client_auth_mode {
+        ClientAuthMode::Off if !has_token && request.device_id.is_none() => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(json!({"error": "device_id is required"})),
+            )
+                .into_response();
+        }
+        ClientAuthMode::Optional if !has_token => {
+            return (
+                StatusCode::UNAUTHORIZED,
+                Json(json!({"error": "client token required"})),
+            )
+                .into_response();
+        }
+        _ => {}
+    }
+
+    let params: HashMap<String, String> = [
+        ("device_id", request.device_id),
+        ("token", request.token),
+        ("room", request.room),
+    ]
+    .into_iter()
+    .filter_map(|(name, value)| Some((name.to_string(), value?)))
+    .collect();
+    let client = match admit_client(&state, client_ip, headers, params) {
+        Ok(client) => client,
+        Err(response) => return response,
+    };
+
+    let commands = match state.heartbeat(
+        client.peer_ip,
+        &client.ip,
+        &client.device,
+        &client.device_id,
+        client.verified,
+        client.room.as_deref(),
+    ) {
+        Ok(commands) => commands,
+        Err(rejection) => return reject_over_limit(rejection),
+    };
+
+    let metrics = state.get_user_metrics();
+    Json(json!({
+        "device_id": client.device_id,
+        "activeUsers": metrics.active_users,
+        "totalUsers": metrics.total_users,
+        "activeBots": metrics.active_bots,
+        "ttl_secs": state.heartbeat_ttl.as_secs(),
+        "messages": commands.iter().map(|c| c.to_message()).collect::<Vec<_>>(),
+    }))
+    .into_response()
+}
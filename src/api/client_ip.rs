@@ -0,0 +1,188 @@
+use crate::state::AppState;
+use crate::utils::parse_networks;
+use axum::{
+    extract::{ConnectInfo, FromRequestParts},
+    http::{HeaderMap, StatusCode, request::Parts},
+};
+use ipnet::IpNet;
+use std::env;
+use std::net::{IpAddr, SocketAddr};
+
+/// Real client IP, resolved through `TRUSTED_PROXIES`.
+///
+/// Usable as an extractor from any handler, HTTP or WebSocket.
+#[derive(Debug, Clone)]
+pub struct ClientIp(pub IpAddr);
+
+impl FromRequestParts<AppState> for ClientIp {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        let ConnectInfo(addr) = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .cloned()
+            .ok_or(StatusCode::INTERNAL_SERVER_ERROR)?;
+
+        resolve_client_ip(addr.ip(), &parts.headers, &state.trusted_proxies)
+            .map(ClientIp)
+            .ok_or_else(|| {
+                tracing::warn!(peer = %addr.ip(), "Unreadable hop in forwarded header, rejecting request");
+                StatusCode::BAD_REQUEST
+            })
+    }
+}
+
+/// The one forwarding header the proxies in front of us set, chosen with
+/// `TRUSTED_PROXY_HEADER`. Any other forwarding header is client-supplied and ignored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProxyHeader {
+    // RFC 7239 `Forwarded`
+    Forwarded,
+    XForwardedFor,
+    XRealIp,
+}
+
+impl ProxyHeader {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "forwarded" => Some(ProxyHeader::Forwarded),
+            "x-forwarded-for" => Some(ProxyHeader::XForwardedFor),
+            "x-real-ip" => Some(ProxyHeader::XRealIp),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TrustedProxies {
+    pub networks: Vec<IpNet>,
+    // None ignores forwarding headers altogether
+    pub header: Option<ProxyHeader>,
+}
+
+impl TrustedProxies {
+    pub fn from_env() -> Self {
+        let networks = parse_networks(&env::var("TRUSTED_PROXIES").unwrap_or_default());
+        if networks.is_empty() {
+            tracing::warn!("TRUSTED_PROXIES not set. Ignoring forwarded headers, using socket peer IP.");
+            return Self::default();
+        }
+
+        let setting = env::var("TRUSTED_PROXY_HEADER").unwrap_or_else(|_| "x-forwarded-for".to_string());
+        let header = ProxyHeader::parse(&setting);
+        match header {
+            Some(header) => tracing::info!(proxies = ?networks, ?header, "Trusting forwarded header"),
+            None => tracing::warn!(
+                value = setting,
+                "Invalid TRUSTED_PROXY_HEADER, expected forwarded, x-forwarded-for or x-real-ip. Ignoring forwarded headers."
+            ),
+        }
+        Self { networks, header }
+    }
+
+    fn contains(&self, ip: &IpAddr) -> bool {
+        self.networks.iter().any(|net| net.contains(ip))
+    }
+}
+
+/// Picks the client IP for a request arriving from `peer`.
+///
+/// The configured forwarding header is only honoured when `peer` is a trusted
+/// proxy. Its hop chain is then walked right-to-left, skipping trusted proxies;
+/// the first untrusted hop is the client.
+///
+/// None if the walk reaches a hop that isn't an address (`unknown`, obfuscated
+/// or garbage) before an untrusted one. The client is then unknown: everything
+/// left of that hop is unverified, and falling back to the proxy's own address
+/// would pool every such request under one IP's limits and bans.
+pub fn resolve_client_ip(
+    peer: IpAddr,
+    headers: &HeaderMap,
+    trusted: &TrustedProxies,
+) -> Option<IpAddr> {
+    if !trusted.contains(&peer) {
+        return Some(peer);
+    }
+
+    let chain = match trusted.header {
+        Some(ProxyHeader::Forwarded) => forwarded_chain(headers),
+        Some(ProxyHeader::XForwardedFor) => x_forwarded_for_chain(headers),
+        Some(ProxyHeader::XRealIp) => header_str(headers, "x-real-ip").map(|v| vec![parse_node(v)]),
+        None => None,
+    };
+
+    let Some(chain) = chain else {
+        return Some(peer);
+    };
+
+    let mut client = peer;
+    for hop in chain.into_iter().rev() {
+        client = hop?;
+        if !trusted.contains(&client) {
+            break;
+        }
+    }
+    Some(client)
+}
+
+fn header_str<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|h| h.to_str().ok())
+}
+
+fn forwarded_chain(headers: &HeaderMap) -> Option<Vec<Option<IpAddr>>> {
+    // Multiple Forwarded headers are equivalent to one comma joined header
+    let values: Vec<&str> = headers
+        .get_all("forwarded")
+        .iter()
+        .filter_map(|h| h.to_str().ok())
+        .collect();
+    if values.is_empty() {
+        return None;
+    }
+
+    let chain = values
+        .iter()
+        .flat_map(|v| v.split(','))
+        .map(|element| {
+            element
+                .split(';')
+                .filter_map(|pair| pair.split_once('='))
+                .find(|(k, _)| k.trim().eq_ignore_ascii_case("for"))
+                .and_then(|(_, v)| parse_node(v))
+        })
+        .collect();
+    Some(chain)
+}
+
+fn x_forwarded_for_chain(headers: &HeaderMap) -> Option<Vec<Option<IpAddr>>> {
+    let values: Vec<&str> = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|h| h.to_str().ok())
+        .collect();
+    if values.is_empty() {
+        return None;
+    }
+
+    Some(
+        values
+            .iter()
+            .flat_map(|v| v.split(','))
+            .map(parse_node)
+            .collect(),
+    )
+}
+
+// Accepts `1.2.3.4`, `1.2.3.4:80`, `"[2001:db8::1]:4711"` and bare IPv6
+fn parse_node(raw: &str) -> Option<IpAddr> {
+    let node = raw.trim().trim_matches('"');
+
+    if let Ok(ip) = node.parse::<IpAddr>() {
+        return Some(ip);
+    }
+    if let Some(rest) = node.strip_prefix('[') {
+        return rest.split(']').next()?.parse().ok();
+    }
+    node.parse::<SocketAddr>().ok().map(|addr| addr.ip())
+}
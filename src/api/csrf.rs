@@ -0,0 +1,49 @@
+use axum_extra::extract::cookie::{Key, SignedCookieJar};
+use base64::prelude::*;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+pub const CSRF_HEADER: &str = "x-csrf-token";
+pub const CSRF_FIELD: &str = "csrf_token";
+
+// Signed cookie holding the admin session id once logged in
+pub const SESSION_COOKIE: &str = "auth_token";
+// Signed cookie holding a pre-login session id, so the login form itself is protected
+pub const PRE_SESSION_COOKIE: &str = "csrf_session";
+
+pub fn new_session_id() -> String {
+    let mut bytes = [0u8; 32];
+    rand::rng().fill_bytes(&mut bytes);
+    BASE64_URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// Returns the session id a CSRF token should be bound to: the admin session
+/// if there is one, otherwise the pre-login session.
+pub fn session_id(jar: &SignedCookieJar) -> Option<String> {
+    jar.get(SESSION_COOKIE)
+        .or_else(|| jar.get(PRE_SESSION_COOKIE))
+        .map(|c| c.value().to_string())
+        .filter(|v| !v.is_empty())
+}
+
+fn mac_for(key: &Key, session_id: &str) -> HmacSha256 {
+    let mut mac =
+        HmacSha256::new_from_slice(key.signing()).expect("HMAC accepts keys of any length");
+    mac.update(b"csrf:");
+    mac.update(session_id.as_bytes());
+    mac
+}
+
+pub fn token_for(key: &Key, session_id: &str) -> String {
+    BASE64_URL_SAFE_NO_PAD.encode(mac_for(key, session_id).finalize().into_bytes())
+}
+
+pub fn verify_token(key: &Key, session_id: &str, token: &str) -> bool {
+    match BASE64_URL_SAFE_NO_PAD.decode(token) {
+        Ok(raw) => mac_for(key, session_id).verify_slice(&raw).is_ok(),
+        Err(_) => false,
+    }
+}
@@ -0,0 +1,306 @@
+use crate::api::client_ip::ClientIp;
+use crate::api::websocket::{
+    AdminFeed, ClientAdmission, admit_admin, admit_client, announcement_message,
+};
+use crate::domain::{Announcement, UserMetrics};
+use crate::services::connection_limiter::{ConnectionPermit, LimitRejection};
+use crate::state::{AppState, ConnectionCommand, Presence};
+use axum::{
+    extract::{Query, State},
+    http::{HeaderMap, StatusCode},
+    response::{
+        IntoResponse, Response,
+        sse::{Event, KeepAlive, Sse},
+    },
+};
+use futures_util::stream::{self, Stream};
+use std::collections::{HashMap, VecDeque};
+use std::convert::Infallible;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::mpsc;
+
+// Server-Sent Events versions of /client/ws and /admin/ws, for networks whose
+// proxies drop WebSocket upgrades. Each event's data is exactly the text
+// message the socket would have sent, so `onmessage` handlers can be shared.
+//
+// Event ids are the server time in unix milliseconds. A reconnecting
+// EventSource sends the last one back as `Last-Event-ID`; the client feed then
+// replays only announcements started or ended since, instead of every active
+// one. Metrics are snapshots, so every (re)connect starts with the current one.
+
+pub async fn client_events_handler(
+    State(state): State<AppState>,
+    client_ip: ClientIp,
+    headers: HeaderMap,
+    Query(params): Query<HashMap<String, String>>,
+) -> Response {
+    let last_event_id = last_event_id(&headers);
+    let client = match admit_client(&state, client_ip, headers, params) {
+        Ok(client) => client,
+        Err(response) => return response,
+    };
+
+    let permit = match state.connection_limiter.try_acquire(client.peer_ip, &client.device_id) {
+        Ok(permit) => permit,
+        Err(rejection) => return reject_over_limit(rejection),
+    };
+
+    let session = ClientSession::open(state, client, permit, last_event_id);
+    event_stream(stream::unfold(session, |mut session| async move {
+        let data = session.next().await?;
+        Some((event(data), session))
+    }))
+}
+
+pub async fn admin_events_handler(
+    State(state): State<AppState>,
+    client_ip: ClientIp,
+    headers: HeaderMap,
+    Query(params): Query<HashMap<String, String>>,
+) -> Response {
+    // Same `stream=users` switch as the admin socket
+    let stream = params.get("stream").cloned().unwrap_or_default();
+    let peer_ip = client_ip.0;
+    let (ip, device, connection_id) = match admit_admin(&state, client_ip, headers, params) {
+        Ok(admin) => admin,
+        Err(response) => return response,
+    };
+
+    let permit = match state.connection_limiter.try_acquire(peer_ip, &connection_id) {
+        Ok(permit) => permit,
+        Err(rejection) => return reject_over_limit(rejection),
+    };
+
+    state.admin_join(&ip, &device, &connection_id);
+    tracing::info!(connection_id, "Admin event stream connected");
+    let (initial, feed) = AdminFeed::open(&state, &stream);
+    let session = AdminSession {
+        state,
+        connection_id,
+        feed,
+        initial: Some(initial),
+        _permit: permit,
+    };
+    event_stream(stream::unfold(session, |mut session| async move {
+        let data = match session.initial.take() {
+            Some(initial) => initial,
+            None => session.feed.next(&session.state).await?,
+        };
+        Some((event(data), session))
+    }))
+}
+
+fn event_stream<S>(stream: S) -> Response
+where
+    S: Stream<Item = Result<Event, Infallible>> + Send + 'static,
+{
+    (
+        // Keeps nginx and similar proxies from holding events back in a buffer
+        [("x-accel-buffering", "no")],
+        Sse::new(stream).keep_alive(KeepAlive::default()),
+    )
+        .into_response()
+}
+
+fn event(data: String) -> Result<Event, Infallible> {
+    let id = chrono::Utc::now().timestamp_millis().to_string();
+    Ok(Event::default().id(id).data(data))
+}
+
+fn last_event_id(headers: &HeaderMap) -> Option<i64> {
+    headers
+        .get("last-event-id")
+        .and_then(|h| h.to_str().ok())
+        .and_then(|v| v.trim().parse().ok())
+}
+
+// Refused before the stream starts, so a plain HTTP status stands in for the close code
+pub(crate) fn reject_over_limit(rejection: LimitRejection) -> Response {
+    tracing::info!(reason = rejection.as_str(), "Connection over limit");
+    let status = match rejection {
+        LimitRejection::ServerFull => StatusCode::SERVICE_UNAVAILABLE,
+        LimitRejection::RateLimited
+        | LimitRejection::TooManyForIp
+        | LimitRejection::TooManyForDevice => StatusCode::TOO_MANY_REQUESTS,
+    };
+    (status, rejection.as_str()).into_response()
+}
+
+/// One client's event stream. Joins presence when opened and leaves when
+/// dropped, which happens once the client goes away or the stream ends.
+struct ClientSession {
+    state: AppState,
+    ip: String,
+    device: String,
+    device_id: String,
+    room: Option<String>,
+    connection_id: u64,
+    users: broadcast::Receiver<UserMetrics>,
+    announcements: broadcast::Receiver<Announcement>,
+    commands: mpsc::UnboundedReceiver<ConnectionCommand>,
+    // Initial state, sent before any live update
+    backlog: VecDeque<String>,
+    kick_reason: Option<String>,
+    // Released on drop, freeing the connection slot
+    _permit: ConnectionPermit,
+}
+
+impl ClientSession {
+    fn open(
+        state: AppState,
+        client: ClientAdmission,
+        permit: ConnectionPermit,
+        last_event_id: Option<i64>,
+    ) -> Self {
+        let ClientAdmission {
+            ip,
+            device,
+            device_id,
+            verified,
+            room,
+            ..
+        } = client;
+        let Presence {
+            connection_id,
+            commands,
+        } = state.join(&ip, &device, &device_id, verified, room.as_deref());
+        tracing::info!(device_id, connection_id, resumed = last_event_id.is_some(), "Client event stream connected");
+
+        let users = state.users_tx.subscribe();
+        let announcements = state.announcements_tx.subscribe();
+        let backlog = client_backlog(&state, room.as_deref(), last_event_id);
+
+        Self {
+            state,
+            ip,
+            device,
+            device_id,
+            room,
+            connection_id,
+            users,
+            announcements,
+            commands,
+            backlog,
+            kick_reason: None,
+            _permit: permit,
+        }
+    }
+
+    /// Next message to send, or `None` to end the stream.
+    async fn next(&mut self) -> Option<String> {
+        if let Some(message) = self.backlog.pop_front() {
+            return Some(message);
+        }
+        if self.kick_reason.is_some() {
+            return None;
+        }
+
+        let Self {
+            state,
+            room,
+            users,
+            announcements,
+            commands,
+            kick_reason,
+            ..
+        } = self;
+        loop {
+            tokio::select! {
+                update = users.recv() => match update {
+                    Ok(metrics) => return Some(serde_json::to_string(&metrics).unwrap()),
+                    Err(RecvError::Lagged(skipped)) => {
+                        tracing::warn!(skipped, "Client event stream fell behind");
+                        state.metrics.lagged("client", skipped);
+                    }
+                    Err(RecvError::Closed) => return None,
+                },
+                update = announcements.recv() => match update {
+                    Ok(announcement) if announcement.targets(room.as_deref()) => {
+                        return Some(announcement_message(&announcement));
+                    }
+                    Ok(_) => {}
+                    Err(RecvError::Lagged(skipped)) => {
+                        tracing::warn!(skipped, "Client event stream fell behind");
+                        state.metrics.lagged("client", skipped);
+                    }
+                    Err(RecvError::Closed) => return None,
+                },
+                Some(command) = commands.recv() => {
+                    // EventSource reconnects on its own, so a kicked client is
+                    // told to close() rather than just seeing the stream end
+                    if let ConnectionCommand::Kick { reason } = &command {
+                        *kick_reason = Some(reason.clone());
+                    }
+                    return Some(command.to_message().to_string());
+                }
+            }
+        }
+    }
+}
+
+impl Drop for ClientSession {
+    fn drop(&mut self) {
+        let (ip, device, device_id) = (&self.ip, &self.device, &self.device_id);
+        tracing::info!(
+            device_id,
+            connection_id = self.connection_id,
+            kick_reason = self.kick_reason.as_deref(),
+            "Client event stream disconnected"
+        );
+        match &self.kick_reason {
+            Some(reason) => self.state.kicked(ip, device, device_id, self.connection_id, reason),
+            None => self.state.leave(ip, device, device_id, self.connection_id),
+        };
+    }
+}
+
+// Current metrics, then announcements oldest first: every active one for a new
+// stream, or those started or ended since `last_event_id` for a resumed one.
+fn client_backlog(
+    state: &AppState,
+    room: Option<&str>,
+    last_event_id: Option<i64>,
+) -> VecDeque<String> {
+    let mut backlog = VecDeque::from([serde_json::to_string(&state.get_user_metrics()).unwrap()]);
+
+    let mut announcements = match last_event_id {
+        Some(last_ms) => {
+            let now = chrono::Utc::now().timestamp();
+            // Announcement times are whole seconds; erring early may repeat one, never skip it
+            let since = last_ms.div_euclid(1000);
+            state
+                .announcements
+                .history()
+                .into_iter()
+                .filter(|a| match a.expires_at {
+                    Some(expires_at) if a.is_expired(now) => expires_at >= since,
+                    _ => a.created_at >= since,
+                })
+                .collect()
+        }
+        None => state.announcements.active(),
+    };
+    announcements.reverse();
+    backlog.extend(
+        announcements
+            .iter()
+            .filter(|a| a.targets(room))
+            .map(announcement_message),
+    );
+    backlog
+}
+
+struct AdminSession {
+    state: AppState,
+    connection_id: String,
+    feed: AdminFeed,
+    initial: Option<String>,
+    _permit: ConnectionPermit,
+}
+
+impl Drop for AdminSession {
+    fn drop(&mut self) {
+        self.state.admin_leave(&self.connection_id);
+        tracing::info!(connection_id = self.connection_id, "Admin event stream disconnected");
+    }
+}
@@ -1,12 +1,26 @@
-use crate::domain::{LogEntry, LogQuery, NavItem};
-use crate::state::AppState;
+use crate::api::audit::AuditContext;
+use crate::api::csrf;
+use crate::domain::{
+    AccessAction, AccessRule, Alert, AlertComparison, AlertMetric, AnnouncementSeverity,
+    AuditEntry, DeliveryStatus, HistoryRange, LogArchive, LogEntry, LogFilter, LogQuery,
+    LogStats, NavItem, NewAccessRule, NewAlertRule, NewAnnouncement, NewVpnPeer,
+    NewWebhookSubscription, SubjectEvent, SystemMetrics, WebhookEvent,
+};
+use crate::state::{AppState, ConnectionCommand};
 use askama::Template;
 use axum::{
-    extract::{Query, State},
-    http::StatusCode,
+    extract::{Form, Path, Query, State},
+    http::{HeaderMap, StatusCode},
     response::{Html, IntoResponse, Response},
 };
+use axum_extra::extract::cookie::SignedCookieJar;
+use serde::Deserialize;
+use serde_json::json;
 use std::env;
+use std::sync::Arc;
+
+// Older entries are only available through the export
+const AUDIT_TAB_LIMIT: usize = 200;
 
 // Wrapper struct for templates to implement IntoResponse
 pub struct HtmlTemplate<T>(pub T);
@@ -35,29 +49,34 @@ pub struct DashboardTemplate {
     pub total_events: usize,
     pub unique_device_ids: usize,
     pub active_users: u32,
-    pub uptime: String,
-    pub cpu: String,
-    pub ram: String,
+    pub active_bots: u32,
+    pub system: Arc<SystemMetrics>,
     pub nav_items: Vec<NavItem>,
     pub unique_ips: usize,
     pub top_ips: Vec<(String, u32)>,
+    pub top_countries: Vec<(String, u32)>,
     pub chart_labels: String,
     pub chart_data: String,
+    pub breakdown_charts: String,
+    pub system_history: String,
+    pub csrf_token: String,
 }
 
 #[derive(Template)]
 #[template(path = "components/overview.htmx", escape = "html")]
 pub struct OverviewTemplate {
     pub active_users: u32,
+    pub active_bots: u32,
     pub total_events: usize,
     pub unique_device_ids: usize,
     pub unique_ips: usize,
-    pub uptime: String,
-    pub cpu: String,
-    pub ram: String,
+    pub system: Arc<SystemMetrics>,
     pub top_ips: Vec<(String, u32)>,
+    pub top_countries: Vec<(String, u32)>,
     pub chart_labels: String,
     pub chart_data: String,
+    pub breakdown_charts: String,
+    pub system_history: String,
 }
 
 #[derive(Template)]
@@ -65,6 +84,10 @@ pub struct OverviewTemplate {
 pub struct LogsTemplate {
     pub q: String,
     pub exclude_ip: String,
+    pub browser: String,
+    pub os: String,
+    pub device_class: String,
+    pub country: String,
     pub logs: Vec<LogEntry>,
     pub page: usize,
     pub page_size: usize,
@@ -81,9 +104,7 @@ pub struct StatsTemplate {
     pub unique_ips: usize,
     pub unique_device_ids: usize,
     pub active_users: u32,
-    pub uptime: String,
-    pub cpu: String,
-    pub ram: String,
+    pub system: Arc<SystemMetrics>,
 }
 
 #[derive(Template)]
@@ -96,6 +117,10 @@ pub struct TableTemplate {
     pub total_pages: usize,
     pub q: String,
     pub exclude_ip: String,
+    pub browser: String,
+    pub os: String,
+    pub device_class: String,
+    pub country: String,
     pub sort_by: String,
     pub order: String,
 }
@@ -103,6 +128,7 @@ pub struct TableTemplate {
 #[derive(Clone, Debug)]
 pub struct ActiveUserDisplay {
     pub device_id: String,
+    pub room: String,
     pub ip: String,
     pub device: String,
     pub duration: String,
@@ -112,6 +138,225 @@ pub struct ActiveUserDisplay {
 #[template(path = "components/active_users.htmx", escape = "html")]
 pub struct ActiveUsersTemplate {
     pub users: Vec<ActiveUserDisplay>,
+    pub bots: Vec<ActiveUserDisplay>,
+    pub message: Option<String>,
+    pub error: Option<String>,
+}
+
+// Text the admin typed into an hx-prompt dialog, posted as a form field
+#[derive(Deserialize)]
+pub struct PromptForm {
+    #[serde(default)]
+    pub prompt: String,
+}
+
+impl PromptForm {
+    fn value(&self) -> Option<String> {
+        Some(self.prompt.trim().to_string()).filter(|s| !s.is_empty())
+    }
+}
+
+#[derive(Deserialize)]
+pub struct BanParams {
+    // "ip" or "device"
+    pub target: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct AccessRuleDisplay {
+    pub id: u64,
+    pub network: String,
+    pub action: String,
+    pub reason: String,
+    pub created_at: String,
+    pub expires: String,
+}
+
+#[derive(Template)]
+#[template(path = "components/access_rules.htmx", escape = "html")]
+pub struct AccessRulesTemplate {
+    pub rules: Vec<AccessRuleDisplay>,
+    pub error: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct AccessRuleForm {
+    pub network: String,
+    pub action: AccessAction,
+    pub reason: Option<String>,
+    // Empty string when left blank in the form
+    pub expires_in_minutes: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct AnnouncementDisplay {
+    pub id: u64,
+    pub message: String,
+    pub severity: String,
+    pub room: String,
+    pub created_at: String,
+    pub expires: String,
+    pub active: bool,
+}
+
+#[derive(Template)]
+#[template(path = "components/announcements.htmx", escape = "html")]
+pub struct AnnouncementsTemplate {
+    pub announcements: Vec<AnnouncementDisplay>,
+    pub error: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct AnnouncementForm {
+    pub message: String,
+    pub severity: AnnouncementSeverity,
+    pub room: Option<String>,
+    // Empty string when left blank in the form
+    pub expires_in_minutes: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct AlertRuleDisplay {
+    pub id: u64,
+    pub name: String,
+    pub condition: String,
+    pub duration: String,
+    pub webhook: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct AlertDisplay {
+    pub name: String,
+    pub metric: String,
+    pub subject: String,
+    pub value: String,
+    pub threshold: String,
+    pub started_at: String,
+    pub resolved_at: String,
+}
+
+#[derive(Template)]
+#[template(path = "components/alerts.htmx", escape = "html")]
+pub struct AlertsTemplate {
+    pub rules: Vec<AlertRuleDisplay>,
+    pub firing: Vec<AlertDisplay>,
+    pub resolved: Vec<AlertDisplay>,
+    pub webhooks_configured: bool,
+    pub error: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct AlertRuleForm {
+    pub name: String,
+    pub metric: AlertMetric,
+    pub comparison: AlertComparison,
+    pub threshold: String,
+    // Empty string when left blank in the form
+    pub for_minutes: Option<String>,
+    pub webhook_url: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct WebhookDisplay {
+    pub id: u64,
+    pub url: String,
+    pub events: String,
+    pub created_at: String,
+}
+
+#[derive(Clone, Debug)]
+pub struct DeliveryDisplay {
+    pub id: u64,
+    pub event: String,
+    pub url: String,
+    pub status: String,
+    pub attempts: u32,
+    pub created_at: String,
+    // Response code or error of the last attempt
+    pub result: String,
+    pub next_attempt: String,
+}
+
+#[derive(Template)]
+#[template(path = "components/webhooks.htmx", escape = "html")]
+pub struct WebhooksTemplate {
+    pub subscriptions: Vec<WebhookDisplay>,
+    pub deliveries: Vec<DeliveryDisplay>,
+    pub message: Option<String>,
+    pub error: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct WebhookForm {
+    pub url: String,
+    // Checkboxes: present only when ticked
+    pub connected: Option<String>,
+    pub disconnected: Option<String>,
+    pub secret: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct VpnPeerDisplay {
+    pub id: u64,
+    pub name: String,
+    pub device_id: String,
+    pub owner: String,
+    pub public_key: String,
+    pub overlay_ip: String,
+    pub allowed_ips: String,
+    pub tags: Vec<String>,
+    pub online: bool,
+    pub last_handshake: String,
+    pub last_seen: String,
+}
+
+#[derive(Template)]
+#[template(path = "components/vpn_peers.htmx", escape = "html")]
+pub struct VpnPeersTemplate {
+    pub peers: Vec<VpnPeerDisplay>,
+    pub online: usize,
+    pub message: Option<String>,
+    pub error: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct VpnPeerForm {
+    pub device_id: String,
+    pub name: Option<String>,
+    pub owner: Option<String>,
+    pub public_key: String,
+    // Blank for the next free address
+    pub overlay_ip: Option<String>,
+    // Comma separated
+    pub allowed_ips: Option<String>,
+    pub tags: Option<String>,
+}
+
+#[derive(Template)]
+#[template(path = "components/audit.htmx", escape = "html")]
+pub struct AuditTemplate {
+    pub entries: Vec<AuditEntry>,
+}
+
+#[derive(Template)]
+#[template(path = "components/archives.htmx", escape = "html")]
+pub struct ArchivesTemplate {
+    pub archives: Vec<LogArchive>,
+    pub message: Option<String>,
+    pub error: Option<String>,
+}
+
+#[derive(Template)]
+#[template(path = "components/privacy.htmx", escape = "html")]
+pub struct PrivacyTemplate {
+    pub mode: String,
+    pub device_id: String,
+    pub ip: String,
+    pub export_query: String,
+    // None until a search was run
+    pub events: Option<Vec<SubjectEvent>>,
+    pub message: Option<String>,
+    pub error: Option<String>,
 }
 
 #[derive(Template)]
@@ -120,39 +365,45 @@ pub struct LoggedOutTemplate;
 
 // Handlers
 
-pub async fn dashboard_handler(State(state): State<AppState>) -> impl IntoResponse {
+pub async fn dashboard_handler(
+    State(state): State<AppState>,
+    jar: SignedCookieJar,
+) -> impl IntoResponse {
     // Only need stats for initial overview load
     let params = LogQuery {
-        page: 1,
         page_size: 1,
-        q: None,
-        exclude_ip: None,
-        sort_by: "timestamp".to_string(),
-        order: "desc".to_string(),
+        ..LogQuery::default()
     };
 
     let (_, meta, stats) = state.log_repository.find_all(&params);
-    let (uptime, cpu, ram) = get_system_metrics(&state);
+    let system = state.system_snapshot();
 
     let username = env::var("ADMIN_USERNAME").unwrap_or_else(|_| "admin".to_string());
+    let csrf_token = csrf::session_id(&jar)
+        .map(|id| csrf::token_for(&state.key, &id))
+        .unwrap_or_default();
 
     // Prepare chart data
     let labels: Vec<String> = stats.requests_over_time.iter().map(|(t, _)| t.clone()).collect();
     let data: Vec<u32> = stats.requests_over_time.iter().map(|(_, c)| *c).collect();
+    let breakdown_charts = breakdown_charts(&stats);
 
     HtmlTemplate(DashboardTemplate {
         username,
         total_events: meta.total,
         unique_device_ids: stats.unique_device_ids,
         active_users: stats.active_users,
-        uptime,
-        cpu,
-        ram,
+        active_bots: state.get_bot_count(),
+        system,
         nav_items: get_nav_menu("/admin"),
         unique_ips: stats.unique_ips,
         top_ips: stats.top_ips,
+        top_countries: stats.top_countries,
         chart_labels: serde_json::to_string(&labels).unwrap_or_default(),
         chart_data: serde_json::to_string(&data).unwrap_or_default(),
+        breakdown_charts,
+        system_history: system_history(&state),
+        csrf_token,
     })
 }
 
@@ -169,16 +420,12 @@ fn get_nav_menu(current_path: &str) -> Vec<NavItem> {
 
 pub async fn overview_tab_handler(State(state): State<AppState>) -> impl IntoResponse {
     let params = LogQuery {
-        page: 1,
         page_size: 1,
-        q: None,
-        exclude_ip: None,
-        sort_by: "timestamp".to_string(),
-        order: "desc".to_string(),
+        ..LogQuery::default()
     };
 
     let (_, meta, stats) = state.log_repository.find_all(&params);
-    let (uptime, cpu, ram) = get_system_metrics(&state);
+    let system = state.system_snapshot();
 
     // Prepare chart data
     let labels: Vec<String> = stats
@@ -187,21 +434,45 @@ pub async fn overview_tab_handler(State(state): State<AppState>) -> impl IntoRes
         .map(|(t, _)| t.clone())
         .collect();
     let data: Vec<u32> = stats.requests_over_time.iter().map(|(_, c)| *c).collect();
+    let breakdown_charts = breakdown_charts(&stats);
 
     HtmlTemplate(OverviewTemplate {
         active_users: stats.active_users,
+        active_bots: state.get_bot_count(),
         total_events: meta.total,
         unique_device_ids: stats.unique_device_ids,
         unique_ips: stats.unique_ips,
-        uptime,
-        cpu,
-        ram,
+        system,
         top_ips: stats.top_ips,
+        top_countries: stats.top_countries,
         chart_labels: serde_json::to_string(&labels).unwrap_or_default(),
         chart_data: serde_json::to_string(&data).unwrap_or_default(),
+        breakdown_charts,
+        system_history: system_history(&state),
     })
 }
 
+// The last hour of system metrics, for the history charts on the overview tab
+fn system_history(state: &AppState) -> String {
+    serde_json::to_string(&state.get_metrics_history(HistoryRange::Hour)).unwrap_or_default()
+}
+
+// Labels and counts for the browser / OS / device class charts on the overview tab
+fn breakdown_charts(stats: &LogStats) -> String {
+    let chart = |pairs: &[(String, u32)]| {
+        json!({
+            "labels": pairs.iter().map(|(l, _)| l).collect::<Vec<_>>(),
+            "data": pairs.iter().map(|(_, c)| c).collect::<Vec<_>>(),
+        })
+    };
+    json!({
+        "browsers": chart(&stats.browsers),
+        "os": chart(&stats.operating_systems),
+        "device_classes": chart(&stats.device_classes),
+    })
+    .to_string()
+}
+
 pub async fn logs_tab_handler(
     State(state): State<AppState>,
     Query(params): Query<LogQuery>,
@@ -211,6 +482,10 @@ pub async fn logs_tab_handler(
     HtmlTemplate(LogsTemplate {
         q: params.q.unwrap_or_default(),
         exclude_ip: params.exclude_ip.unwrap_or_default(),
+        browser: params.browser.unwrap_or_default(),
+        os: params.os.unwrap_or_default(),
+        device_class: params.device_class.unwrap_or_default(),
+        country: params.country.unwrap_or_default(),
         logs,
         page: meta.page,
         page_size: meta.page_size,
@@ -225,6 +500,27 @@ pub async fn logs_handler(
     State(state): State<AppState>,
     Query(params): Query<LogQuery>,
 ) -> impl IntoResponse {
+    render_logs_table(&state, params)
+}
+
+// "Clear Logs" in the logs tab: archive, then show the now empty table
+pub async fn logs_archive_handler(
+    State(state): State<AppState>,
+    audit: AuditContext,
+    Query(params): Query<LogQuery>,
+) -> impl IntoResponse {
+    match state.log_repository.archive() {
+        Ok(archive) => audit.record(
+            &state,
+            "logs.archive",
+            json!({"archive": archive.map(|a| a.name)}),
+        ),
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+    render_logs_table(&state, params).into_response()
+}
+
+fn render_logs_table(state: &AppState, params: LogQuery) -> HtmlTemplate<TableTemplate> {
     let (logs, meta, _) = state.log_repository.find_all(&params);
 
     HtmlTemplate(TableTemplate {
@@ -235,48 +531,736 @@ pub async fn logs_handler(
         total_pages: meta.total_pages,
         q: params.q.unwrap_or_default(),
         exclude_ip: params.exclude_ip.unwrap_or_default(),
+        browser: params.browser.unwrap_or_default(),
+        os: params.os.unwrap_or_default(),
+        device_class: params.device_class.unwrap_or_default(),
+        country: params.country.unwrap_or_default(),
         sort_by: params.sort_by,
         order: params.order,
     })
 }
 
 pub async fn active_users_tab_handler(State(state): State<AppState>) -> impl IntoResponse {
+    render_active_users(&state, None, None)
+}
+
+pub async fn kick_user_handler(
+    State(state): State<AppState>,
+    audit: AuditContext,
+    Path(device_id): Path<String>,
+    Form(form): Form<PromptForm>,
+) -> impl IntoResponse {
+    let reason = form.value().unwrap_or_else(|| "Disconnected by admin".to_string());
+    let details = json!({"device_id": device_id, "reason": reason});
+    if state.send_command(&device_id, ConnectionCommand::Kick { reason }) {
+        audit.record(&state, "client.kick", details);
+        render_active_users(&state, Some(format!("Disconnected {}", device_id)), None)
+    } else {
+        render_active_users(&state, None, Some(format!("{} is not connected", device_id)))
+    }
+}
+
+pub async fn message_user_handler(
+    State(state): State<AppState>,
+    audit: AuditContext,
+    Path(device_id): Path<String>,
+    Form(form): Form<PromptForm>,
+) -> impl IntoResponse {
+    let Some(message) = form.value() else {
+        return render_active_users(&state, None, Some("Message is empty".to_string()));
+    };
+    let details = json!({"device_id": device_id, "message": message});
+    if state.send_command(&device_id, ConnectionCommand::Notice { message }) {
+        audit.record(&state, "client.message", details);
+        render_active_users(&state, Some(format!("Message sent to {}", device_id)), None)
+    } else {
+        render_active_users(&state, None, Some(format!("{} is not connected", device_id)))
+    }
+}
+
+pub async fn ban_user_handler(
+    State(state): State<AppState>,
+    audit: AuditContext,
+    Path(device_id): Path<String>,
+    Query(params): Query<BanParams>,
+    Form(form): Form<PromptForm>,
+) -> impl IntoResponse {
+    let Some(conn) = state.active_connections.lock().unwrap().get(&device_id).cloned() else {
+        return render_active_users(&state, None, Some(format!("{} is not connected", device_id)));
+    };
+
+    let reason = form.value();
+    let (rule, target) = match params.target.as_str() {
+        "ip" => (
+            NewAccessRule {
+                network: conn.ip.clone(),
+                device_id: None,
+                action: AccessAction::Deny,
+                reason: reason.clone(),
+                expires_in_minutes: None,
+            },
+            conn.ip.clone(),
+        ),
+        "device" => (
+            NewAccessRule {
+                network: String::new(),
+                device_id: Some(device_id.clone()),
+                action: AccessAction::Deny,
+                reason: reason.clone(),
+                expires_in_minutes: None,
+            },
+            device_id.clone(),
+        ),
+        other => {
+            return render_active_users(&state, None, Some(format!("Unknown ban target: {}", other)));
+        }
+    };
+
+    match state.access_list.add(rule) {
+        Ok(rule) => audit.record(
+            &state,
+            "client.ban",
+            json!({"device_id": device_id, "rule": rule}),
+        ),
+        Err(e) => return render_active_users(&state, None, Some(e.to_string())),
+    }
+
+    state.send_command(
+        &device_id,
+        ConnectionCommand::Kick {
+            reason: reason.unwrap_or_else(|| "Banned by admin".to_string()),
+        },
+    );
+    render_active_users(&state, Some(format!("Banned {}", target)), None)
+}
+
+// Text the admin typed into an hx-prompt dialog on a DELETE, which htmx
+// sends only as a header
+fn prompt_value(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get("hx-prompt")
+        .and_then(|h| h.to_str().ok())
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+}
+
+fn render_active_users(
+    state: &AppState,
+    message: Option<String>,
+    error: Option<String>,
+) -> HtmlTemplate<ActiveUsersTemplate> {
     let connections = state.get_active_users();
-    let users: Vec<ActiveUserDisplay> = connections
+    let (bots, users): (Vec<_>, Vec<_>) = connections
         .iter()
         .map(|c| {
             let duration = c.connected_at.elapsed();
             let secs = duration.as_secs();
             let duration_str = crate::utils::format_duration(secs);
-            ActiveUserDisplay {
+            let display = ActiveUserDisplay {
                 device_id: c.device_id.clone(),
+                room: c.room.clone().unwrap_or_default(),
                 ip: c.ip.clone(),
                 device: c.device.clone(),
                 duration: duration_str,
+            };
+            (c.is_bot, display)
+        })
+        .partition(|(is_bot, _)| *is_bot);
+
+    HtmlTemplate(ActiveUsersTemplate {
+        users: users.into_iter().map(|(_, u)| u).collect(),
+        bots: bots.into_iter().map(|(_, u)| u).collect(),
+        message,
+        error,
+    })
+}
+
+pub async fn access_rules_tab_handler(State(state): State<AppState>) -> impl IntoResponse {
+    render_access_rules(&state, None)
+}
+
+pub async fn access_rules_create_handler(
+    State(state): State<AppState>,
+    audit: AuditContext,
+    Form(form): Form<AccessRuleForm>,
+) -> impl IntoResponse {
+    let rule = NewAccessRule {
+        network: form.network,
+        device_id: None,
+        action: form.action,
+        reason: form.reason,
+        expires_in_minutes: form
+            .expires_in_minutes
+            .and_then(|m| m.trim().parse().ok()),
+    };
+    let error = match state.access_list.add(rule) {
+        Ok(rule) => {
+            audit.record(&state, "access_rule.add", json!(rule));
+            None
+        }
+        Err(e) => Some(e.to_string()),
+    };
+    render_access_rules(&state, error)
+}
+
+pub async fn access_rules_delete_handler(
+    State(state): State<AppState>,
+    audit: AuditContext,
+    Path(id): Path<u64>,
+) -> impl IntoResponse {
+    let error = match state.access_list.remove(id) {
+        Ok(removed) => {
+            if removed {
+                audit.record(&state, "access_rule.remove", json!({"id": id}));
+            }
+            None
+        }
+        Err(e) => Some(e.to_string()),
+    };
+    render_access_rules(&state, error)
+}
+
+fn render_access_rules(state: &AppState, error: Option<String>) -> HtmlTemplate<AccessRulesTemplate> {
+    let rules = state
+        .access_list
+        .list()
+        .into_iter()
+        .map(|r: AccessRule| AccessRuleDisplay {
+            id: r.id,
+            network: match r.device_id {
+                Some(device_id) => format!("device: {}", device_id),
+                None => r.network,
+            },
+            action: match r.action {
+                AccessAction::Allow => "allow".to_string(),
+                AccessAction::Deny => "deny".to_string(),
+            },
+            reason: r.reason.unwrap_or_default(),
+            created_at: format_timestamp(r.created_at),
+            expires: r
+                .expires_at
+                .map(format_timestamp)
+                .unwrap_or_else(|| "Never".to_string()),
+        })
+        .collect();
+
+    HtmlTemplate(AccessRulesTemplate { rules, error })
+}
+
+pub async fn alerts_tab_handler(State(state): State<AppState>) -> impl IntoResponse {
+    render_alerts(&state, None)
+}
+
+pub async fn alert_rules_create_handler(
+    State(state): State<AppState>,
+    audit: AuditContext,
+    Form(form): Form<AlertRuleForm>,
+) -> impl IntoResponse {
+    let Ok(threshold) = form.threshold.trim().parse::<f64>() else {
+        return render_alerts(&state, Some(format!("Invalid threshold: {}", form.threshold)));
+    };
+    let rule = NewAlertRule {
+        name: form.name,
+        metric: form.metric,
+        comparison: form.comparison,
+        threshold,
+        for_secs: form
+            .for_minutes
+            .and_then(|m| m.trim().parse::<u64>().ok())
+            .map(|m| m * 60)
+            .unwrap_or(0),
+        webhook_url: form.webhook_url,
+    };
+    let error = match state.alert_rules.add(rule) {
+        Ok(rule) => {
+            audit.record(&state, "alert_rule.add", json!(rule));
+            None
+        }
+        Err(e) => Some(e.to_string()),
+    };
+    render_alerts(&state, error)
+}
+
+pub async fn alert_rules_delete_handler(
+    State(state): State<AppState>,
+    audit: AuditContext,
+    Path(id): Path<u64>,
+) -> impl IntoResponse {
+    let error = match state.alert_rules.remove(id) {
+        Ok(removed) => {
+            if removed {
+                audit.record(&state, "alert_rule.remove", json!({"id": id}));
+            }
+            None
+        }
+        Err(e) => Some(e.to_string()),
+    };
+    render_alerts(&state, error)
+}
+
+fn render_alerts(state: &AppState, error: Option<String>) -> HtmlTemplate<AlertsTemplate> {
+    let rules = state
+        .alert_rules
+        .list()
+        .into_iter()
+        .map(|r| AlertRuleDisplay {
+            id: r.id,
+            condition: format!("{} {} {}", r.metric.as_str(), r.comparison.as_str(), r.threshold),
+            duration: match r.for_secs {
+                0 => "Immediately".to_string(),
+                secs if secs % 60 == 0 => format!("{} min", secs / 60),
+                secs => format!("{} s", secs),
+            },
+            webhook: r.webhook_url.unwrap_or_default(),
+            name: r.name,
+        })
+        .collect();
+
+    let display = |a: Alert| AlertDisplay {
+        metric: a.metric.as_str().to_string(),
+        subject: a.subject.unwrap_or_default(),
+        value: format!("{:.1}", a.value),
+        threshold: a.threshold.to_string(),
+        started_at: format_timestamp(a.started_at),
+        resolved_at: a.resolved_at.map(format_timestamp).unwrap_or_default(),
+        name: a.rule_name,
+    };
+    let alerts = state.get_alerts();
+
+    HtmlTemplate(AlertsTemplate {
+        rules,
+        firing: alerts.firing.into_iter().map(display).collect(),
+        resolved: alerts.resolved.into_iter().map(display).collect(),
+        webhooks_configured: state.alerts.has_global_webhooks(),
+        error,
+    })
+}
+
+pub async fn webhooks_tab_handler(State(state): State<AppState>) -> impl IntoResponse {
+    render_webhooks(&state, None, None)
+}
+
+pub async fn webhooks_create_handler(
+    State(state): State<AppState>,
+    audit: AuditContext,
+    Form(form): Form<WebhookForm>,
+) -> impl IntoResponse {
+    let mut events = Vec::new();
+    if form.connected.is_some() {
+        events.push(WebhookEvent::Connected);
+    }
+    if form.disconnected.is_some() {
+        events.push(WebhookEvent::Disconnected);
+    }
+    let subscription = NewWebhookSubscription {
+        url: form.url,
+        events,
+        secret: form.secret,
+    };
+    match state.webhook_subscriptions.add(subscription) {
+        Ok(subscription) => {
+            audit.record(
+                &state,
+                "webhook.add",
+                json!(subscription.clone().without_secret()),
+            );
+            let message = format!(
+                "Signing secret for {} (shown only once): {}",
+                subscription.url, subscription.secret
+            );
+            render_webhooks(&state, Some(message), None)
+        }
+        Err(e) => render_webhooks(&state, None, Some(e.to_string())),
+    }
+}
+
+pub async fn webhooks_delete_handler(
+    State(state): State<AppState>,
+    audit: AuditContext,
+    Path(id): Path<u64>,
+) -> impl IntoResponse {
+    let error = match state.webhook_subscriptions.remove(id) {
+        Ok(removed) => {
+            if removed {
+                audit.record(&state, "webhook.remove", json!({"id": id}));
+            }
+            None
+        }
+        Err(e) => Some(e.to_string()),
+    };
+    render_webhooks(&state, None, error)
+}
+
+fn render_webhooks(
+    state: &AppState,
+    message: Option<String>,
+    error: Option<String>,
+) -> HtmlTemplate<WebhooksTemplate> {
+    let subscriptions = state
+        .webhook_subscriptions
+        .list()
+        .into_iter()
+        .map(|s| WebhookDisplay {
+            id: s.id,
+            events: s
+                .events
+                .iter()
+                .map(|e| e.as_str())
+                .collect::<Vec<_>>()
+                .join(", "),
+            created_at: format_timestamp(s.created_at),
+            url: s.url,
+        })
+        .collect();
+
+    let deliveries = state
+        .webhook_deliveries
+        .recent(50)
+        .into_iter()
+        .map(|d| DeliveryDisplay {
+            id: d.id,
+            event: d.event.as_str().to_string(),
+            status: d.status.as_str().to_string(),
+            attempts: d.attempts,
+            created_at: format_timestamp(d.created_at),
+            result: match (&d.last_error, d.response_status) {
+                (Some(error), _) => error.clone(),
+                (None, Some(status)) => format!("HTTP {}", status),
+                (None, None) => String::new(),
+            },
+            next_attempt: if d.status == DeliveryStatus::Pending {
+                format_timestamp(d.next_attempt_at)
+            } else {
+                String::new()
+            },
+            url: d.url,
+        })
+        .collect();
+
+    HtmlTemplate(WebhooksTemplate {
+        subscriptions,
+        deliveries,
+        message,
+        error,
+    })
+}
+
+pub async fn vpn_peers_tab_handler(State(state): State<AppState>) -> impl IntoResponse {
+    render_vpn_peers(&state, None, None)
+}
+
+pub async fn vpn_peers_create_handler(
+    State(state): State<AppState>,
+    audit: AuditContext,
+    Form(form): Form<VpnPeerForm>,
+) -> impl IntoResponse {
+    let overlay_ip = match form.overlay_ip.as_deref().unwrap_or("").trim() {
+        "" => None,
+        ip => match ip.parse() {
+            Ok(ip) => Some(ip),
+            Err(_) => {
+                return render_vpn_peers(&state, None, Some(format!("Invalid overlay IP: {}", ip)));
+            }
+        },
+    };
+    let split = |list: Option<String>| {
+        list.unwrap_or_default().split(',').map(str::to_string).collect()
+    };
+    let peer = NewVpnPeer {
+        device_id: form.device_id,
+        name: form.name.unwrap_or_default(),
+        owner: form.owner.unwrap_or_default(),
+        public_key: form.public_key,
+        overlay_ip,
+        allowed_ips: split(form.allowed_ips),
+        tags: split(form.tags),
+    };
+    match state.vpn_peers.add(peer) {
+        Ok(peer) => {
+            audit.record(&state, "vpn_peer.add", json!(peer));
+            let message = format!("Added {} at {}", peer.name, peer.overlay_ip);
+            render_vpn_peers(&state, Some(message), None)
+        }
+        Err(e) => render_vpn_peers(&state, None, Some(e.to_string())),
+    }
+}
+
+pub async fn vpn_peers_delete_handler(
+    State(state): State<AppState>,
+    audit: AuditContext,
+    Path(id): Path<u64>,
+) -> impl IntoResponse {
+    let error = match state.vpn_peers.remove(id) {
+        Ok(removed) => {
+            if removed {
+                audit.record(&state, "vpn_peer.remove", json!({"id": id}));
+            }
+            None
+        }
+        Err(e) => Some(e.to_string()),
+    };
+    render_vpn_peers(&state, None, error)
+}
+
+fn render_vpn_peers(
+    state: &AppState,
+    message: Option<String>,
+    error: Option<String>,
+) -> HtmlTemplate<VpnPeersTemplate> {
+    let format_time = |ts: Option<i64>| ts.map_or_else(|| "Never".to_string(), format_timestamp);
+    let peers: Vec<VpnPeerDisplay> = state
+        .get_vpn_peers()
+        .into_iter()
+        .map(|status| {
+            let peer = status.peer;
+            VpnPeerDisplay {
+                id: peer.id,
+                online: status.online,
+                last_handshake: format_time(peer.last_handshake),
+                last_seen: format_time(peer.last_seen),
+                overlay_ip: peer.overlay_ip.to_string(),
+                allowed_ips: peer.allowed_ips.join(", "),
+                name: peer.name,
+                device_id: peer.device_id,
+                owner: peer.owner,
+                public_key: peer.public_key,
+                tags: peer.tags,
             }
         })
         .collect();
 
-    HtmlTemplate(ActiveUsersTemplate { users })
-}
-// Helper for System Metrics
-fn get_system_metrics(state: &AppState) -> (String, String, String) {
-    let mut sys = state.system.lock().unwrap();
-    sys.refresh_cpu_all();
-    sys.refresh_memory();
-
-    let uptime_sec = state.start_time.elapsed().as_secs();
-    let hrs = uptime_sec / 3600;
-    let mins = (uptime_sec % 3600) / 60;
-    let secs = uptime_sec % 60;
-    let uptime = format!("{}h {}m {}s", hrs, mins, secs);
-
-    let cpu = format!("{:.1}", sys.global_cpu_usage());
-    let ram = format!(
-        "{}MB / {}MB",
-        sys.used_memory() / 1024 / 1024,
-        sys.total_memory() / 1024 / 1024
+    HtmlTemplate(VpnPeersTemplate {
+        online: peers.iter().filter(|p| p.online).count(),
+        peers,
+        message,
+        error,
+    })
+}
+
+pub async fn announcements_tab_handler(State(state): State<AppState>) -> impl IntoResponse {
+    render_announcements(&state, None)
+}
+
+pub async fn announcements_create_handler(
+    State(state): State<AppState>,
+    audit: AuditContext,
+    Form(form): Form<AnnouncementForm>,
+) -> impl IntoResponse {
+    let announcement = NewAnnouncement {
+        message: form.message,
+        severity: form.severity,
+        room: form.room,
+        expires_in_minutes: form
+            .expires_in_minutes
+            .and_then(|m| m.trim().parse().ok()),
+    };
+    let error = match state.announce(announcement) {
+        Ok(announcement) => {
+            audit.record(&state, "announcement.create", json!(announcement));
+            None
+        }
+        Err(e) => Some(e.to_string()),
+    };
+    render_announcements(&state, error)
+}
+
+pub async fn announcements_end_handler(
+    State(state): State<AppState>,
+    audit: AuditContext,
+    Path(id): Path<u64>,
+) -> impl IntoResponse {
+    let error = match state.end_announcement(id) {
+        Ok(ended) => {
+            if ended.is_some() {
+                audit.record(&state, "announcement.end", json!({"id": id}));
+            }
+            None
+        }
+        Err(e) => Some(e.to_string()),
+    };
+    render_announcements(&state, error)
+}
+
+fn render_announcements(
+    state: &AppState,
+    error: Option<String>,
+) -> HtmlTemplate<AnnouncementsTemplate> {
+    let now = chrono::Utc::now().timestamp();
+    let announcements = state
+        .announcements
+        .history()
+        .into_iter()
+        .map(|a| AnnouncementDisplay {
+            id: a.id,
+            active: !a.is_expired(now),
+            severity: a.severity.as_str().to_string(),
+            room: a.room.unwrap_or_else(|| "All".to_string()),
+            created_at: format_timestamp(a.created_at),
+            expires: a
+                .expires_at
+                .map(format_timestamp)
+                .unwrap_or_else(|| "Never".to_string()),
+            message: a.message,
+        })
+        .collect();
+
+    HtmlTemplate(AnnouncementsTemplate {
+        announcements,
+        error,
+    })
+}
+
+pub async fn audit_tab_handler(State(state): State<AppState>) -> impl IntoResponse {
+    HtmlTemplate(AuditTemplate {
+        entries: state.audit_log.recent(AUDIT_TAB_LIMIT),
+    })
+}
+
+pub async fn archives_tab_handler(State(state): State<AppState>) -> impl IntoResponse {
+    render_archives(&state, None, None)
+}
+
+pub async fn archives_create_handler(
+    State(state): State<AppState>,
+    audit: AuditContext,
+) -> impl IntoResponse {
+    match state.log_repository.archive() {
+        Ok(Some(archive)) => {
+            audit.record(&state, "logs.archive", json!({"archive": archive.name}));
+            render_archives(&state, Some(format!("Archived as {}", archive.name)), None)
+        }
+        Ok(None) => render_archives(&state, None, Some("The live log is empty".to_string())),
+        Err(e) => render_archives(&state, None, Some(e.to_string())),
+    }
+}
+
+pub async fn archives_restore_handler(
+    State(state): State<AppState>,
+    audit: AuditContext,
+    Path(name): Path<String>,
+) -> impl IntoResponse {
+    match state.log_repository.restore_archive(&name) {
+        Ok(records) => {
+            audit.record(
+                &state,
+                "archive.restore",
+                json!({"archive": name, "records": records}),
+            );
+            render_archives(
+                &state,
+                Some(format!("Restored {} records from {}", records, name)),
+                None,
+            )
+        }
+        Err(e) => render_archives(&state, None, Some(e.to_string())),
+    }
+}
+
+pub async fn archives_purge_handler(
+    State(state): State<AppState>,
+    audit: AuditContext,
+    Path(name): Path<String>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    // The admin has to type the archive name into the prompt
+    if prompt_value(&headers).as_deref() != Some(name.as_str()) {
+        return render_archives(
+            &state,
+            None,
+            Some("Archive name did not match, nothing was purged".to_string()),
+        );
+    }
+
+    match state.log_repository.purge_archive(&name) {
+        Ok(true) => {
+            audit.record(&state, "archive.purge", json!({"archive": name}));
+            render_archives(&state, Some(format!("Purged {}", name)), None)
+        }
+        Ok(false) => render_archives(&state, None, Some(format!("No such archive: {}", name))),
+        Err(e) => render_archives(&state, None, Some(e.to_string())),
+    }
+}
+
+pub async fn privacy_tab_handler(
+    State(state): State<AppState>,
+    audit: AuditContext,
+    Query(filter): Query<LogFilter>,
+) -> impl IntoResponse {
+    let filter = state.subject_filter(filter);
+    if filter.is_empty() {
+        return render_privacy(&state, &filter, None, None, None);
+    }
+
+    let events = state.log_repository.find_matching(&filter);
+    audit.record(
+        &state,
+        "subject.search",
+        json!({"device_id": filter.device_id, "ip": filter.ip, "found": events.len()}),
     );
+    render_privacy(&state, &filter, Some(events), None, None)
+}
+
+pub async fn privacy_erase_handler(
+    State(state): State<AppState>,
+    audit: AuditContext,
+    Form(filter): Form<LogFilter>,
+) -> impl IntoResponse {
+    let filter = state.subject_filter(filter);
+    match state.log_repository.delete_matching(&filter) {
+        Ok(removed) => {
+            audit.record(
+                &state,
+                "logs.erase",
+                json!({"device_id": filter.device_id, "ip": filter.ip, "removed": removed}),
+            );
+            let message = format!("Erased {} events", removed);
+            render_privacy(&state, &filter, None, Some(message), None)
+        }
+        Err(e) => render_privacy(&state, &filter, None, None, Some(e.to_string())),
+    }
+}
 
-    (uptime, cpu, ram)
+fn render_privacy(
+    state: &AppState,
+    filter: &LogFilter,
+    events: Option<Vec<SubjectEvent>>,
+    message: Option<String>,
+    error: Option<String>,
+) -> HtmlTemplate<PrivacyTemplate> {
+    let device_id = filter.device_id.clone().unwrap_or_default();
+    let ip = filter.ip.clone().unwrap_or_default();
+    let export_query = serde_urlencoded::to_string([("device_id", &device_id), ("ip", &ip)])
+        .unwrap_or_default();
+
+    HtmlTemplate(PrivacyTemplate {
+        mode: state.ip_privacy.as_str().to_string(),
+        device_id,
+        ip,
+        export_query,
+        events,
+        message,
+        error,
+    })
+}
+
+fn render_archives(
+    state: &AppState,
+    message: Option<String>,
+    error: Option<String>,
+) -> HtmlTemplate<ArchivesTemplate> {
+    HtmlTemplate(ArchivesTemplate {
+        archives: state.log_repository.list_archives(),
+        message,
+        error,
+    })
+}
+
+fn format_timestamp(ts: i64) -> String {
+    chrono::DateTime::from_timestamp(ts, 0)
+        .map(|dt| {
+            dt.with_timezone(&chrono::Local)
+                .format("%Y-%m-%d %H:%M:%S")
+                .to_string()
+        })
+        .unwrap_or_default()
 }
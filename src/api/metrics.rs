@@ -0,0 +1,94 @@
+use crate::services::metrics::Exposition;
+use crate::state::AppState;
+use axum::{
+    extract::State,
+    http::header,
+    response::IntoResponse,
+};
+use std::collections::BTreeMap;
+
+/// Prometheus scrape target: presence gauges and process health read now,
+/// plus the counters collected since startup.
+pub async fn prometheus_metrics(State(state): State<AppState>) -> impl IntoResponse {
+    let mut out = Exposition::default();
+
+    // Rooms are client-chosen, so only those in METRICS_ROOMS get their own
+    // series; the rest share "other" and connections without one report ""
+    let mut connections: BTreeMap<(&str, &str), u64> = BTreeMap::new();
+    for conn in state.active_connections.lock().unwrap().values() {
+        let room = match conn.room.as_deref() {
+            None => "",
+            Some(room) => state.metrics_rooms.get(room).map_or("other", String::as_str),
+        };
+        *connections.entry(("client", room)).or_insert(0) += 1;
+    }
+    let admins = state.admin_connections.lock().unwrap().len() as u64;
+    if admins > 0 {
+        connections.insert(("admin", ""), admins);
+    }
+    out.family(
+        "counter_active_connections",
+        "gauge",
+        "Open WebSocket connections by endpoint and room",
+    );
+    for ((endpoint, room), count) in &connections {
+        out.sample(
+            "counter_active_connections",
+            &[("endpoint", endpoint), ("room", room)],
+            count,
+        );
+    }
+
+    out.family("counter_active_users", "gauge", "Connected users, as shown to clients");
+    out.sample("counter_active_users", &[], state.get_active_count());
+    out.family("counter_active_bots", "gauge", "Connected clients classified as bots");
+    out.sample("counter_active_bots", &[], state.get_bot_count());
+
+    out.family(
+        "counter_connection_rejections_total",
+        "counter",
+        "Connections refused by the connection limits, by reason",
+    );
+    for (reason, count) in state.connection_limiter.rejection_counts() {
+        out.sample("counter_connection_rejections_total", &[("reason", reason)], count);
+    }
+
+    state.metrics.write(&mut out);
+
+    let system = state.system_snapshot();
+    let usage = &system.process;
+    out.family(
+        "process_cpu_usage_percent",
+        "gauge",
+        "CPU used by this process over the last sampling interval, in percent of one core",
+    );
+    out.sample("process_cpu_usage_percent", &[], usage.cpu_percent);
+    out.family(
+        "process_resident_memory_bytes",
+        "gauge",
+        "Resident memory size in bytes",
+    );
+    out.sample("process_resident_memory_bytes", &[], usage.memory_bytes);
+    out.family(
+        "process_virtual_memory_bytes",
+        "gauge",
+        "Virtual memory size in bytes",
+    );
+    out.sample("process_virtual_memory_bytes", &[], usage.virtual_memory_bytes);
+    if let Some(threads) = usage.threads {
+        out.family("process_threads", "gauge", "Threads in this process");
+        out.sample("process_threads", &[], threads);
+    }
+    if let Some(open_files) = usage.open_files {
+        out.family("process_open_fds", "gauge", "Open file descriptors");
+        out.sample("process_open_fds", &[], open_files);
+    }
+    out.family(
+        "counter_uptime_seconds",
+        "gauge",
+        "Seconds since the server started",
+    );
+    out.sample("counter_uptime_seconds", &[], system.uptime_secs);
+
+    ([(header::CONTENT_TYPE, Exposition::CONTENT_TYPE)], out.finish())
+}
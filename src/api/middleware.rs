@@ -1,10 +1,21 @@
+use crate::api::csrf;
+use crate::state::AppState;
 use axum::{
     body::Body,
-    http::{Request, StatusCode},
+    extract::State,
+    http::{HeaderMap, Method, Request, StatusCode, header},
     middleware::Next,
-    response::{IntoResponse, Redirect, Response},
+    response::{IntoResponse, Json, Redirect, Response},
 };
 use axum_extra::extract::cookie::SignedCookieJar;
+use hmac::{Hmac, Mac};
+use serde_json::json;
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::env;
+
+// Upper bound on form bodies buffered while looking for the CSRF field
+const MAX_FORM_BODY: usize = 64 * 1024;
 
 pub async fn auth(
     jar: SignedCookieJar,
@@ -17,3 +28,121 @@ pub async fn auth(
 
     Ok(Redirect::to("/login").into_response())
 }
+
+// Like `auth`, but for API and WebSocket routes: answers 401 instead of
+// redirecting, and also accepts `Authorization: Bearer $ADMIN_API_TOKEN`.
+pub async fn admin_api(jar: SignedCookieJar, req: Request<Body>, next: Next) -> Response {
+    if is_admin(&jar, req.headers()) {
+        return next.run(req).await;
+    }
+
+    (
+        StatusCode::UNAUTHORIZED,
+        Json(json!({"error": "Unauthorized"})),
+    )
+        .into_response()
+}
+
+// Scrapers can't log in, so `/metrics` takes `Authorization: Bearer
+// $METRICS_TOKEN` instead (or an admin session). Open when the token is unset.
+pub async fn metrics_auth(jar: SignedCookieJar, req: Request<Body>, next: Next) -> Response {
+    let expected = env::var("METRICS_TOKEN").unwrap_or_default();
+    if expected.is_empty()
+        || bearer_token(req.headers()).is_some_and(|token| secret_matches(token, &expected))
+        || is_admin(&jar, req.headers())
+    {
+        return next.run(req).await;
+    }
+
+    (StatusCode::UNAUTHORIZED, "Unauthorized").into_response()
+}
+
+pub fn is_admin(jar: &SignedCookieJar, headers: &HeaderMap) -> bool {
+    jar.get("auth_token").is_some() || has_admin_token(headers)
+}
+
+fn has_admin_token(headers: &HeaderMap) -> bool {
+    let expected = match env::var("ADMIN_API_TOKEN") {
+        Ok(v) if !v.is_empty() => v,
+        _ => return false,
+    };
+
+    bearer_token(headers).is_some_and(|token| secret_matches(token, &expected))
+}
+
+// Constant-time comparison: both values are MACed first so neither the
+// position of the first differing byte nor the length leaks through timing.
+pub(crate) fn secret_matches(given: &str, expected: &str) -> bool {
+    let mac = |value: &str| {
+        let mut mac = Hmac::<Sha256>::new_from_slice(b"bearer-token")
+            .expect("HMAC accepts keys of any length");
+        mac.update(value.as_bytes());
+        mac
+    };
+    mac(given)
+        .verify_slice(&mac(expected).finalize().into_bytes())
+        .is_ok()
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(str::trim)
+}
+
+pub async fn csrf(
+    State(state): State<AppState>,
+    jar: SignedCookieJar,
+    req: Request<Body>,
+    next: Next,
+) -> Response {
+    if matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS) {
+        return next.run(req).await;
+    }
+
+    let Some(session_id) = csrf::session_id(&jar) else {
+        return (StatusCode::FORBIDDEN, "Missing CSRF session").into_response();
+    };
+
+    // HTMX requests carry the token in a header, plain forms in a hidden field
+    let header_token = req
+        .headers()
+        .get(csrf::CSRF_HEADER)
+        .and_then(|h| h.to_str().ok())
+        .map(|s| s.to_string());
+
+    let (req, token) = match header_token {
+        Some(token) => (req, Some(token)),
+        None => {
+            let is_form = req
+                .headers()
+                .get(header::CONTENT_TYPE)
+                .and_then(|h| h.to_str().ok())
+                .is_some_and(|ct| ct.starts_with("application/x-www-form-urlencoded"));
+
+            if !is_form {
+                (req, None)
+            } else {
+                let (parts, body) = req.into_parts();
+                let bytes = match axum::body::to_bytes(body, MAX_FORM_BODY).await {
+                    Ok(b) => b,
+                    Err(_) => {
+                        return (StatusCode::PAYLOAD_TOO_LARGE, "Form body too large")
+                            .into_response();
+                    }
+                };
+                let token = serde_urlencoded::from_bytes::<HashMap<String, String>>(&bytes)
+                    .ok()
+                    .and_then(|mut fields| fields.remove(csrf::CSRF_FIELD));
+                (Request::from_parts(parts, Body::from(bytes)), token)
+            }
+        }
+    };
+
+    match token {
+        Some(token) if csrf::verify_token(&state.key, &session_id, &token) => next.run(req).await,
+        _ => (StatusCode::FORBIDDEN, "Invalid CSRF token").into_response(),
+    }
+}
@@ -1,7 +1,13 @@
 pub mod admin;
+pub mod audit;
 pub mod auth;
+pub mod client;
+pub mod client_ip;
+pub mod csrf;
+pub mod events;
 pub mod health;
 pub mod htmx;
+pub mod metrics;
 pub mod middleware;
 pub mod wakatime;
 pub mod websocket;
@@ -1,41 +1,190 @@
-use crate::state::AppState;
+use crate::api::client_ip::ClientIp;
+use crate::domain::{Announcement, SystemMetrics, UserMetrics};
+use crate::services::client_token::ClientAuthMode;
+use crate::services::connection_limiter::{ConnectionPermit, LimitRejection};
+use crate::state::{AppState, ConnectionCommand, Presence};
 use axum::{
     extract::{
-        ConnectInfo, Query, State,
-        ws::{Message, WebSocket, WebSocketUpgrade},
+        Query, State,
+        ws::{CloseFrame, Message, WebSocket, WebSocketUpgrade, close_code},
     },
-    http::HeaderMap,
-    response::IntoResponse,
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Response},
 };
+use serde::Serialize;
+use serde_json::json;
 use std::collections::HashMap;
-use std::net::SocketAddr;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::watch;
+
+// Admin sockets are keyed by a server-assigned id rather than a client-supplied device_id
+static ADMIN_CONNECTION_SEQ: AtomicU64 = AtomicU64::new(1);
 
 pub async fn client_ws_handler(
     ws: WebSocketUpgrade,
     State(state): State<AppState>,
-    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    client_ip: ClientIp,
     headers: HeaderMap,
     Query(params): Query<HashMap<String, String>>,
-) -> impl IntoResponse {
-    let (ip, device, device_id) = extract_connection_info(headers, params, addr);
-    ws.on_upgrade(move |socket| handle_user_socket(socket, state, ip, device, device_id))
+) -> Response {
+    let client = match admit_client(&state, client_ip, headers, params) {
+        Ok(client) => client,
+        Err(response) => return response,
+    };
+
+    let permit = match state.connection_limiter.try_acquire(client.peer_ip, &client.device_id) {
+        Ok(permit) => permit,
+        Err(rejection) => return ws.on_upgrade(move |socket| close_rejected(socket, rejection)),
+    };
+
+    ws.on_upgrade(move |socket| handle_user_socket(socket, state, client, permit))
+}
+
+/// A client that passed the access list and token checks, not yet counted
+/// against the connection limits.
+pub(crate) struct ClientAdmission {
+    pub peer_ip: IpAddr,
+    pub ip: String,
+    pub device: String,
+    pub device_id: String,
+    // The device_id came from a signed client token rather than the client's say-so
+    pub verified: bool,
+    pub room: Option<String>,
+}
+
+// Shared by every client transport; Err is the response to send instead
+#[allow(clippy::result_large_err)]
+pub(crate) fn admit_client(
+    state: &AppState,
+    client_ip: ClientIp,
+    headers: HeaderMap,
+    params: HashMap<String, String>,
+) -> Result<ClientAdmission, Response> {
+    // Browsers can't set headers on a WebSocket or EventSource, so the token may come via the query string
+    let token = params.get("token").cloned().or_else(|| {
+        headers
+            .get(header::AUTHORIZATION)
+            .and_then(|h| h.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .map(|s| s.trim().to_string())
+    });
+    let room = params
+        .get("room")
+        .map(|r| r.trim().to_string())
+        .filter(|r| !r.is_empty());
+    let peer_ip = client_ip.0;
+    let blocked = state.access_list.find_block(peer_ip).is_some();
+    let (ip, device, claimed_id) = extract_connection_info(headers, params, client_ip);
+
+    if blocked {
+        return Err(reject_blocked(state, &ip, &device, &claimed_id));
+    }
+
+    let verified = token.is_some();
+    let device_id = match token {
+        Some(token) => match state.client_tokens.verify(&token) {
+            Ok(claims) => claims.sub,
+            Err(e) => return Err((StatusCode::UNAUTHORIZED, e.to_string()).into_response()),
+        },
+        None => match state.client_auth_mode {
+            ClientAuthMode::Off => claimed_id,
+            ClientAuthMode::Optional => anonymous_device_id(),
+            ClientAuthMode::Required => {
+                return Err((StatusCode::UNAUTHORIZED, "client token required").into_response());
+            }
+        },
+    };
+
+    if state.access_list.find_device_block(&device_id).is_some() {
+        return Err(reject_blocked(state, &ip, &device, &device_id));
+    }
+
+    Ok(ClientAdmission {
+        peer_ip,
+        ip,
+        device,
+        device_id,
+        verified,
+        room,
+    })
 }
 
 pub async fn admin_ws_handler(
     ws: WebSocketUpgrade,
     State(state): State<AppState>,
-    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    client_ip: ClientIp,
     headers: HeaderMap,
     Query(params): Query<HashMap<String, String>>,
-) -> impl IntoResponse {
-    let (ip, device, device_id) = extract_connection_info(headers, params, addr);
-    ws.on_upgrade(move |socket| handle_system_socket(socket, state, ip, device, device_id))
+) -> Response {
+    // `stream=users` lets the dashboard follow presence without joining it as a client
+    let stream = params.get("stream").cloned().unwrap_or_default();
+    let peer_ip = client_ip.0;
+    let (ip, device, connection_id) = match admit_admin(&state, client_ip, headers, params) {
+        Ok(admin) => admin,
+        Err(response) => return response,
+    };
+
+    let permit = match state.connection_limiter.try_acquire(peer_ip, &connection_id) {
+        Ok(permit) => permit,
+        Err(rejection) => return ws.on_upgrade(move |socket| close_rejected(socket, rejection)),
+    };
+
+    ws.on_upgrade(move |socket| async move {
+        // Slot is held for as long as the socket task runs
+        let _permit = permit;
+        let (initial, feed) = AdminFeed::open(&state, &stream);
+        handle_admin_socket(socket, state, ip, device, connection_id, initial, feed).await
+    })
+}
+
+// Access list check for admin feeds. Ok is (ip, device, server-assigned connection id)
+#[allow(clippy::result_large_err)]
+pub(crate) fn admit_admin(
+    state: &AppState,
+    client_ip: ClientIp,
+    headers: HeaderMap,
+    params: HashMap<String, String>,
+) -> Result<(String, String, String), Response> {
+    let blocked = state.access_list.find_block(client_ip.0).is_some();
+    let (ip, device, _) = extract_connection_info(headers, params, client_ip);
+    let connection_id = format!(
+        "admin-{}",
+        ADMIN_CONNECTION_SEQ.fetch_add(1, Ordering::Relaxed)
+    );
+
+    if blocked {
+        return Err(reject_blocked(state, &ip, &device, &connection_id));
+    }
+    Ok((ip, device, connection_id))
+}
+
+// Over a connection limit: complete the upgrade so the client gets a proper close code
+async fn close_rejected(mut socket: WebSocket, rejection: LimitRejection) {
+    tracing::info!(reason = rejection.as_str(), "Connection over limit");
+    let _ = socket
+        .send(Message::Close(Some(CloseFrame {
+            code: rejection.close_code(),
+            reason: rejection.as_str().into(),
+        })))
+        .await;
+}
+
+// Denied before the upgrade; the attempt is still recorded in the event log
+fn reject_blocked(state: &AppState, ip: &str, device: &str, device_id: &str) -> Response {
+    tracing::info!(device_id, "Connection blocked");
+    state
+        .logger
+        .log(ip, device, device_id, "BLOCKED", state.get_active_count(), None);
+    (StatusCode::FORBIDDEN, "Forbidden").into_response()
 }
 
 fn extract_connection_info(
     headers: HeaderMap,
     params: HashMap<String, String>,
-    addr: SocketAddr,
+    ClientIp(ip): ClientIp,
 ) -> (String, String, String) {
     // Extract User-Agent
     let device = headers
@@ -45,65 +194,101 @@ fn extract_connection_info(
         .to_string();
 
     // Extract Device ID
-    let device_id = params.get("device_id").cloned().unwrap_or_else(|| {
-        use rand::Rng;
-        let mut rng = rand::rng();
-        let id: u32 = rng.random();
-        format!("anon-{}", id)
-    });
+    let device_id = params
+        .get("device_id")
+        .cloned()
+        .unwrap_or_else(anonymous_device_id);
 
-    // Extract Real IP
-    let ip = headers
-        .get("x-forwarded-for")
-        .and_then(|h| h.to_str().ok())
-        .map(|s| s.split(',').next().unwrap_or(s).trim().to_string())
-        .or_else(|| {
-            headers
-                .get("x-real-ip")
-                .and_then(|h| h.to_str().ok())
-                .map(|s| s.to_string())
-        })
-        .unwrap_or_else(|| addr.ip().to_string());
+    // Real IP was already resolved through the trusted proxy list
+    (ip.to_string(), device, device_id)
+}
+
+fn anonymous_device_id() -> String {
+    use rand::Rng;
+    let mut rng = rand::rng();
+    let id: u32 = rng.random();
+    format!("anon-{}", id)
+}
+
+fn json_text<T: Serialize + ?Sized>(value: &T) -> String {
+    serde_json::to_string(value).unwrap()
+}
 
-    (ip, device, device_id)
+// What an admin feed follows: presence broadcasts, or the system sampler's snapshots
+pub(crate) enum AdminFeed {
+    Users(broadcast::Receiver<UserMetrics>),
+    System(watch::Receiver<Arc<SystemMetrics>>),
 }
 
-async fn handle_system_socket(
+impl AdminFeed {
+    /// Subscribes to `stream` ("users", anything else is system metrics) and
+    /// returns the current value to send first.
+    pub(crate) fn open(state: &AppState, stream: &str) -> (String, AdminFeed) {
+        if stream == "users" {
+            let rx = state.users_tx.subscribe();
+            (json_text(&state.get_user_metrics()), AdminFeed::Users(rx))
+        } else {
+            let mut rx = state.system.clone();
+            let snapshot = rx.borrow_and_update().clone();
+            (json_text(&*snapshot), AdminFeed::System(rx))
+        }
+    }
+
+    /// Next message to send, or `None` once the source has gone away.
+    pub(crate) async fn next(&mut self, state: &AppState) -> Option<String> {
+        match self {
+            AdminFeed::Users(rx) => loop {
+                match rx.recv().await {
+                    Ok(metrics) => return Some(json_text(&metrics)),
+                    Err(RecvError::Lagged(skipped)) => {
+                        tracing::warn!(skipped, "Admin socket fell behind");
+                        state.metrics.lagged("admin", skipped);
+                    }
+                    Err(RecvError::Closed) => return None,
+                }
+            },
+            // Only the latest snapshot matters, so a slow socket just skips readings
+            AdminFeed::System(rx) => {
+                rx.changed().await.ok()?;
+                let snapshot = rx.borrow_and_update().clone();
+                Some(json_text(&*snapshot))
+            }
+        }
+    }
+}
+
+#[tracing::instrument(name = "admin_socket", skip_all, fields(connection_id = %connection_id))]
+async fn handle_admin_socket(
     mut socket: WebSocket,
     state: AppState,
     ip: String,
     device: String,
-    device_id: String,
+    connection_id: String,
+    initial: String,
+    mut feed: AdminFeed,
 ) {
-    // 1. Client connected
-    state.join(&ip, &device, &device_id);
-
-    // 2. Subscribe to SYSTEM updates
-    let mut rx = state.system_tx.subscribe();
-
-    // 3. Send initial state immediately
-    let stats = state.get_system_metrics();
-    let initial_msg = serde_json::to_string(&stats).unwrap();
+    // 1. Admin connected (tracked apart from client presence)
+    state.admin_join(&ip, &device, &connection_id);
+    tracing::info!("Admin connected");
 
-    if socket
-        .send(Message::Text(initial_msg.into()))
-        .await
-        .is_err()
-    {
-        state.leave(&ip, &device, &device_id);
+    // 2. Send initial state immediately
+    if !send_text(&mut socket, &state, "admin", initial).await {
+        state.admin_leave(&connection_id);
         return;
     }
 
-    // 4. Listen for updates OR client disconnect
+    // 3. Listen for updates OR client disconnect
     loop {
         tokio::select! {
-            // Receive update from channel
-            Ok(msg) = rx.recv() => {
-                let json = serde_json::to_string(&msg).unwrap();
-                if socket.send(Message::Text(json.into())).await.is_err() {
-                    break;
+            // Receive update from the feed
+            update = feed.next(&state) => match update {
+                Some(json) => {
+                    if !send_text(&mut socket, &state, "admin", json).await {
+                        break;
+                    }
                 }
-            }
+                None => break,
+            },
             // Receive message from client (ignore or handle close)
             incoming = socket.recv() => {
                 match incoming {
@@ -114,44 +299,115 @@ async fn handle_system_socket(
         }
     }
 
-    // 5. Client disconnected
-    state.leave(&ip, &device, &device_id);
+    // 4. Admin disconnected
+    state.admin_leave(&connection_id);
+    tracing::info!("Admin disconnected");
 }
 
+#[tracing::instrument(
+    name = "client_socket",
+    skip_all,
+    fields(device_id = %client.device_id, room = client.room.as_deref(), connection_id)
+)]
 async fn handle_user_socket(
     mut socket: WebSocket,
     state: AppState,
-    ip: String,
-    device: String,
-    device_id: String,
+    client: ClientAdmission,
+    // Released on return, freeing the connection slot
+    _permit: ConnectionPermit,
 ) {
+    let ClientAdmission {
+        ip,
+        device,
+        device_id,
+        verified,
+        room,
+        ..
+    } = client;
     // 1. Client connected
-    state.join(&ip, &device, &device_id);
+    let Presence {
+        connection_id,
+        mut commands,
+    } = state.join(&ip, &device, &device_id, verified, room.as_deref());
+    tracing::Span::current().record("connection_id", connection_id);
+    tracing::info!("Client connected");
 
-    // 2. Subscribe to USER updates
+    // 2. Subscribe to USER updates and announcements
     let mut rx = state.users_tx.subscribe();
+    let mut announcements_rx = state.announcements_tx.subscribe();
 
     // 3. Send initial state immediately
     let stats = state.get_user_metrics();
     let initial_msg = serde_json::to_string(&stats).unwrap();
 
-    if socket
-        .send(Message::Text(initial_msg.into()))
-        .await
-        .is_err()
-    {
-        state.leave(&ip, &device, &device_id);
+    if !send_text(&mut socket, &state, "client", initial_msg).await {
+        state.leave(&ip, &device, &device_id, connection_id);
         return;
     }
 
-    // 4. Listen for updates OR client disconnect
+    // Replay announcements still in force, oldest first
+    let mut active = state.announcements.active();
+    active.reverse();
+    for announcement in active.iter().filter(|a| a.targets(room.as_deref())) {
+        if !send_text(&mut socket, &state, "client", announcement_message(announcement)).await {
+            state.leave(&ip, &device, &device_id, connection_id);
+            return;
+        }
+    }
+
+    // 4. Listen for updates, announcements, admin commands OR client disconnect
+    let mut kick_reason = None;
     loop {
         tokio::select! {
             // Receive update from channel
-            Ok(msg) = rx.recv() => {
-                let json = serde_json::to_string(&msg).unwrap();
-                if socket.send(Message::Text(json.into())).await.is_err() {
-                    break;
+            update = rx.recv() => match update {
+                Ok(msg) => {
+                    let json = serde_json::to_string(&msg).unwrap();
+                    if !send_text(&mut socket, &state, "client", json).await {
+                        break;
+                    }
+                }
+                Err(RecvError::Lagged(skipped)) => {
+                    tracing::warn!(skipped, "Client socket fell behind");
+                    state.metrics.lagged("client", skipped);
+                }
+                Err(RecvError::Closed) => break,
+            },
+            update = announcements_rx.recv() => match update {
+                Ok(announcement) => {
+                    if !announcement.targets(room.as_deref()) {
+                        continue;
+                    }
+                    let json = announcement_message(&announcement);
+                    if !send_text(&mut socket, &state, "client", json).await {
+                        break;
+                    }
+                }
+                Err(RecvError::Lagged(skipped)) => {
+                    tracing::warn!(skipped, "Client socket fell behind");
+                    state.metrics.lagged("client", skipped);
+                }
+                Err(RecvError::Closed) => break,
+            },
+            // Command pushed from the admin dashboard
+            Some(command) = commands.recv() => {
+                match command {
+                    ConnectionCommand::Notice { .. } => {
+                        let json = command.to_message().to_string();
+                        if !send_text(&mut socket, &state, "client", json).await {
+                            break;
+                        }
+                    }
+                    ConnectionCommand::Kick { reason } => {
+                        let _ = socket
+                            .send(Message::Close(Some(CloseFrame {
+                                code: close_code::POLICY,
+                                reason: reason.clone().into(),
+                            })))
+                            .await;
+                        kick_reason = Some(reason);
+                        break;
+                    }
                 }
             }
             // Receive message from client (ignore or handle close)
@@ -164,6 +420,31 @@ async fn handle_user_socket(
         }
     }
 
-    // 5. Client disconnected
-    state.leave(&ip, &device, &device_id);
+    // 5. Client disconnected (or was kicked)
+    tracing::info!(kick_reason = kick_reason.as_deref(), "Client disconnected");
+    match kick_reason {
+        Some(reason) => state.kicked(&ip, &device, &device_id, connection_id, &reason),
+        None => state.leave(&ip, &device, &device_id, connection_id),
+    };
+}
+
+// False if the socket is gone; the failure is counted against `endpoint`
+async fn send_text(socket: &mut WebSocket, state: &AppState, endpoint: &str, text: String) -> bool {
+    let sent = socket.send(Message::Text(text.into())).await.is_ok();
+    if !sent {
+        tracing::debug!(endpoint, "WebSocket send failed");
+        state.metrics.send_failed(endpoint);
+    }
+    sent
+}
+
+// Ended announcements are sent once more so clients can take them down
+pub(crate) fn announcement_message(announcement: &Announcement) -> String {
+    let now = chrono::Utc::now().timestamp();
+    let kind = if announcement.is_expired(now) {
+        "announcement_ended"
+    } else {
+        "announcement"
+    };
+    json!({"type": kind, "announcement": announcement}).to_string()
 }
@@ -1,8 +1,11 @@
-use chrono::{DateTime, Duration};
+use chrono::DateTime;
 use std::collections::HashMap;
 use std::fs;
 use std::io::Write;
 
+#[path = "../services/log_chain.rs"]
+mod log_chain;
+
 #[derive(Debug, Clone)]
 struct LogEntry {
     timestamp: String,
@@ -12,7 +15,8 @@ struct LogEntry {
     action: String,
     count: u32,
     duration: Option<String>,
-    raw: String,
+    // Columns after duration (notes etc.), carried through untouched
+    extra: Vec<String>,
 }
 
 // Helper function to format duration
@@ -34,6 +38,15 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     }
 
     let content = fs::read_to_string(path)?;
+
+    // Rewriting hash-chained records makes them fail `counter verify-logs`
+    let force = std::env::args().any(|arg| arg == "--force");
+    if !force && content.lines().any(is_chained) {
+        println!("{} contains hash-chained records; rewriting it will break the chain.", path);
+        println!("Re-run with --force to migrate anyway.");
+        return Ok(());
+    }
+
     let mut logs: Vec<LogEntry> = content
         .lines()
         .filter_map(parse_log_line)
@@ -51,7 +64,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             "CONNECTED" => {
                 start_times.insert(log.device_id.clone(), log.timestamp.clone());
             }
-            "DISCONNECTED" => {
+            "DISCONNECTED" | "KICKED" => {
                  process_disconnected(&mut log, &mut start_times);
             }
             _ => {}
@@ -65,11 +78,15 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     for log in updated_logs {
         let duration_str = log.duration.unwrap_or_default();
 
-        writeln!(
+        write!(
             file,
             "{},{},{},{},{},{},{}",
             log.timestamp, log.ip, log.device, log.device_id, log.action, log.count, duration_str
         )?;
+        for column in &log.extra {
+            write!(file, ",{}", column)?;
+        }
+        writeln!(file)?;
     }
 
     println!("Migration complete. Processed {} logs.", count);
@@ -103,20 +120,24 @@ fn parse_log_line(line: &str) -> Option<LogEntry> {
         action,
         count,
         duration,
-        raw: line.to_string(),
+        extra: parts.iter().skip(7).map(|p| p.to_string()).collect(),
     })
 }
 
+// Same test `counter verify-logs` uses to find the chain
+fn is_chained(line: &str) -> bool {
+    log_chain::split_hash(line).is_some()
+}
+
 fn process_disconnected(log: &mut LogEntry, start_times: &mut HashMap<String, String>) {
-    if let Some(start_ts) = start_times.remove(&log.device_id) {
-        if let Ok(start) = DateTime::parse_from_str(&start_ts, "%Y-%m-%d %H:%M:%S %z") {
-            if let Ok(end) = DateTime::parse_from_str(&log.timestamp, "%Y-%m-%d %H:%M:%S %z") {
-                let duration = end.signed_duration_since(start);
-                let secs = duration.num_seconds();
-                if secs >= 0 {
-                    log.duration = Some(format_duration(secs));
-                }
-            }
+    if let Some(start_ts) = start_times.remove(&log.device_id)
+        && let Ok(start) = DateTime::parse_from_str(&start_ts, "%Y-%m-%d %H:%M:%S %z")
+        && let Ok(end) = DateTime::parse_from_str(&log.timestamp, "%Y-%m-%d %H:%M:%S %z")
+    {
+        let duration = end.signed_duration_since(start);
+        let secs = duration.num_seconds();
+        if secs >= 0 {
+            log.duration = Some(format_duration(secs));
         }
     }
 }
@@ -8,4 +8,20 @@ pub trait EventLogger: Send + Sync {
         count: u32,
         duration: Option<String>,
     );
+
+    /// Same as `log`, with a free-form note (e.g. a kick reason) appended as an extra column.
+    #[allow(clippy::too_many_arguments)]
+    fn log_with_note(
+        &self,
+        ip: &str,
+        device: &str,
+        device_id: &str,
+        action: &str,
+        count: u32,
+        duration: Option<String>,
+        note: &str,
+    ) {
+        let _ = note;
+        self.log(ip, device, device_id, action, count, duration);
+    }
 }
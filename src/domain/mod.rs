@@ -1,34 +1,187 @@
 use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
 
 pub mod logger;
 pub mod repositories;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Host and process health, as published by the system sampler. Values are raw
+/// numbers (seconds, percent, bytes); templates and the dashboard format them.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct SystemMetrics {
-    pub uptime: String,
-    pub cpu: String,
-    pub ram: String,
+    pub uptime_secs: u64,
+    // Percent of all cores, then of each core
+    pub cpu: f32,
+    pub cpu_cores: Vec<f32>,
+    pub load: LoadAverage,
+    pub ram_used: u64,
+    pub ram_total: u64,
+    pub swap_used: u64,
+    pub swap_total: u64,
+    pub disks: Vec<DiskUsage>,
+    // Bytes per second across all interfaces
+    pub net_rx: u64,
+    pub net_tx: u64,
+    pub process: ProcessUsage,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct UserMetrics {
-    #[serde(rename = "activeUsers")]
-    pub active_users: u32,
-    #[serde(rename = "totalUsers")]
-    pub total_users: u32,
+impl SystemMetrics {
+    pub fn ram_percent(&self) -> f64 {
+        percent(self.ram_used, self.ram_total)
+    }
+
+    pub fn swap_percent(&self) -> f64 {
+        percent(self.swap_used, self.swap_total)
+    }
+}
+
+fn percent(used: u64, total: u64) -> f64 {
+    if total == 0 {
+        return 0.0;
+    }
+    used as f64 * 100.0 / total as f64
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct LoadAverage {
+    pub one: f64,
+    pub five: f64,
+    pub fifteen: f64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DiskUsage {
+    pub mount_point: String,
+    pub file_system: String,
+    pub total: u64,
+    pub available: u64,
+}
+
+impl DiskUsage {
+    pub fn used_percent(&self) -> f64 {
+        percent(self.total.saturating_sub(self.available), self.total)
+    }
+}
+
+/// This process's own resource use, as opposed to the host-wide `SystemMetrics`.
+/// Thread and file descriptor counts are None where the OS doesn't report them.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct ProcessUsage {
+    pub cpu_percent: f32,
+    pub memory_bytes: u64,
+    pub virtual_memory_bytes: u64,
+    pub threads: Option<usize>,
+    pub open_files: Option<usize>,
+}
+
+/// One point of system metrics history. Downsampled points cover a whole
+/// minute or hour bucket, starting at `timestamp` (unix seconds).
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct MetricsSample {
+    pub timestamp: i64,
+    // Percent of all cores
+    pub cpu: f32,
+    pub ram_used: u64,
+    pub ram_total: u64,
+    // One-minute load average
+    pub load: f64,
+    // Bytes per second across all interfaces
+    pub net_rx: u64,
+    pub net_tx: u64,
+    // Client sockets, bots included
+    pub connections: u32,
+    pub users: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HistoryResolution {
+    // As sampled, every 2 seconds
+    Raw,
+    Minute,
+    Hour,
+}
+
+impl HistoryResolution {
+    pub fn seconds(&self) -> i64 {
+        match self {
+            HistoryResolution::Raw => 2,
+            HistoryResolution::Minute => 60,
+            HistoryResolution::Hour => 3600,
+        }
+    }
+}
+
+/// Time spans offered by `/api/metrics/history?range=`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HistoryRange {
+    FifteenMinutes,
+    Hour,
+    SixHours,
+    Day,
+    Week,
+    Month,
+}
+
+impl HistoryRange {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HistoryRange::FifteenMinutes => "15m",
+            HistoryRange::Hour => "1h",
+            HistoryRange::SixHours => "6h",
+            HistoryRange::Day => "24h",
+            HistoryRange::Week => "7d",
+            HistoryRange::Month => "30d",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "15m" => Some(HistoryRange::FifteenMinutes),
+            "1h" => Some(HistoryRange::Hour),
+            "6h" => Some(HistoryRange::SixHours),
+            "24h" => Some(HistoryRange::Day),
+            "7d" => Some(HistoryRange::Week),
+            "30d" => Some(HistoryRange::Month),
+            _ => None,
+        }
+    }
+
+    pub fn seconds(&self) -> i64 {
+        match self {
+            HistoryRange::FifteenMinutes => 15 * 60,
+            HistoryRange::Hour => 3600,
+            HistoryRange::SixHours => 6 * 3600,
+            HistoryRange::Day => 24 * 3600,
+            HistoryRange::Week => 7 * 24 * 3600,
+            HistoryRange::Month => 30 * 24 * 3600,
+        }
+    }
+
+    // Finest resolution still kept for the whole range
+    pub fn resolution(&self) -> HistoryResolution {
+        match self {
+            HistoryRange::FifteenMinutes | HistoryRange::Hour => HistoryResolution::Raw,
+            HistoryRange::SixHours | HistoryRange::Day => HistoryResolution::Minute,
+            HistoryRange::Week | HistoryRange::Month => HistoryResolution::Hour,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MetricsHistoryResponse {
+    pub range: &'static str,
+    pub resolution_secs: i64,
+    pub samples: Vec<MetricsSample>,
 }
 
-// Keep DashboardStats for backward compatibility / initial render if needed,
-// or reconstruct it from the two new structs.
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct DashboardStats {
+pub struct UserMetrics {
     #[serde(rename = "activeUsers")]
     pub active_users: u32,
     #[serde(rename = "totalUsers")]
     pub total_users: u32,
-    pub uptime: String,
-    pub cpu: String,
-    pub ram: String,
+    // Bot connections, not included in the user counts unless configured
+    #[serde(rename = "activeBots")]
+    pub active_bots: u32,
 }
 
 #[derive(Debug, Serialize, Clone)]
@@ -48,9 +201,169 @@ pub struct LogEntry {
     pub count: u32,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub duration: Option<String>,
+    // Free-form detail, e.g. why a client was kicked
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+    #[serde(flatten)]
+    pub agent: UserAgentInfo,
+    #[serde(flatten)]
+    pub geo: GeoInfo,
     pub raw: String,
 }
 
+/// Where an IP is located, from the GeoIP database. Fields are empty when
+/// no database is configured or the IP isn't in it.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct GeoInfo {
+    // ISO 3166-1 alpha-2 code
+    pub country: String,
+    pub city: String,
+    pub asn: Option<u32>,
+    pub as_org: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeviceClass {
+    Desktop,
+    Mobile,
+    Tablet,
+    Bot,
+    Unknown,
+}
+
+impl DeviceClass {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeviceClass::Desktop => "desktop",
+            DeviceClass::Mobile => "mobile",
+            DeviceClass::Tablet => "tablet",
+            DeviceClass::Bot => "bot",
+            DeviceClass::Unknown => "unknown",
+        }
+    }
+
+    pub fn parse(value: &str) -> Self {
+        match value {
+            "desktop" => DeviceClass::Desktop,
+            "mobile" => DeviceClass::Mobile,
+            "tablet" => DeviceClass::Tablet,
+            "bot" => DeviceClass::Bot,
+            _ => DeviceClass::Unknown,
+        }
+    }
+}
+
+/// What a client's user agent string says about it. Families are "Other"
+/// and versions empty when the user agent isn't recognised.
+#[derive(Debug, Clone, Serialize)]
+pub struct UserAgentInfo {
+    pub browser: String,
+    pub browser_version: String,
+    pub os: String,
+    pub os_version: String,
+    pub device_class: DeviceClass,
+}
+
+impl UserAgentInfo {
+    /// Short label such as "Chrome 120 on Windows 10", kept in the device column.
+    pub fn summary(&self) -> String {
+        let part = |family: &str, version: &str| {
+            if version.is_empty() {
+                family.to_string()
+            } else {
+                format!("{} {}", family, version)
+            }
+        };
+        match (self.browser.as_str(), self.os.as_str()) {
+            ("Other", "Other") => "Unknown".to_string(),
+            ("Other", _) => part(&self.os, &self.os_version),
+            (_, "Other") => part(&self.browser, &self.browser_version),
+            _ => format!(
+                "{} on {}",
+                part(&self.browser, &self.browser_version),
+                part(&self.os, &self.os_version)
+            ),
+        }
+    }
+}
+
+/// One administrative action in the audit trail.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AuditEntry {
+    pub timestamp: String,
+    // Admin username, "api-token" for bearer requests
+    pub actor: String,
+    pub ip: String,
+    pub action: String,
+    // Action parameters, e.g. the rule that was added
+    pub details: serde_json::Value,
+}
+
+/// A previous event log, moved aside when the live log was cleared.
+#[derive(Debug, Serialize, Clone)]
+pub struct LogArchive {
+    pub name: String,
+    pub size_bytes: u64,
+    pub records: usize,
+    pub created_at: String,
+}
+
+/// Selects events to erase. Events matching any given field are removed.
+#[derive(Debug, Deserialize, Default)]
+pub struct LogFilter {
+    pub device_id: Option<String>,
+    pub ip: Option<String>,
+    // How `ip` is stored under the current privacy mode; matched as well
+    #[serde(skip)]
+    pub ip_alias: Option<String>,
+}
+
+impl LogFilter {
+    pub fn is_empty(&self) -> bool {
+        self.device_id.as_deref().is_none_or(str::is_empty)
+            && self.ip.as_deref().is_none_or(str::is_empty)
+    }
+
+    pub fn matches(&self, entry: &LogEntry) -> bool {
+        let is = |field: &Option<String>, value: &str| {
+            field.as_deref().is_some_and(|f| !f.is_empty() && f == value)
+        };
+        is(&self.device_id, &entry.device_id)
+            || is(&self.ip, &entry.ip)
+            || is(&self.ip_alias, &entry.ip)
+    }
+}
+
+/// An event found for a data subject, and where it is stored.
+#[derive(Debug, Serialize)]
+pub struct SubjectEvent {
+    // "live" or an archive name
+    pub source: String,
+    #[serde(flatten)]
+    pub event: LogEntry,
+}
+
+/// Outcome of checking the event log's hash chain and checkpoints.
+#[derive(Debug, Serialize)]
+pub struct IntegrityReport {
+    pub intact: bool,
+    // Chained records verified, up to the first broken one
+    pub records: u64,
+    // Records written before chaining was enabled; not covered by the chain
+    pub legacy_records: u64,
+    pub checkpoints: usize,
+    pub unsigned_checkpoints: usize,
+    pub first_broken: Option<BrokenLink>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BrokenLink {
+    // 1-based line in the log; 0 when the problem isn't tied to one record
+    pub line: usize,
+    pub reason: String,
+}
+
 #[derive(Debug, Serialize)]
 pub struct LogMetadata {
     pub total: usize,
@@ -73,7 +386,354 @@ pub struct LogStats {
     pub active_users: u32,
     pub last_activity: String,
     pub top_ips: Vec<(String, u32)>,
+    pub top_countries: Vec<(String, u32)>,
     pub requests_over_time: Vec<(String, u32)>,
+    // Connections per browser family, OS family and device class
+    pub browsers: Vec<(String, u32)>,
+    pub operating_systems: Vec<(String, u32)>,
+    pub device_classes: Vec<(String, u32)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AccessAction {
+    Allow,
+    Deny,
+}
+
+/// An IP/CIDR or device_id entry on the connection allow/deny list.
+/// Allow entries take precedence, so they can carve holes in a denied range.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AccessRule {
+    pub id: u64,
+    // Empty for device rules
+    #[serde(default)]
+    pub network: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device_id: Option<String>,
+    pub action: AccessAction,
+    pub reason: Option<String>,
+    // unix seconds
+    pub created_at: i64,
+    pub expires_at: Option<i64>,
+}
+
+impl AccessRule {
+    pub fn is_expired(&self, now: i64) -> bool {
+        self.expires_at.is_some_and(|exp| exp <= now)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewAccessRule {
+    // Either a network or a device_id must be given
+    #[serde(default)]
+    pub network: String,
+    #[serde(default)]
+    pub device_id: Option<String>,
+    pub action: AccessAction,
+    pub reason: Option<String>,
+    pub expires_in_minutes: Option<i64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AnnouncementSeverity {
+    Info,
+    Warning,
+    Critical,
+}
+
+impl AnnouncementSeverity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AnnouncementSeverity::Info => "info",
+            AnnouncementSeverity::Warning => "warning",
+            AnnouncementSeverity::Critical => "critical",
+        }
+    }
+}
+
+/// A message pushed to connected clients, optionally limited to one room.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Announcement {
+    pub id: u64,
+    pub message: String,
+    pub severity: AnnouncementSeverity,
+    // None targets every client
+    pub room: Option<String>,
+    // unix seconds
+    pub created_at: i64,
+    pub expires_at: Option<i64>,
+}
+
+impl Announcement {
+    pub fn is_expired(&self, now: i64) -> bool {
+        self.expires_at.is_some_and(|exp| exp <= now)
+    }
+
+    pub fn targets(&self, room: Option<&str>) -> bool {
+        self.room.is_none() || self.room.as_deref() == room
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewAnnouncement {
+    pub message: String,
+    pub severity: AnnouncementSeverity,
+    pub room: Option<String>,
+    pub expires_in_minutes: Option<i64>,
+}
+
+/// What an alert rule watches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertMetric {
+    // Host CPU, percent of all cores
+    CpuPercent,
+    ActiveUsers,
+    // Connections opened from a single IP in the last minute; each IP alerts on its own
+    ConnectionsPerIp,
+    // Consecutive failed WakaTime fetches
+    WakatimeFailures,
+}
+
+impl AlertMetric {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AlertMetric::CpuPercent => "cpu_percent",
+            AlertMetric::ActiveUsers => "active_users",
+            AlertMetric::ConnectionsPerIp => "connections_per_ip",
+            AlertMetric::WakatimeFailures => "wakatime_failures",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlertComparison {
+    Above,
+    Below,
+}
+
+impl AlertComparison {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AlertComparison::Above => "above",
+            AlertComparison::Below => "below",
+        }
+    }
+
+    pub fn breached(&self, value: f64, threshold: f64) -> bool {
+        match self {
+            AlertComparison::Above => value > threshold,
+            AlertComparison::Below => value < threshold,
+        }
+    }
+}
+
+/// Fires when `metric` stays above/below `threshold` for `for_secs`.
+/// Notifications go to `webhook_url` and to every `ALERT_WEBHOOK_URLS` entry.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AlertRule {
+    pub id: u64,
+    pub name: String,
+    pub metric: AlertMetric,
+    pub comparison: AlertComparison,
+    pub threshold: f64,
+    #[serde(default)]
+    pub for_secs: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub webhook_url: Option<String>,
+    // unix seconds
+    pub created_at: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewAlertRule {
+    pub name: String,
+    pub metric: AlertMetric,
+    pub comparison: AlertComparison,
+    pub threshold: f64,
+    #[serde(default)]
+    pub for_secs: u64,
+    pub webhook_url: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlertStatus {
+    Firing,
+    Resolved,
+}
+
+/// One firing (or since resolved) instance of a rule. This is also the
+/// webhook payload; `fingerprint` stays the same from firing to resolved.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Alert {
+    pub fingerprint: String,
+    pub rule_id: u64,
+    pub rule_name: String,
+    pub metric: AlertMetric,
+    // The IP for per-IP metrics
+    pub subject: Option<String>,
+    pub status: AlertStatus,
+    // Latest evaluated value
+    pub value: f64,
+    pub threshold: f64,
+    // unix seconds
+    pub started_at: i64,
+    pub resolved_at: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AlertsResponse {
+    pub firing: Vec<Alert>,
+    // Most recent first
+    pub resolved: Vec<Alert>,
+}
+
+/// Device events that can be pushed to webhook subscribers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WebhookEvent {
+    #[serde(rename = "device.connected")]
+    Connected,
+    // Also sent when an admin kicks the device
+    #[serde(rename = "device.disconnected")]
+    Disconnected,
+}
+
+impl WebhookEvent {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WebhookEvent::Connected => "device.connected",
+            WebhookEvent::Disconnected => "device.disconnected",
+        }
+    }
+}
+
+/// A downstream endpoint that is POSTed the events it subscribed to. Each
+/// payload is signed with `secret`, which is only returned when the
+/// subscription is created.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookSubscription {
+    pub id: u64,
+    pub url: String,
+    pub events: Vec<WebhookEvent>,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub secret: String,
+    // unix seconds
+    pub created_at: i64,
+}
+
+impl WebhookSubscription {
+    pub fn wants(&self, event: WebhookEvent) -> bool {
+        self.events.contains(&event)
+    }
+
+    pub fn without_secret(self) -> Self {
+        Self {
+            secret: String::new(),
+            ..self
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewWebhookSubscription {
+    pub url: String,
+    pub events: Vec<WebhookEvent>,
+    // Generated when not given
+    pub secret: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeliveryStatus {
+    // Waiting for its first attempt or a retry
+    Pending,
+    Delivered,
+    // Out of retries, or the subscription was removed
+    Failed,
+}
+
+impl DeliveryStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeliveryStatus::Pending => "pending",
+            DeliveryStatus::Delivered => "delivered",
+            DeliveryStatus::Failed => "failed",
+        }
+    }
+}
+
+/// One event queued for one subscriber, kept after completion as delivery history.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookDelivery {
+    pub id: u64,
+    pub subscription_id: u64,
+    pub url: String,
+    pub event: WebhookEvent,
+    // The exact JSON body, so every attempt sends and signs the same bytes
+    pub body: String,
+    pub status: DeliveryStatus,
+    pub attempts: u32,
+    // unix seconds
+    pub created_at: i64,
+    pub next_attempt_at: i64,
+    pub last_attempt_at: Option<i64>,
+    pub response_status: Option<u16>,
+    pub last_error: Option<String>,
+}
+
+/// A device registered as a WireGuard peer on the mesh overlay, keyed by the
+/// same `device_id` it connects with.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VpnPeer {
+    pub id: u64,
+    pub device_id: String,
+    pub name: String,
+    pub owner: String,
+    // Base64, as printed by `wg pubkey`
+    pub public_key: String,
+    // The peer's own address on the overlay network
+    pub overlay_ip: IpAddr,
+    // Networks routed to the peer (WireGuard AllowedIPs)
+    pub allowed_ips: Vec<String>,
+    pub tags: Vec<String>,
+    // unix seconds
+    pub created_at: i64,
+    // Last time the device was connected
+    pub last_seen: Option<i64>,
+    // When its latest connection was opened
+    pub last_handshake: Option<i64>,
+}
+
+/// Editable fields of a peer, for both registering and updating one.
+#[derive(Debug, Deserialize)]
+pub struct NewVpnPeer {
+    pub device_id: String,
+    // Defaults to the device_id
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub owner: String,
+    pub public_key: String,
+    // Next free address of the overlay network when not given
+    pub overlay_ip: Option<IpAddr>,
+    // Defaults to the overlay address alone
+    #[serde(default)]
+    pub allowed_ips: Vec<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+/// A peer with its live presence.
+#[derive(Debug, Clone, Serialize)]
+pub struct VpnPeerStatus {
+    #[serde(flatten)]
+    pub peer: VpnPeer,
+    pub online: bool,
 }
 
 #[derive(Debug, Deserialize)]
@@ -84,12 +744,35 @@ pub struct LogQuery {
     pub page_size: usize,
     pub q: Option<String>,
     pub exclude_ip: Option<String>,
+    // Exact, case-insensitive matches on the parsed user agent
+    pub browser: Option<String>,
+    pub os: Option<String>,
+    pub device_class: Option<String>,
+    // ISO country code, case-insensitive
+    pub country: Option<String>,
     #[serde(default = "default_sort_by")]
     pub sort_by: String,
     #[serde(default = "default_order")]
     pub order: String,
 }
 
+impl Default for LogQuery {
+    fn default() -> Self {
+        Self {
+            page: default_page(),
+            page_size: default_page_size(),
+            q: None,
+            exclude_ip: None,
+            browser: None,
+            os: None,
+            device_class: None,
+            country: None,
+            sort_by: default_sort_by(),
+            order: default_order(),
+        }
+    }
+}
+
 fn default_page() -> usize {
     1
 }
@@ -1,9 +1,167 @@
-use crate::domain::{LogEntry, LogMetadata, LogQuery, LogStats};
+use crate::domain::{
+    AccessAction, AccessRule, AlertRule, Announcement, AuditEntry, HistoryResolution,
+    IntegrityReport, LogArchive, LogEntry, LogFilter, LogMetadata, LogQuery, LogStats,
+    MetricsSample, NewAccessRule, NewAlertRule, NewAnnouncement, NewWebhookSubscription,
+    NewVpnPeer, SubjectEvent, VpnPeer, WebhookDelivery, WebhookEvent, WebhookSubscription,
+};
 use std::error::Error;
+use std::net::IpAddr;
 
 pub trait LogRepository: Send + Sync {
     fn append(&self, entry: &LogEntry) -> Result<(), Box<dyn Error + Send + Sync>>;
     fn find_all(&self, query: &LogQuery) -> (Vec<LogEntry>, LogMetadata, LogStats);
-    fn clear(&self) -> Result<(), Box<dyn Error + Send + Sync>>;
+    /// Moves the live log into a timestamped archive and starts an empty one.
+    /// Returns None when there was nothing to archive.
+    fn archive(&self) -> Result<Option<LogArchive>, Box<dyn Error + Send + Sync>>;
+    /// Newest first
+    fn list_archives(&self) -> Vec<LogArchive>;
+    fn read_archive(&self, name: &str) -> Result<String, Box<dyn Error + Send + Sync>>;
+    /// Appends an archive's records back onto the live log and removes the archive.
+    /// Returns the number of records restored.
+    fn restore_archive(&self, name: &str) -> Result<usize, Box<dyn Error + Send + Sync>>;
+    /// Permanently deletes an archive. Returns false if it doesn't exist.
+    fn purge_archive(&self, name: &str) -> Result<bool, Box<dyn Error + Send + Sync>>;
+    /// Every matching event in the live log and the archives.
+    fn find_matching(&self, filter: &LogFilter) -> Vec<SubjectEvent>;
+    /// Erases matching events from the live log and every archive.
+    /// Returns the number of events removed.
+    fn delete_matching(&self, filter: &LogFilter) -> Result<usize, Box<dyn Error + Send + Sync>>;
     fn get_raw_content(&self) -> Result<String, Box<dyn Error + Send + Sync>>;
+    /// Records the current head of the log so later truncation can be detected.
+    fn checkpoint(&self) -> Result<(), Box<dyn Error + Send + Sync>>;
+    fn verify_integrity(&self) -> IntegrityReport;
+}
+
+pub trait AccessListRepository: Send + Sync {
+    /// Active (non-expired) rules
+    fn list(&self) -> Vec<AccessRule>;
+    fn add(&self, rule: NewAccessRule) -> Result<AccessRule, Box<dyn Error + Send + Sync>>;
+    fn remove(&self, id: u64) -> Result<bool, Box<dyn Error + Send + Sync>>;
+
+    /// Returns the deny rule blocking `ip`, unless an allow rule covers it.
+    fn find_block(&self, ip: IpAddr) -> Option<AccessRule> {
+        let matching: Vec<AccessRule> = self
+            .list()
+            .into_iter()
+            .filter(|rule| {
+                crate::utils::parse_network(&rule.network)
+                    .is_some_and(|net| net.contains(&ip))
+            })
+            .collect();
+
+        if matching.iter().any(|r| r.action == AccessAction::Allow) {
+            return None;
+        }
+        matching.into_iter().find(|r| r.action == AccessAction::Deny)
+    }
+
+    /// Returns the deny rule banning `device_id`, if any.
+    fn find_device_block(&self, device_id: &str) -> Option<AccessRule> {
+        self.list().into_iter().find(|r| {
+            r.action == AccessAction::Deny && r.device_id.as_deref() == Some(device_id)
+        })
+    }
+}
+
+pub trait AnnouncementRepository: Send + Sync {
+    /// Every announcement ever sent, newest first
+    fn history(&self) -> Vec<Announcement>;
+    fn add(&self, announcement: NewAnnouncement)
+    -> Result<Announcement, Box<dyn Error + Send + Sync>>;
+    /// Expires an announcement now. Returns the updated entry, or None if unknown.
+    fn end(&self, id: u64) -> Result<Option<Announcement>, Box<dyn Error + Send + Sync>>;
+
+    /// Announcements still in force
+    fn active(&self) -> Vec<Announcement> {
+        let now = chrono::Utc::now().timestamp();
+        self.history()
+            .into_iter()
+            .filter(|a| !a.is_expired(now))
+            .collect()
+    }
+}
+
+/// Append-only: there is deliberately no way to clear or edit entries.
+pub trait AuditRepository: Send + Sync {
+    fn record(&self, entry: &AuditEntry) -> Result<(), Box<dyn Error + Send + Sync>>;
+    /// Newest first, at most `limit` entries
+    fn recent(&self, limit: usize) -> Vec<AuditEntry>;
+    fn get_raw_content(&self) -> Result<String, Box<dyn Error + Send + Sync>>;
+}
+
+pub trait MetricsHistoryRepository: Send + Sync {
+    /// Adds a raw sample. Finished minutes and hours are rolled up into the
+    /// coarser resolutions as samples arrive.
+    fn record(&self, sample: MetricsSample) -> Result<(), Box<dyn Error + Send + Sync>>;
+    /// Samples at `resolution` taken at or after `since` (unix seconds), oldest first.
+    fn since(&self, resolution: HistoryResolution, since: i64) -> Vec<MetricsSample>;
+    /// Writes everything recorded so far, including the minute still open.
+    fn flush(&self) -> Result<(), Box<dyn Error + Send + Sync>>;
+}
+
+pub trait AlertRuleRepository: Send + Sync {
+    fn list(&self) -> Vec<AlertRule>;
+    fn add(&self, rule: NewAlertRule) -> Result<AlertRule, Box<dyn Error + Send + Sync>>;
+    fn remove(&self, id: u64) -> Result<bool, Box<dyn Error + Send + Sync>>;
+}
+
+pub trait WebhookSubscriptionRepository: Send + Sync {
+    fn list(&self) -> Vec<WebhookSubscription>;
+    fn add(
+        &self,
+        subscription: NewWebhookSubscription,
+    ) -> Result<WebhookSubscription, Box<dyn Error + Send + Sync>>;
+    fn remove(&self, id: u64) -> Result<bool, Box<dyn Error + Send + Sync>>;
+
+    fn get(&self, id: u64) -> Option<WebhookSubscription> {
+        self.list().into_iter().find(|s| s.id == id)
+    }
+}
+
+/// The delivery queue. Pending deliveries survive restarts; finished ones are
+/// kept as history up to a limit.
+pub trait WebhookDeliveryRepository: Send + Sync {
+    /// Queues `body` once for each subscription, due immediately. A
+    /// subscription whose queue is already full is skipped; returns how many were.
+    fn enqueue(
+        &self,
+        event: WebhookEvent,
+        body: &str,
+        subscriptions: &[WebhookSubscription],
+    ) -> Result<usize, Box<dyn Error + Send + Sync>>;
+    /// Pending deliveries due at `now`. They are pushed back by `lease_secs` so
+    /// that a delivery whose attempt never reports back is retried later.
+    fn claim_due(
+        &self,
+        now: i64,
+        lease_secs: i64,
+    ) -> Result<Vec<WebhookDelivery>, Box<dyn Error + Send + Sync>>;
+    /// Stores the outcome of an attempt.
+    fn update(&self, delivery: &WebhookDelivery) -> Result<(), Box<dyn Error + Send + Sync>>;
+    /// Newest first, at most `limit` entries
+    fn recent(&self, limit: usize) -> Vec<WebhookDelivery>;
+}
+
+pub trait VpnPeerRepository: Send + Sync {
+    fn list(&self) -> Vec<VpnPeer>;
+    fn add(&self, peer: NewVpnPeer) -> Result<VpnPeer, Box<dyn Error + Send + Sync>>;
+    /// Replaces the editable fields. Returns None if `id` is unknown.
+    fn update(
+        &self,
+        id: u64,
+        peer: NewVpnPeer,
+    ) -> Result<Option<VpnPeer>, Box<dyn Error + Send + Sync>>;
+    fn remove(&self, id: u64) -> Result<bool, Box<dyn Error + Send + Sync>>;
+    /// Presence of the peer registered as `device_id`, if there is one: a new
+    /// connection at `at` (unix seconds) sets the handshake as well.
+    fn record_presence(
+        &self,
+        device_id: &str,
+        at: i64,
+        handshake: bool,
+    ) -> Result<(), Box<dyn Error + Send + Sync>>;
+
+    fn get(&self, id: u64) -> Option<VpnPeer> {
+        self.list().into_iter().find(|p| p.id == id)
+    }
 }
@@ -1,36 +1,77 @@
+use crate::domain::{DeviceClass, LogEntry};
 use crate::domain::logger::EventLogger;
+use crate::domain::repositories::LogRepository;
+use crate::services::bot_detection::BotDetector;
+use crate::services::geoip::GeoIp;
+use crate::services::metrics::Metrics;
+use crate::services::privacy::IpPrivacy;
+use crate::services::user_agent;
 use chrono::Local;
-use std::fs::OpenOptions;
-use std::io::Write;
-use std::sync::Mutex;
+use std::sync::Arc;
 
+/// Writes connection events through the log repository, which owns the file
+/// and keeps its hash chain intact.
 pub struct FileLogger {
-    file: Mutex<std::fs::File>,
+    repository: Arc<dyn LogRepository>,
+    // Applied to every IP before it reaches disk
+    ip_privacy: IpPrivacy,
+    // Also flags bots the user agent alone doesn't give away
+    bots: Arc<BotDetector>,
+    // Looked up from the real IP, so it works with any privacy mode
+    geoip: GeoIp,
+    metrics: Arc<Metrics>,
 }
 
 impl FileLogger {
-    pub fn new(path: &str) -> Self {
-        let file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(path)
-            .expect("Unable to open log file");
-
+    pub fn new(
+        repository: Arc<dyn LogRepository>,
+        ip_privacy: IpPrivacy,
+        bots: Arc<BotDetector>,
+        geoip: GeoIp,
+        metrics: Arc<Metrics>,
+    ) -> Self {
         Self {
-            file: Mutex::new(file),
+            repository,
+            ip_privacy,
+            bots,
+            geoip,
+            metrics,
         }
     }
 
-    fn shorten_device(device: &str) -> String {
-        // Simple heuristic: try to grab content inside the first parentheses (OS info usually),
-        // effectively shortening the long standard Mozilla/5.0 prefix and engine suffix.
-        if let Some(start) = device.find('(') {
-            if let Some(end) = device[start..].find(')') {
-                return device[start + 1..start + end].to_string();
-            }
+    #[allow(clippy::too_many_arguments)]
+    fn write_line(
+        &self,
+        ip: &str,
+        device: &str,
+        device_id: &str,
+        action: &str,
+        count: u32,
+        duration: Option<String>,
+        note: Option<&str>,
+    ) {
+        let mut agent = user_agent::parse(device);
+        if self.bots.is_bot(ip, device) {
+            agent.device_class = DeviceClass::Bot;
+        }
+        let entry = LogEntry {
+            timestamp: Local::now().format("%Y-%m-%d %H:%M:%S %z").to_string(),
+            ip: self.ip_privacy.apply(ip),
+            device: agent.summary(),
+            device_id: device_id.to_string(),
+            action: action.to_string(),
+            count,
+            duration,
+            note: note.map(str::to_string),
+            agent,
+            geo: self.geoip.lookup(ip),
+            raw: String::new(),
+        };
+
+        if let Err(e) = self.repository.append(&entry) {
+            self.metrics.log_write_failed();
+            tracing::error!(action, error = %e, "Failed to write to log");
         }
-        // Fallback: truncate to 30 chars
-        device.chars().take(30).collect()
     }
 }
 
@@ -44,20 +85,19 @@ impl EventLogger for FileLogger {
         count: u32,
         duration: Option<String>,
     ) {
-        if let Ok(mut file) = self.file.lock() {
-            let timestamp = Local::now().format("%Y-%m-%d %H:%M:%S %z");
-            let short_device = Self::shorten_device(device);
-            // Sanitize commas in device string
-            let sanitized_device = short_device.replace(",", " ");
-            let duration_str = duration.unwrap_or_default();
+        self.write_line(ip, device, device_id, action, count, duration, None);
+    }
 
-            if let Err(e) = writeln!(
-                file,
-                "{},{},{},{},{},{},{}",
-                timestamp, ip, sanitized_device, device_id, action, count, duration_str
-            ) {
-                eprintln!("Failed to write to log: {}", e);
-            }
-        }
+    fn log_with_note(
+        &self,
+        ip: &str,
+        device: &str,
+        device_id: &str,
+        action: &str,
+        count: u32,
+        duration: Option<String>,
+        note: &str,
+    ) {
+        self.write_line(ip, device, device_id, action, count, duration, Some(note));
     }
 }
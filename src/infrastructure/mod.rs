@@ -1 +1,2 @@
 pub mod file_logger;
+pub mod telemetry;
@@ -0,0 +1,87 @@
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_otlp::SpanExporter;
+use opentelemetry_sdk::Resource;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use std::env;
+use std::io::IsTerminal;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer};
+
+/// Keeps the OTLP exporter alive; call `shutdown` before exiting so buffered
+/// spans are flushed.
+pub struct Telemetry {
+    provider: Option<SdkTracerProvider>,
+}
+
+impl Telemetry {
+    pub fn shutdown(self) {
+        if let Some(provider) = self.provider
+            && let Err(e) = provider.shutdown()
+        {
+            tracing::error!(error = %e, "Failed to flush OTLP spans");
+        }
+    }
+}
+
+/// Installs the global `tracing` subscriber:
+/// - `RUST_LOG` filters events and spans (default `info`)
+/// - `LOG_FORMAT=json` switches console output to one JSON object per line
+/// - `OTEL_EXPORTER_OTLP_ENDPOINT` (or `OTEL_EXPORTER_OTLP_TRACES_ENDPOINT`)
+///   also exports spans over OTLP/HTTP, named by `OTEL_SERVICE_NAME`
+pub fn init() -> Telemetry {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+
+    let console = match env::var("LOG_FORMAT").as_deref() {
+        Ok("json") => tracing_subscriber::fmt::layer()
+            .json()
+            .with_current_span(true)
+            .boxed(),
+        _ => tracing_subscriber::fmt::layer()
+            .with_ansi(std::io::stdout().is_terminal())
+            .boxed(),
+    };
+
+    let otlp_configured = ["OTEL_EXPORTER_OTLP_ENDPOINT", "OTEL_EXPORTER_OTLP_TRACES_ENDPOINT"]
+        .iter()
+        .any(|var| env::var(var).is_ok_and(|v| !v.is_empty()));
+    // Reported once the subscriber is up, so the message isn't lost
+    let mut otlp_error = None;
+    let provider = if otlp_configured {
+        match SpanExporter::builder().with_http().build() {
+            Ok(exporter) => {
+                let service =
+                    env::var("OTEL_SERVICE_NAME").unwrap_or_else(|_| "counter".to_string());
+                Some(
+                    SdkTracerProvider::builder()
+                        .with_batch_exporter(exporter)
+                        .with_resource(Resource::builder().with_service_name(service).build())
+                        .build(),
+                )
+            }
+            Err(e) => {
+                otlp_error = Some(e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+    let otlp = provider
+        .as_ref()
+        .map(|p| tracing_opentelemetry::layer().with_tracer(p.tracer("counter")));
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(console)
+        .with(otlp)
+        .init();
+
+    match (&provider, otlp_error) {
+        (Some(_), _) => tracing::info!("Exporting spans over OTLP"),
+        (None, Some(e)) => tracing::error!(error = %e, "Failed to set up the OTLP exporter"),
+        (None, None) => {}
+    }
+
+    Telemetry { provider }
+}
@@ -1,7 +1,12 @@
-use axum::{Router, routing::get};
+use axum::{
+    Router,
+    routing::{delete, get, post},
+};
 use std::net::SocketAddr;
 use std::sync::Arc;
 use tower_http::cors::CorsLayer;
+use tower_http::trace::TraceLayer;
+use tracing::Instrument;
 
 mod api;
 mod domain;
@@ -12,29 +17,139 @@ mod state;
 mod utils;
 
 use infrastructure::file_logger::FileLogger;
+use repositories::access_list_repository::FileAccessListRepository;
+use repositories::alert_rule_repository::FileAlertRuleRepository;
+use repositories::webhook_delivery_repository::FileWebhookDeliveryRepository;
+use repositories::webhook_subscription_repository::FileWebhookSubscriptionRepository;
+use repositories::announcement_repository::FileAnnouncementRepository;
+use repositories::audit_repository::FileAuditRepository;
 use repositories::log_repository::FileLogRepository;
+use repositories::metrics_history_repository::FileMetricsHistoryRepository;
+use repositories::vpn_peer_repository::FileVpnPeerRepository;
+use services::bot_detection::BotDetector;
+use services::geoip::GeoIp;
+use services::metrics::Metrics;
+use services::privacy::IpPrivacy;
+use services::system_sampler::SystemSampler;
 use services::wakatime::{WakatimeData, WakatimeService};
+use domain::repositories::LogRepository;
 use state::AppState;
 
 pub mod admin {
     pub use crate::api::admin::*;
 }
 
+// Stored state that fails to load stops startup, rather than being replaced
+fn or_exit<T>(result: Result<T, Box<dyn std::error::Error + Send + Sync>>) -> T {
+    result.unwrap_or_else(|e| {
+        tracing::error!(error = %e, "Failed to load stored state, refusing to start");
+        std::process::exit(1);
+    })
+}
+
 #[tokio::main]
 async fn main() {
     dotenvy::dotenv().ok();
-    let logger = Arc::new(FileLogger::new("server.log"));
+    let telemetry = infrastructure::telemetry::init();
     let log_repo = Arc::new(FileLogRepository::new("server.log"));
-    let app_state = AppState::new(logger, log_repo);
 
-    // Spawn background task to broadcast system stats
+    // `counter verify-logs` checks the event log's hash chain and exits
+    if std::env::args().nth(1).as_deref() == Some("verify-logs") {
+        let report = log_repo.verify_integrity();
+        println!("{}", serde_json::to_string_pretty(&report).unwrap());
+        std::process::exit(if report.intact { 0 } else { 1 });
+    }
+
+    let ip_privacy = IpPrivacy::from_env();
+    let bots = Arc::new(BotDetector::from_env());
+    let metrics = Arc::new(Metrics::new());
+    let logger = Arc::new(FileLogger::new(
+        log_repo.clone(),
+        ip_privacy.clone(),
+        bots.clone(),
+        GeoIp::from_env(),
+        metrics.clone(),
+    ));
+    let access_list = Arc::new(or_exit(FileAccessListRepository::new("access_list.json")));
+    let announcements = Arc::new(or_exit(FileAnnouncementRepository::new("announcements.json")));
+    let audit_log = Arc::new(FileAuditRepository::new("audit.log"));
+    let metrics_history = Arc::new(or_exit(FileMetricsHistoryRepository::new("metrics_history.json")));
+    let alert_rules = Arc::new(or_exit(FileAlertRuleRepository::new("alert_rules.json")));
+    let webhook_subscriptions = Arc::new(or_exit(FileWebhookSubscriptionRepository::new("webhooks.json")));
+    let webhook_deliveries = Arc::new(or_exit(FileWebhookDeliveryRepository::new("webhook_deliveries.json")));
+    let vpn_network = std::env::var("VPN_OVERLAY_NETWORK")
+        .ok()
+        .and_then(|v| {
+            utils::parse_network(&v).or_else(|| {
+                tracing::warn!(network = v, "Ignoring invalid VPN_OVERLAY_NETWORK");
+                None
+            })
+        })
+        .unwrap_or_else(|| "10.100.0.0/24".parse().unwrap());
+    let vpn_peers = Arc::new(or_exit(FileVpnPeerRepository::new("vpn_peers.json", vpn_network)));
+    // The only place sysinfo is refreshed; everything else reads its snapshots
+    let system = SystemSampler::new().spawn(std::time::Duration::from_secs(2));
+    let app_state = AppState::new(
+        system,
+        logger,
+        log_repo,
+        access_list,
+        announcements,
+        audit_log,
+        metrics_history,
+        alert_rules,
+        webhook_subscriptions,
+        webhook_deliveries,
+        vpn_peers,
+        ip_privacy,
+        bots,
+        metrics,
+    );
+
+    // Record history and check alert rules on every new system snapshot
     let app_state_for_task = app_state.clone();
     tokio::spawn(async move {
-        let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(2));
+        let mut rx = app_state_for_task.system.clone();
+        while rx.changed().await.is_ok() {
+            let stats = rx.borrow_and_update().clone();
+            let sample = app_state_for_task.history_sample(&stats);
+            if let Err(e) = app_state_for_task.metrics_history.record(sample) {
+                tracing::error!(error = %e, "Failed to save metrics history");
+            }
+            app_state_for_task.evaluate_alerts(&stats);
+        }
+    });
+
+    // Flushed on shutdown, after the router has taken the state
+    let metrics_history = app_state.metrics_history.clone();
+
+    // Spawn background task to send queued webhooks
+    tokio::spawn(app_state.webhooks.clone().run());
+
+    // Spawn background task to expire heartbeat presences and announcements
+    let app_state_for_task = app_state.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(1));
         loop {
             interval.tick().await;
-            let stats = app_state_for_task.get_system_metrics();
-            let _ = app_state_for_task.system_tx.send(stats);
+            app_state_for_task.expire_heartbeats();
+            app_state_for_task.expire_announcements();
+        }
+    });
+
+    // Spawn background task to checkpoint the event log
+    let log_repo_for_task = app_state.log_repository.clone();
+    tokio::spawn(async move {
+        let secs = std::env::var("LOG_CHECKPOINT_INTERVAL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(300);
+        let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(secs));
+        loop {
+            interval.tick().await;
+            if let Err(e) = log_repo_for_task.checkpoint() {
+                tracing::error!(error = %e, "Failed to write log checkpoint");
+            }
         }
     });
 
@@ -42,11 +157,20 @@ async fn main() {
     let app_state_waka = app_state.clone();
     tokio::spawn(async move {
         let waka_service = WakatimeService::new();
+        let metrics = app_state_waka.metrics.clone();
+        let fetch = || {
+            async {
+                let all_time = waka_service.fetch_all_time_stats().await.ok();
+                metrics.wakatime_fetch("all_time", all_time.is_some());
+                let summaries = waka_service.fetch_summaries().await.ok();
+                metrics.wakatime_fetch("summaries", summaries.is_some());
+                (all_time, summaries)
+            }
+            .instrument(tracing::info_span!("wakatime.refresh"))
+        };
+
         // Fetch immediately on startup
-        println!("Fetching initial WakaTime stats...");
-        
-        let all_time = waka_service.fetch_all_time_stats().await.ok();
-        let summaries = waka_service.fetch_summaries().await.ok();
+        let (all_time, summaries) = fetch().await;
 
         if all_time.is_some() || summaries.is_some() {
              let mut data = app_state_waka.wakatime_data.write().unwrap();
@@ -54,17 +178,16 @@ async fn main() {
                  all_time,
                  summaries,
              });
-             println!("Initial WakaTime stats fetched.");
+             tracing::info!("Initial WakaTime stats fetched");
         } else {
-             eprintln!("Failed to fetch initial WakaTime stats");
+             tracing::warn!("Failed to fetch initial WakaTime stats");
         }
 
         let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(3600)); // Every hour
         loop {
             interval.tick().await;
             
-            let all_time = waka_service.fetch_all_time_stats().await.ok();
-            let summaries = waka_service.fetch_summaries().await.ok();
+            let (all_time, summaries) = fetch().await;
 
             if all_time.is_some() || summaries.is_some() {
                 let mut data = app_state_waka.wakatime_data.write().unwrap();
@@ -82,42 +205,200 @@ async fn main() {
                     });
                 }
             } else {
-                 eprintln!("Failed to fetch WakaTime stats");
+                 tracing::warn!("Failed to fetch WakaTime stats");
             }
         }
     });
 
+    if std::env::var("METRICS_TOKEN").unwrap_or_default().is_empty() {
+        tracing::warn!("METRICS_TOKEN not set. /metrics is open to anyone who can reach the server.");
+    }
+
     let app = Router::new()
         .route("/health", get(api::health::health_check))
         .route(
             "/login",
             get(api::auth::login_page).post(api::auth::login_submit),
         )
-        .route("/logout", get(api::auth::logout))
+        .route("/logout", post(api::auth::logout))
         .route("/client/ws", get(api::websocket::client_ws_handler))
-        .route("/admin/ws", get(api::websocket::admin_ws_handler))
+        .route("/client/events", get(api::events::client_events_handler))
+        .route("/client/token", get(api::client::issue_token))
         .merge(
             Router::new()
                 .route("/admin", get(api::htmx::dashboard_handler))
                 .route("/htmx/overview", get(api::htmx::overview_tab_handler))
                 .route("/htmx/logs-tab", get(api::htmx::logs_tab_handler))
                 .route("/htmx/logs", get(api::htmx::logs_handler))
+                .route("/htmx/logs/archive", post(api::htmx::logs_archive_handler))
+                .route(
+                    "/htmx/archives",
+                    get(api::htmx::archives_tab_handler).post(api::htmx::archives_create_handler),
+                )
+                .route("/htmx/privacy", get(api::htmx::privacy_tab_handler))
+                .route("/htmx/privacy/erase", post(api::htmx::privacy_erase_handler))
+                .route(
+                    "/htmx/archives/{name}",
+                    delete(api::htmx::archives_purge_handler),
+                )
+                .route(
+                    "/htmx/archives/{name}/restore",
+                    post(api::htmx::archives_restore_handler),
+                )
                 .route(
                     "/htmx/active-users",
                     get(api::htmx::active_users_tab_handler),
                 )
+                .route(
+                    "/htmx/active-users/{device_id}/kick",
+                    post(api::htmx::kick_user_handler),
+                )
+                .route(
+                    "/htmx/active-users/{device_id}/message",
+                    post(api::htmx::message_user_handler),
+                )
+                .route(
+                    "/htmx/active-users/{device_id}/ban",
+                    post(api::htmx::ban_user_handler),
+                )
                 .route(
                     "/api/logs",
                     get(api::admin::get_logs).delete(api::admin::clear_logs),
                 )
+                .route(
+                    "/htmx/access-rules",
+                    get(api::htmx::access_rules_tab_handler)
+                        .post(api::htmx::access_rules_create_handler),
+                )
+                .route(
+                    "/htmx/access-rules/{id}",
+                    delete(api::htmx::access_rules_delete_handler),
+                )
+                .route(
+                    "/api/access-rules",
+                    get(api::admin::list_access_rules).post(api::admin::create_access_rule),
+                )
+                .route(
+                    "/api/access-rules/{id}",
+                    delete(api::admin::delete_access_rule),
+                )
+                .route(
+                    "/htmx/alerts",
+                    get(api::htmx::alerts_tab_handler).post(api::htmx::alert_rules_create_handler),
+                )
+                .route(
+                    "/htmx/alerts/rules/{id}",
+                    delete(api::htmx::alert_rules_delete_handler),
+                )
+                .route("/api/alerts", get(api::admin::get_alerts))
+                .route(
+                    "/api/alerts/rules",
+                    get(api::admin::list_alert_rules).post(api::admin::create_alert_rule),
+                )
+                .route(
+                    "/api/alerts/rules/{id}",
+                    delete(api::admin::delete_alert_rule),
+                )
+                .route(
+                    "/htmx/webhooks",
+                    get(api::htmx::webhooks_tab_handler).post(api::htmx::webhooks_create_handler),
+                )
+                .route(
+                    "/htmx/webhooks/{id}",
+                    delete(api::htmx::webhooks_delete_handler),
+                )
+                .route(
+                    "/api/webhooks",
+                    get(api::admin::list_webhooks).post(api::admin::create_webhook),
+                )
+                .route("/api/webhooks/{id}", delete(api::admin::delete_webhook))
+                .route(
+                    "/api/webhooks/deliveries",
+                    get(api::admin::list_webhook_deliveries),
+                )
+                .route(
+                    "/htmx/vpn-peers",
+                    get(api::htmx::vpn_peers_tab_handler).post(api::htmx::vpn_peers_create_handler),
+                )
+                .route(
+                    "/htmx/vpn-peers/{id}",
+                    delete(api::htmx::vpn_peers_delete_handler),
+                )
+                .route(
+                    "/api/vpn/peers",
+                    get(api::admin::list_vpn_peers).post(api::admin::create_vpn_peer),
+                )
+                .route(
+                    "/api/vpn/peers/{id}",
+                    get(api::admin::get_vpn_peer)
+                        .put(api::admin::update_vpn_peer)
+                        .delete(api::admin::delete_vpn_peer),
+                )
+                .route(
+                    "/htmx/announcements",
+                    get(api::htmx::announcements_tab_handler)
+                        .post(api::htmx::announcements_create_handler),
+                )
+                .route(
+                    "/htmx/announcements/{id}",
+                    delete(api::htmx::announcements_end_handler),
+                )
+                .route(
+                    "/api/announcements",
+                    get(api::admin::list_announcements).post(api::admin::create_announcement),
+                )
+                .route(
+                    "/api/announcements/{id}",
+                    delete(api::admin::end_announcement),
+                )
+                .route("/htmx/audit", get(api::htmx::audit_tab_handler))
+                .route("/api/audit", get(api::admin::get_audit_log))
+                .route("/api/audit/export", get(api::admin::download_audit_log))
                 .route("/api/export", get(api::admin::download_logs))
+                .route("/api/logs/verify", get(api::admin::verify_logs))
+                .route("/api/logs/erase", post(api::admin::erase_log_events))
+                .route("/api/subjects", get(api::admin::find_subject_events))
+                .route("/api/subjects/export", get(api::admin::export_subject_events))
+                .route("/api/logs/archives", get(api::admin::list_log_archives))
+                .route(
+                    "/api/logs/archives/{name}",
+                    get(api::admin::download_log_archive).delete(api::admin::purge_log_archive),
+                )
+                .route(
+                    "/api/logs/archives/{name}/restore",
+                    post(api::admin::restore_log_archive),
+                )
                 .route("/api/status", get(api::admin::get_system_status))
+                .route("/api/metrics/history", get(api::admin::get_metrics_history))
                 .route_layer(axum::middleware::from_fn_with_state(
                     app_state.clone(),
                     api::middleware::auth,
                 )),
         )
-        .route("/api/wakatime", get(api::wakatime::get_wakatime_stats))
+        .merge(
+            Router::new()
+                .route("/admin/ws", get(api::websocket::admin_ws_handler))
+                .route("/admin/events", get(api::events::admin_events_handler))
+                .route("/api/wakatime", get(api::wakatime::get_wakatime_stats))
+                .route_layer(axum::middleware::from_fn_with_state(
+                    app_state.clone(),
+                    api::middleware::admin_api,
+                )),
+        )
+        .merge(
+            Router::new()
+                .route("/metrics", get(api::metrics::prometheus_metrics))
+                .route_layer(axum::middleware::from_fn_with_state(
+                    app_state.clone(),
+                    api::middleware::metrics_auth,
+                )),
+        )
+        .layer(axum::middleware::from_fn_with_state(
+            app_state.clone(),
+            api::middleware::csrf,
+        ))
+        // Outside the CSRF check: clients post here cross-origin without cookies
+        .route("/client/heartbeat", post(api::client::heartbeat))
         .with_state(app_state)
         .layer({
             // Read allowed origins from env
@@ -129,8 +410,21 @@ async fn main() {
                 .collect::<Vec<String>>();
 
             if allowed_origins.is_empty() {
-                println!("WARNING: ALLOWED_ORIGINS not set. Defaulting to permissive CORS.");
-                CorsLayer::permissive()
+                use axum::http::Method;
+                use axum::http::header;
+                use tower_http::cors::AllowOrigin;
+
+                // Client widgets are embedded on other sites and never send
+                // cookies; the admin UI and API stay same-origin only.
+                tracing::warn!(
+                    "ALLOWED_ORIGINS not set. Allowing cross-origin requests to /client/* only."
+                );
+                CorsLayer::new()
+                    .allow_origin(AllowOrigin::predicate(|_, parts| {
+                        parts.uri.path().starts_with("/client/")
+                    }))
+                    .allow_methods([Method::GET, Method::POST, Method::OPTIONS])
+                    .allow_headers([header::CONTENT_TYPE, header::ACCEPT])
             } else {
                 use axum::http::HeaderValue;
                 use axum::http::Method;
@@ -141,7 +435,7 @@ async fn main() {
                     .map(|s| s.parse::<HeaderValue>().unwrap())
                     .collect();
 
-                println!("Configuring CORS for origins: {:?}", allowed_origins);
+                tracing::info!(origins = ?allowed_origins, "Configuring CORS");
 
                 CorsLayer::new()
                     .allow_origin(origins)
@@ -151,20 +445,46 @@ async fn main() {
                         header::AUTHORIZATION,
                         header::ACCEPT,
                         header::ORIGIN,
+                        header::HeaderName::from_static(api::csrf::CSRF_HEADER),
                     ])
                     .allow_credentials(true)
             }
-        });
+        })
+        // Only the path is recorded: client tokens travel in the query string
+        .layer(
+            TraceLayer::new_for_http().make_span_with(|req: &axum::http::Request<_>| {
+                let route = req
+                    .extensions()
+                    .get::<axum::extract::MatchedPath>()
+                    .map(|p| p.as_str().to_string());
+                tracing::info_span!(
+                    "http",
+                    method = %req.method(),
+                    path = req.uri().path(),
+                    route,
+                )
+            }),
+        );
 
     let addr = SocketAddr::from(([0, 0, 0, 0], 3000));
-    println!("listening on {}", addr);
+    tracing::info!(%addr, "listening");
 
     // Axum 0.8 uses axum::serve
     let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
-    axum::serve(
+    let server = axum::serve(
         listener,
         app.into_make_service_with_connect_info::<SocketAddr>(),
-    )
-    .await
-    .unwrap();
+    );
+    tokio::select! {
+        result = server => result.unwrap(),
+        _ = tokio::signal::ctrl_c() => tracing::info!("shutting down"),
+    }
+
+    // Keep the raw samples and the open minute for the history charts
+    if let Err(e) = metrics_history.flush() {
+        tracing::error!(error = %e, "Failed to save metrics history");
+    }
+
+    // Flush spans still waiting for the OTLP exporter
+    telemetry.shutdown();
 }
@@ -0,0 +1,93 @@
+use crate::domain::repositories::AccessListRepository;
+use crate::domain::{AccessRule, NewAccessRule};
+use super::Numbered;
+use std::error::Error;
+use std::fs;
+use std::sync::RwLock;
+
+/// Access rules kept in memory and written through to a JSON file on every change.
+pub struct FileAccessListRepository {
+    path: String,
+    rules: RwLock<Numbered<AccessRule>>,
+}
+
+impl FileAccessListRepository {
+    pub fn new(path: &str) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let rules = super::load_numbered(path, |r: &AccessRule| r.id)?;
+
+        Ok(Self {
+            path: path.to_string(),
+            rules: RwLock::new(rules),
+        })
+    }
+
+    fn persist(&self, rules: &Numbered<AccessRule>) -> Result<(), Box<dyn Error + Send + Sync>> {
+        // Write to a temp file first so a crash never leaves a half-written list
+        let tmp = format!("{}.tmp", self.path);
+        fs::write(&tmp, serde_json::to_string_pretty(rules)?)?;
+        fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+}
+
+impl AccessListRepository for FileAccessListRepository {
+    fn list(&self) -> Vec<AccessRule> {
+        let now = chrono::Utc::now().timestamp();
+        self.rules
+            .read()
+            .unwrap()
+            .entries
+            .iter()
+            .filter(|r| !r.is_expired(now))
+            .cloned()
+            .collect()
+    }
+
+    fn add(&self, rule: NewAccessRule) -> Result<AccessRule, Box<dyn Error + Send + Sync>> {
+        let device_id = rule
+            .device_id
+            .map(|id| id.trim().to_string())
+            .filter(|id| !id.is_empty());
+        let network = match &device_id {
+            Some(_) => String::new(),
+            None => crate::utils::parse_network(&rule.network)
+                .ok_or_else(|| format!("Invalid IP or CIDR: {}", rule.network))?
+                .trunc()
+                .to_string(),
+        };
+
+        let now = chrono::Utc::now().timestamp();
+        let mut rules = self.rules.write().unwrap();
+
+        // Expired rules are dropped whenever the list is rewritten
+        rules.entries.retain(|r| !r.is_expired(now));
+
+        let entry = AccessRule {
+            id: rules.take_id(),
+            network,
+            device_id,
+            action: rule.action,
+            reason: rule.reason.filter(|r| !r.trim().is_empty()),
+            created_at: now,
+            expires_at: rule
+                .expires_in_minutes
+                .filter(|m| *m > 0)
+                .map(|m| now + m * 60),
+        };
+        rules.entries.push(entry.clone());
+        self.persist(&rules)?;
+
+        Ok(entry)
+    }
+
+    fn remove(&self, id: u64) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let mut rules = self.rules.write().unwrap();
+        let before = rules.entries.len();
+        rules.entries.retain(|r| r.id != id);
+        if rules.entries.len() == before {
+            return Ok(false);
+        }
+        self.persist(&rules)?;
+        Ok(true)
+    }
+}
@@ -0,0 +1,82 @@
+use crate::domain::repositories::AlertRuleRepository;
+use crate::domain::{AlertRule, NewAlertRule};
+use super::Numbered;
+use std::error::Error;
+use std::fs;
+use std::sync::RwLock;
+
+/// Alert rules kept in memory and written through to a JSON file on every change.
+pub struct FileAlertRuleRepository {
+    path: String,
+    rules: RwLock<Numbered<AlertRule>>,
+}
+
+impl FileAlertRuleRepository {
+    pub fn new(path: &str) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let rules = super::load_numbered(path, |r: &AlertRule| r.id)?;
+
+        Ok(Self {
+            path: path.to_string(),
+            rules: RwLock::new(rules),
+        })
+    }
+
+    fn persist(&self, rules: &Numbered<AlertRule>) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let tmp = format!("{}.tmp", self.path);
+        fs::write(&tmp, serde_json::to_string_pretty(rules)?)?;
+        fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+}
+
+impl AlertRuleRepository for FileAlertRuleRepository {
+    fn list(&self) -> Vec<AlertRule> {
+        self.rules.read().unwrap().entries.clone()
+    }
+
+    fn add(&self, rule: NewAlertRule) -> Result<AlertRule, Box<dyn Error + Send + Sync>> {
+        let name = rule.name.trim().to_string();
+        if name.is_empty() {
+            return Err("Rule name is required".into());
+        }
+        if !rule.threshold.is_finite() {
+            return Err("Threshold must be a number".into());
+        }
+        let webhook_url = rule
+            .webhook_url
+            .map(|url| url.trim().to_string())
+            .filter(|url| !url.is_empty());
+        if let Some(url) = &webhook_url
+            && !(url.starts_with("http://") || url.starts_with("https://"))
+        {
+            return Err(format!("Invalid webhook URL: {}", url).into());
+        }
+
+        let mut rules = self.rules.write().unwrap();
+        let entry = AlertRule {
+            id: rules.take_id(),
+            name,
+            metric: rule.metric,
+            comparison: rule.comparison,
+            threshold: rule.threshold,
+            for_secs: rule.for_secs,
+            webhook_url,
+            created_at: chrono::Utc::now().timestamp(),
+        };
+        rules.entries.push(entry.clone());
+        self.persist(&rules)?;
+
+        Ok(entry)
+    }
+
+    fn remove(&self, id: u64) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let mut rules = self.rules.write().unwrap();
+        let before = rules.entries.len();
+        rules.entries.retain(|r| r.id != id);
+        if rules.entries.len() == before {
+            return Ok(false);
+        }
+        self.persist(&rules)?;
+        Ok(true)
+    }
+}
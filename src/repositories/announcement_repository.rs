@@ -0,0 +1,89 @@
+use crate::domain::repositories::AnnouncementRepository;
+use crate::domain::{Announcement, NewAnnouncement};
+use std::error::Error;
+use std::fs;
+use std::sync::RwLock;
+
+// Oldest announcements are dropped from history past this many
+const MAX_HISTORY: usize = 500;
+
+/// Announcement history kept in memory and written through to a JSON file.
+pub struct FileAnnouncementRepository {
+    path: String,
+    announcements: RwLock<Vec<Announcement>>,
+}
+
+impl FileAnnouncementRepository {
+    pub fn new(path: &str) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let announcements = super::load_json(path)?;
+
+        Ok(Self {
+            path: path.to_string(),
+            announcements: RwLock::new(announcements),
+        })
+    }
+
+    fn persist(&self, announcements: &[Announcement]) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let tmp = format!("{}.tmp", self.path);
+        fs::write(&tmp, serde_json::to_string_pretty(announcements)?)?;
+        fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+}
+
+impl AnnouncementRepository for FileAnnouncementRepository {
+    fn history(&self) -> Vec<Announcement> {
+        self.announcements.read().unwrap().iter().rev().cloned().collect()
+    }
+
+    fn add(
+        &self,
+        announcement: NewAnnouncement,
+    ) -> Result<Announcement, Box<dyn Error + Send + Sync>> {
+        let message = announcement.message.trim().to_string();
+        if message.is_empty() {
+            return Err("Message is empty".into());
+        }
+
+        let now = chrono::Utc::now().timestamp();
+        let mut announcements = self.announcements.write().unwrap();
+
+        let entry = Announcement {
+            id: announcements.iter().map(|a| a.id).max().unwrap_or(0) + 1,
+            message,
+            severity: announcement.severity,
+            room: announcement
+                .room
+                .map(|r| r.trim().to_string())
+                .filter(|r| !r.is_empty()),
+            created_at: now,
+            expires_at: announcement
+                .expires_in_minutes
+                .filter(|m| *m > 0)
+                .map(|m| now + m * 60),
+        };
+        announcements.push(entry.clone());
+
+        let overflow = announcements.len().saturating_sub(MAX_HISTORY);
+        announcements.drain(..overflow);
+        self.persist(&announcements)?;
+
+        Ok(entry)
+    }
+
+    fn end(&self, id: u64) -> Result<Option<Announcement>, Box<dyn Error + Send + Sync>> {
+        let now = chrono::Utc::now().timestamp();
+        let mut announcements = self.announcements.write().unwrap();
+
+        let Some(entry) = announcements.iter_mut().find(|a| a.id == id) else {
+            return Ok(None);
+        };
+        if !entry.is_expired(now) {
+            entry.expires_at = Some(now);
+        }
+        let entry = entry.clone();
+        self.persist(&announcements)?;
+
+        Ok(Some(entry))
+    }
+}
@@ -0,0 +1,57 @@
+use crate::domain::AuditEntry;
+use crate::domain::repositories::AuditRepository;
+use std::error::Error;
+use std::fs::{self, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::sync::Mutex;
+
+/// Audit trail stored as JSON lines, kept apart from the connection log so
+/// clearing one never touches the other.
+pub struct FileAuditRepository {
+    path: String,
+    write_lock: Mutex<()>,
+}
+
+impl FileAuditRepository {
+    pub fn new(path: &str) -> Self {
+        Self {
+            path: path.to_string(),
+            write_lock: Mutex::new(()),
+        }
+    }
+}
+
+impl AuditRepository for FileAuditRepository {
+    #[tracing::instrument(level = "debug", skip_all, fields(action = %entry.action))]
+    fn record(&self, entry: &AuditEntry) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let line = serde_json::to_string(entry)?;
+
+        let _lock = self.write_lock.lock().unwrap();
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(file, "{}", line)?;
+        Ok(())
+    }
+
+    fn recent(&self, limit: usize) -> Vec<AuditEntry> {
+        let Ok(file) = fs::File::open(&self.path) else {
+            return Vec::new();
+        };
+
+        // Split on raw bytes so an undecodable line is skipped, not the end of the file
+        let mut entries: Vec<AuditEntry> = BufReader::new(file)
+            .split(b'\n')
+            .map_while(Result::ok)
+            .filter_map(|line| serde_json::from_slice(&line).ok())
+            .collect();
+        entries.reverse();
+        entries.truncate(limit);
+        entries
+    }
+
+    fn get_raw_content(&self) -> Result<String, Box<dyn Error + Send + Sync>> {
+        Ok(fs::read_to_string(&self.path)?)
+    }
+}
@@ -1,17 +1,32 @@
 use crate::domain::repositories::LogRepository;
-use crate::domain::{LogEntry, LogMetadata, LogQuery, LogStats};
+use crate::domain::{
+    DeviceClass, GeoInfo, IntegrityReport, LogArchive, LogEntry, LogFilter, LogMetadata, LogQuery,
+    LogStats, SubjectEvent, UserAgentInfo,
+};
+use crate::services::log_integrity::{self, Checkpoint, CheckpointKind, CheckpointSigner};
+use crate::services::user_agent;
 use std::collections::HashSet;
 use std::error::Error;
 use std::fs::{self, OpenOptions};
 use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::sync::Mutex;
+use tracing::instrument;
 
 pub struct FileLogRepository {
     path: String,
-    // We keep a mutex for writing only, assuming reads can be concurrent/loose or also protected.
-    // However, LogService was stateless with reads.
-    // For now, let's keep the write mutex if we want to ensure thread safety on writes inside this repo.
-    write_lock: Mutex<()>,
+    // Cleared logs are moved here rather than deleted
+    archive_dir: PathBuf,
+    // Serializes writes and tracks the head of the hash chain
+    chain: Mutex<ChainHead>,
+    signer: Option<CheckpointSigner>,
+}
+
+struct ChainHead {
+    hash: String,
+    // Chained records since the last reset
+    records: u64,
+    checkpointed: u64,
 }
 
 impl FileLogRepository {
@@ -21,23 +36,129 @@ impl FileLogRepository {
             fs::File::create(path).unwrap();
         }
 
+        let (hash, records) = log_integrity::restore_head(path);
+
         Self {
             path: path.to_string(),
-            write_lock: Mutex::new(()),
+            archive_dir: PathBuf::from(format!("{}.archive", path)),
+            chain: Mutex::new(ChainHead {
+                hash,
+                records,
+                checkpointed: records,
+            }),
+            signer: CheckpointSigner::from_env(),
         }
     }
 
-    fn shorten_device(device: &str) -> String {
-        if let Some(start) = device.find('(') {
-            if let Some(end) = device[start..].find(')') {
-                return device[start + 1..start + end].to_string();
-            }
+    fn write_checkpoint(
+        &self,
+        kind: CheckpointKind,
+        head: &ChainHead,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let checkpoint = Checkpoint {
+            kind,
+            records: head.records,
+            hash: head.hash.clone(),
+            timestamp: chrono::Local::now().format("%Y-%m-%d %H:%M:%S %z").to_string(),
+            signature: None,
+        };
+        log_integrity::append_checkpoint(
+            &log_integrity::checkpoints_path(&self.path),
+            checkpoint,
+            self.signer.as_ref(),
+        )?;
+        Ok(())
+    }
+
+    // Archive names come from URLs, so only accept plain file names we could have created
+    fn archive_path(&self, name: &str) -> Result<PathBuf, Box<dyn Error + Send + Sync>> {
+        let valid = !name.is_empty()
+            && !name.starts_with('.')
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+        let path = self.archive_dir.join(name);
+        if !valid || !path.is_file() {
+            return Err(format!("No such archive: {}", name).into());
+        }
+        Ok(path)
+    }
+
+    fn describe_archive(path: &Path) -> Option<LogArchive> {
+        let metadata = fs::metadata(path).ok()?;
+        let records = fs::read_to_string(path)
+            .map(|c| c.lines().filter(|l| !l.trim().is_empty()).count())
+            .unwrap_or(0);
+        let created_at = metadata
+            .modified()
+            .map(|t| {
+                chrono::DateTime::<chrono::Local>::from(t)
+                    .format("%Y-%m-%d %H:%M:%S")
+                    .to_string()
+            })
+            .unwrap_or_default();
+
+        Some(LogArchive {
+            name: path.file_name()?.to_string_lossy().to_string(),
+            size_bytes: metadata.len(),
+            records,
+            created_at,
+        })
+    }
+
+    // The CSV columns of a record, without its hash. Every client-supplied
+    // value is sanitized so that a record always has the same columns.
+    fn record_body(entry: &LogEntry) -> String {
+        use crate::utils::sanitize_csv_field as field;
+        let agent = &entry.agent;
+        let geo = &entry.geo;
+        format!(
+            "{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
+            entry.timestamp,
+            field(&entry.ip),
+            field(&entry.device),
+            field(&entry.device_id),
+            entry.action,
+            entry.count,
+            entry.duration.clone().unwrap_or_default(),
+            entry.note.as_deref().map(field).unwrap_or_default(),
+            field(&agent.browser),
+            field(&agent.browser_version),
+            field(&agent.os),
+            field(&agent.os_version),
+            agent.device_class.as_str(),
+            field(&geo.country),
+            field(&geo.city),
+            geo.asn.map(|n| n.to_string()).unwrap_or_default(),
+            field(&geo.as_org)
+        )
+    }
+
+    // Appends records to the live log, extending the chain from `head`
+    fn write_chained(
+        &self,
+        head: &mut ChainHead,
+        bodies: impl IntoIterator<Item = String>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+
+        for body in bodies {
+            let hash = log_integrity::chain_hash(&head.hash, &body);
+            writeln!(file, "{},{}", body, hash)?;
+            head.hash = hash;
+            head.records += 1;
         }
-        device.chars().take(30).collect()
+        Ok(())
     }
 
     fn parse_log_entry(line: String) -> Option<LogEntry> {
-        let parts: Vec<&str> = line.split(',').collect();
+        let mut parts: Vec<&str> = line.split(',').collect();
+        if log_integrity::split_hash(&line).is_some() {
+            parts.pop();
+        }
         let len = parts.len();
 
         if len < 5 {
@@ -54,6 +175,28 @@ impl FileLogRepository {
         } else {
             None
         };
+        let note = parts.get(7).filter(|n| !n.is_empty()).map(|n| n.to_string());
+
+        // Older records only kept a shortened user agent, which mostly still names the OS
+        let agent = match parts.get(8..13) {
+            Some([browser, browser_version, os, os_version, device_class]) => UserAgentInfo {
+                browser: browser.to_string(),
+                browser_version: browser_version.to_string(),
+                os: os.to_string(),
+                os_version: os_version.to_string(),
+                device_class: DeviceClass::parse(device_class),
+            },
+            _ => user_agent::parse(parts[2]),
+        };
+        let geo = match parts.get(13..17) {
+            Some([country, city, asn, as_org]) => GeoInfo {
+                country: country.to_string(),
+                city: city.to_string(),
+                asn: asn.parse().ok(),
+                as_org: as_org.to_string(),
+            },
+            _ => GeoInfo::default(),
+        };
 
         Some(LogEntry {
             timestamp: parts[0].to_string(),
@@ -63,51 +206,22 @@ impl FileLogRepository {
             action,
             count,
             duration,
+            note,
+            agent,
+            geo,
             raw: line,
         })
     }
 }
 
 impl LogRepository for FileLogRepository {
+    #[instrument(level = "debug", skip_all, fields(action = %entry.action))]
     fn append(&self, entry: &LogEntry) -> Result<(), Box<dyn Error + Send + Sync>> {
-        let _lock = self.write_lock.lock().unwrap();
-
-        let mut file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(&self.path)?;
-
-        let short_device = Self::shorten_device(&entry.device);
-        let sanitized_device = short_device.replace(",", " ");
-
-        // entry.timestamp is expected to be formatted already or we format it here?
-        // In existing code, FileLogger formatted it.
-        // Here, let's assume the caller passes a populated LogEntry, BUT for raw CSV writing we might need to re-construct it or use the struct fields.
-        // Existing FileLogger::log used: Local::now(), ip, device, device_id, action, count.
-        // Let's implement append to take arguments or assume entry has them.
-        // LogEntry has strings.
-
-        // Wait, existing FileLogger generated the timestamp.
-        // If we move logic here, this repo should probably handle the "append raw line" or "append structured".
-        // Let's stick to the trait: append(&LogEntry).
-
-        let duration_str = entry.duration.clone().unwrap_or_default();
-
-        writeln!(
-            file,
-            "{},{},{},{},{},{},{}",
-            entry.timestamp,
-            entry.ip,
-            sanitized_device,
-            entry.device_id,
-            entry.action,
-            entry.count,
-            duration_str
-        )?;
-
-        Ok(())
+        let mut head = self.chain.lock().unwrap();
+        self.write_chained(&mut head, [Self::record_body(entry)])
     }
 
+    #[instrument(skip_all, fields(page = params.page, page_size = params.page_size))]
     fn find_all(&self, params: &LogQuery) -> (Vec<LogEntry>, LogMetadata, LogStats) {
         let file = match fs::File::open(&self.path) {
             Ok(f) => f,
@@ -126,7 +240,11 @@ impl LogRepository for FileLogRepository {
                         active_users: 0,
                         last_activity: "-".to_string(),
                         top_ips: vec![],
+                        top_countries: vec![],
                         requests_over_time: vec![],
+                        browsers: vec![],
+                        operating_systems: vec![],
+                        device_classes: vec![],
                     },
                 );
             }
@@ -138,8 +256,12 @@ impl LogRepository for FileLogRepository {
 
         let mut all_logs = Vec::new();
         let mut ip_counts: HashMap<String, u32> = HashMap::new();
+        let mut country_counts: HashMap<String, u32> = HashMap::new();
         let mut unique_device_ids = HashSet::new();
         let mut hourly_counts: HashMap<String, u32> = HashMap::new(); // Key: YYYY-MM-DD HH:00
+        let mut browser_counts: HashMap<String, u32> = HashMap::new();
+        let mut os_counts: HashMap<String, u32> = HashMap::new();
+        let mut class_counts: HashMap<String, u32> = HashMap::new();
 
         let q_lower = params.q.as_ref().map(|s| s.to_lowercase());
         let exclude_ips: Option<Vec<String>> = params.exclude_ip.as_ref().map(|s| {
@@ -148,45 +270,69 @@ impl LogRepository for FileLogRepository {
                 .filter(|part| !part.is_empty())
                 .collect()
         });
+        let wanted = |filter: &Option<String>, value: &str| {
+            filter
+                .as_deref()
+                .map(str::trim)
+                .is_none_or(|f| f.is_empty() || f.eq_ignore_ascii_case(value))
+        };
 
-        for line_res in reader.lines() {
-            if let Ok(line) = line_res {
-                let entry = Self::parse_log_entry(line);
-
-                if let Some(log) = entry {
-                    // Filter
-                    if let Some(ex_list) = &exclude_ips {
-                       let log_ip_lower = log.ip.to_lowercase();
-                       if ex_list.iter().any(|ex| log_ip_lower.contains(ex)) {
-                           continue;
-                       }
-                    }
-
-                    if let Some(q) = &q_lower {
-                        if !log.timestamp.to_lowercase().contains(q)
-                            && !log.ip.to_lowercase().contains(q)
-                            && !log.device.to_lowercase().contains(q)
-                            && !log.device_id.to_lowercase().contains(q)
-                            && !log.action.to_lowercase().contains(q)
-                        {
-                            continue;
-                        }
-                    }
-
-                    // Collect Stats
-                    *ip_counts.entry(log.ip.clone()).or_insert(0) += 1;
-                    unique_device_ids.insert(log.device_id.clone());
-
-                    // Hourly stats for chart (simple approximation)
-                    // Assuming timestamp format YYYY-MM-DD HH:MM:SS ...
-                    if log.timestamp.len() >= 13 {
-                        let hour_key = format!("{}:00", &log.timestamp[..13]);
-                        *hourly_counts.entry(hour_key).or_insert(0) += 1;
-                    }
-
-                    all_logs.push(log);
+        // Decoded lossily: one bad byte must not hide the records after it
+        for line in reader.split(b'\n').map_while(Result::ok) {
+            let line = String::from_utf8_lossy(&line).into_owned();
+            let Some(log) = Self::parse_log_entry(line) else {
+                continue;
+            };
+
+            // Filter
+            if let Some(ex_list) = &exclude_ips {
+                let log_ip_lower = log.ip.to_lowercase();
+                if ex_list.iter().any(|ex| log_ip_lower.contains(ex)) {
+                    continue;
                 }
             }
+
+            if let Some(q) = &q_lower
+                && !log.timestamp.to_lowercase().contains(q)
+                && !log.ip.to_lowercase().contains(q)
+                && !log.device.to_lowercase().contains(q)
+                && !log.device_id.to_lowercase().contains(q)
+                && !log.action.to_lowercase().contains(q)
+            {
+                continue;
+            }
+
+            if !wanted(&params.browser, &log.agent.browser)
+                || !wanted(&params.os, &log.agent.os)
+                || !wanted(&params.device_class, log.agent.device_class.as_str())
+                || !wanted(&params.country, &log.geo.country)
+            {
+                continue;
+            }
+
+            // Collect Stats
+            *ip_counts.entry(log.ip.clone()).or_insert(0) += 1;
+            if !log.geo.country.is_empty() {
+                *country_counts.entry(log.geo.country.clone()).or_insert(0) += 1;
+            }
+            unique_device_ids.insert(log.device_id.clone());
+
+            if log.action == "CONNECTED" {
+                *browser_counts.entry(log.agent.browser.clone()).or_insert(0) += 1;
+                *os_counts.entry(log.agent.os.clone()).or_insert(0) += 1;
+                *class_counts
+                    .entry(log.agent.device_class.as_str().to_string())
+                    .or_insert(0) += 1;
+            }
+
+            // Hourly stats for chart (simple approximation)
+            // Assuming timestamp format YYYY-MM-DD HH:MM:SS ...
+            if log.timestamp.len() >= 13 {
+                let hour_key = format!("{}:00", &log.timestamp[..13]);
+                *hourly_counts.entry(hour_key).or_insert(0) += 1;
+            }
+
+            all_logs.push(log);
         }
 
         // Stats calculation
@@ -200,14 +346,24 @@ impl LogRepository for FileLogRepository {
 
         // Top IPs
         let mut top_ips_vec: Vec<(String, u32)> = ip_counts.into_iter().collect();
-        top_ips_vec.sort_by(|a, b| b.1.cmp(&a.1));
+        top_ips_vec.sort_by_key(|b| std::cmp::Reverse(b.1));
         top_ips_vec.truncate(10); // Top 10
 
+        let mut top_countries: Vec<(String, u32)> = country_counts.into_iter().collect();
+        top_countries.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        top_countries.truncate(10);
+
         // Chart Data (sorted by time)
         let mut requests_over_time: Vec<(String, u32)> = hourly_counts.into_iter().collect();
         requests_over_time.sort_by(|a, b| a.0.cmp(&b.0));
         // Limit chart points if too many? For now keep all.
 
+        let breakdown = |counts: HashMap<String, u32>| {
+            let mut sorted: Vec<(String, u32)> = counts.into_iter().collect();
+            sorted.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+            sorted
+        };
+
         // Sort Logs
         all_logs.sort_by(|a, b| {
             let cmp = match params.sort_by.as_str() {
@@ -216,6 +372,10 @@ impl LogRepository for FileLogRepository {
                 "device" => a.device.cmp(&b.device),
                 "device_id" => a.device_id.cmp(&b.device_id),
                 "action" => a.action.cmp(&b.action),
+                "browser" => a.agent.browser.cmp(&b.agent.browser),
+                "os" => a.agent.os.cmp(&b.agent.os),
+                "device_class" => a.agent.device_class.as_str().cmp(b.agent.device_class.as_str()),
+                "country" => a.geo.country.cmp(&b.geo.country),
                 _ => a.timestamp.cmp(&b.timestamp),
             };
             if params.order == "asc" {
@@ -258,19 +418,210 @@ impl LogRepository for FileLogRepository {
                 active_users,
                 last_activity,
                 top_ips: top_ips_vec,
+                top_countries,
                 requests_over_time,
+                browsers: breakdown(browser_counts),
+                operating_systems: breakdown(os_counts),
+                device_classes: breakdown(class_counts),
             },
         )
     }
 
-    fn clear(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
-        let _lock = self.write_lock.lock().unwrap();
-        fs::write(&self.path, "")?;
-        Ok(())
+    #[instrument(skip_all)]
+    fn archive(&self) -> Result<Option<LogArchive>, Box<dyn Error + Send + Sync>> {
+        let mut head = self.chain.lock().unwrap();
+        if fs::metadata(&self.path).map(|m| m.len()).unwrap_or(0) == 0 {
+            return Ok(None);
+        }
+
+        fs::create_dir_all(&self.archive_dir)?;
+        let stem = Path::new(&self.path)
+            .file_stem()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_else(|| "log".to_string());
+        let stamp = chrono::Local::now().format("%Y%m%d-%H%M%S");
+        let mut target = self.archive_dir.join(format!("{}-{}.log", stem, stamp));
+        let mut n = 2;
+        while target.exists() {
+            target = self.archive_dir.join(format!("{}-{}-{}.log", stem, stamp, n));
+            n += 1;
+        }
+
+        // The reset checkpoint vouches for the now empty log; the chain carries on from its hash
+        self.write_checkpoint(CheckpointKind::Reset, &head)?;
+        fs::rename(&self.path, &target)?;
+        fs::File::create(&self.path)?;
+        head.records = 0;
+        head.checkpointed = 0;
+
+        Ok(Self::describe_archive(&target))
+    }
+
+    fn list_archives(&self) -> Vec<LogArchive> {
+        let Ok(dir) = fs::read_dir(&self.archive_dir) else {
+            return Vec::new();
+        };
+
+        let mut archives: Vec<LogArchive> = dir
+            .filter_map(Result::ok)
+            .filter(|e| e.path().is_file())
+            .filter_map(|e| Self::describe_archive(&e.path()))
+            .collect();
+        // Names embed the archive time, so they sort chronologically
+        archives.sort_by(|a, b| b.name.cmp(&a.name));
+        archives
+    }
+
+    fn read_archive(&self, name: &str) -> Result<String, Box<dyn Error + Send + Sync>> {
+        Ok(fs::read_to_string(self.archive_path(name)?)?)
+    }
+
+    #[instrument(skip(self))]
+    fn restore_archive(&self, name: &str) -> Result<usize, Box<dyn Error + Send + Sync>> {
+        let path = self.archive_path(name)?;
+        let mut head = self.chain.lock().unwrap();
+
+        // Records are re-chained onto the live log; their old hashes belonged to the old chain
+        let bodies: Vec<String> = fs::read_to_string(&path)?
+            .lines()
+            .filter_map(|line| Self::parse_log_entry(line.to_string()))
+            .map(|entry| Self::record_body(&entry))
+            .collect();
+        let count = bodies.len();
+
+        self.write_chained(&mut head, bodies)?;
+        fs::remove_file(&path)?;
+        Ok(count)
+    }
+
+    #[instrument(skip(self))]
+    fn purge_archive(&self, name: &str) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        match self.archive_path(name) {
+            Ok(path) => {
+                fs::remove_file(path)?;
+                Ok(true)
+            }
+            Err(_) => Ok(false),
+        }
+    }
+
+    // Filters name a data subject, so they stay out of the span
+    #[instrument(skip_all)]
+    fn find_matching(&self, filter: &LogFilter) -> Vec<SubjectEvent> {
+        if filter.is_empty() {
+            return Vec::new();
+        }
+
+        let mut sources = vec![("live".to_string(), PathBuf::from(&self.path))];
+        sources.extend(
+            self.list_archives()
+                .into_iter()
+                .map(|a| (a.name.clone(), self.archive_dir.join(&a.name))),
+        );
+
+        let mut found = Vec::new();
+        for (source, path) in sources {
+            let content = fs::read(path).unwrap_or_default();
+            found.extend(
+                String::from_utf8_lossy(&content)
+                    .lines()
+                    .filter_map(|line| Self::parse_log_entry(line.to_string()))
+                    .filter(|entry| filter.matches(entry))
+                    .map(|event| SubjectEvent {
+                        source: source.clone(),
+                        event,
+                    }),
+            );
+        }
+        found
+    }
+
+    #[instrument(skip_all)]
+    fn delete_matching(&self, filter: &LogFilter) -> Result<usize, Box<dyn Error + Send + Sync>> {
+        if filter.is_empty() {
+            return Err("Filter must name a device_id or ip".into());
+        }
+        let mut head = self.chain.lock().unwrap();
+        let mut removed = 0;
+
+        // Live log: keep the rest and re-chain it from a redaction checkpoint
+        let entries: Vec<LogEntry> = fs::read_to_string(&self.path)?
+            .lines()
+            .filter_map(|line| Self::parse_log_entry(line.to_string()))
+            .collect();
+        let before = entries.len();
+        let kept: Vec<String> = entries
+            .into_iter()
+            .filter(|entry| !filter.matches(entry))
+            .map(|entry| Self::record_body(&entry))
+            .collect();
+
+        if kept.len() < before {
+            removed += before - kept.len();
+            self.write_checkpoint(CheckpointKind::Redaction, &head)?;
+
+            let tmp = format!("{}.tmp", self.path);
+            let mut rechained = ChainHead {
+                hash: head.hash.clone(),
+                records: 0,
+                checkpointed: 0,
+            };
+            let mut content = String::new();
+            for body in kept {
+                let hash = log_integrity::chain_hash(&rechained.hash, &body);
+                content.push_str(&format!("{},{}\n", body, hash));
+                rechained.hash = hash;
+                rechained.records += 1;
+            }
+            fs::write(&tmp, content)?;
+            fs::rename(&tmp, &self.path)?;
+            *head = rechained;
+        }
+
+        // Archives aren't chained, matching lines are simply dropped
+        for archive in self.list_archives() {
+            let path = self.archive_dir.join(&archive.name);
+            let content = fs::read_to_string(&path)?;
+            let lines: Vec<&str> = content.lines().collect();
+            let kept: Vec<&str> = lines
+                .iter()
+                .copied()
+                .filter(|line| {
+                    Self::parse_log_entry(line.to_string()).is_none_or(|e| !filter.matches(&e))
+                })
+                .collect();
+
+            if kept.len() < lines.len() {
+                removed += lines.len() - kept.len();
+                let mut rewritten = kept.join("\n");
+                rewritten.push('\n');
+                fs::write(&path, rewritten)?;
+            }
+        }
+
+        Ok(removed)
     }
 
     fn get_raw_content(&self) -> Result<String, Box<dyn Error + Send + Sync>> {
         let content = fs::read_to_string(&self.path)?;
         Ok(content)
     }
+
+    #[instrument(level = "debug", skip_all)]
+    fn checkpoint(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut head = self.chain.lock().unwrap();
+        if head.records == head.checkpointed {
+            return Ok(());
+        }
+        self.write_checkpoint(CheckpointKind::Periodic, &head)?;
+        head.checkpointed = head.records;
+        Ok(())
+    }
+
+    #[instrument(skip_all)]
+    fn verify_integrity(&self) -> IntegrityReport {
+        // Hold the write lock so the chain isn't extended mid-walk
+        let _head = self.chain.lock().unwrap();
+        log_integrity::verify(&self.path, self.signer.as_ref())
+    }
 }
@@ -0,0 +1,173 @@
+use crate::domain::repositories::MetricsHistoryRepository;
+use crate::domain::{HistoryResolution, MetricsSample};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fs;
+use std::sync::RwLock;
+
+// One hour of 2-second samples
+const RAW_CAPACITY: usize = 1800;
+// Two days of minutes
+const MINUTE_CAPACITY: usize = 2 * 24 * 60;
+// Thirty days of hours
+const HOUR_CAPACITY: usize = 30 * 24;
+
+/// System metrics history in three ring buffers: raw samples, minutes and
+/// hours. All three are written through to a JSON file whenever a minute
+/// closes and again on `flush`, so a restart keeps the last hour of raw
+/// samples and the next sample rolls the stored open minute up.
+pub struct FileMetricsHistoryRepository {
+    path: String,
+    rings: RwLock<Rings>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct Rings {
+    // Files written before raw samples were stored only have the coarser rings
+    #[serde(default)]
+    raw: VecDeque<MetricsSample>,
+    minutes: VecDeque<MetricsSample>,
+    hours: VecDeque<MetricsSample>,
+}
+
+impl FileMetricsHistoryRepository {
+    pub fn new(path: &str) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let rings = super::load_json(path)?;
+
+        Ok(Self {
+            path: path.to_string(),
+            rings: RwLock::new(rings),
+        })
+    }
+
+    fn persist(&self, rings: &Rings) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let tmp = format!("{}.tmp", self.path);
+        fs::write(&tmp, serde_json::to_string(rings)?)?;
+        fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+}
+
+impl MetricsHistoryRepository for FileMetricsHistoryRepository {
+    fn record(&self, sample: MetricsSample) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut rings = self.rings.write().unwrap();
+        let previous = rings.raw.back().map(|s| s.timestamp);
+
+        // The sample opens a new minute: roll the finished one up, and its hour if that closed too
+        let mut closed = false;
+        if let Some(previous) = previous {
+            let minute = bucket(previous, HistoryResolution::Minute);
+            if minute != bucket(sample.timestamp, HistoryResolution::Minute) {
+                let rolled = downsample(&rings.raw, minute, HistoryResolution::Minute);
+                push(&mut rings.minutes, rolled, MINUTE_CAPACITY);
+                closed = true;
+
+                let hour = bucket(previous, HistoryResolution::Hour);
+                if hour != bucket(sample.timestamp, HistoryResolution::Hour) {
+                    let rolled = downsample(&rings.minutes, hour, HistoryResolution::Hour);
+                    push(&mut rings.hours, rolled, HOUR_CAPACITY);
+                }
+            }
+        }
+        push(&mut rings.raw, Some(sample), RAW_CAPACITY);
+
+        if closed {
+            self.persist(&rings)?;
+        }
+        Ok(())
+    }
+
+    fn since(&self, resolution: HistoryResolution, since: i64) -> Vec<MetricsSample> {
+        let rings = self.rings.read().unwrap();
+        let ring = match resolution {
+            HistoryResolution::Raw => &rings.raw,
+            HistoryResolution::Minute => &rings.minutes,
+            HistoryResolution::Hour => &rings.hours,
+        };
+        ring.iter().filter(|s| s.timestamp >= since).copied().collect()
+    }
+
+    fn flush(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.persist(&self.rings.read().unwrap())
+    }
+}
+
+fn bucket(timestamp: i64, resolution: HistoryResolution) -> i64 {
+    timestamp - timestamp.rem_euclid(resolution.seconds())
+}
+
+fn push(ring: &mut VecDeque<MetricsSample>, sample: Option<MetricsSample>, capacity: usize) {
+    if let Some(sample) = sample {
+        ring.push_back(sample);
+    }
+    while ring.len() > capacity {
+        ring.pop_front();
+    }
+}
+
+// Averages the samples in the bucket starting at `start`; connection counts keep their peak
+fn downsample(
+    samples: &VecDeque<MetricsSample>,
+    start: i64,
+    resolution: HistoryResolution,
+) -> Option<MetricsSample> {
+    let in_bucket: Vec<&MetricsSample> = samples
+        .iter()
+        .filter(|s| bucket(s.timestamp, resolution) == start)
+        .collect();
+    let last = in_bucket.last()?;
+    let n = in_bucket.len();
+    let mean = |value: fn(&MetricsSample) -> f64| in_bucket.iter().map(|s| value(s)).sum::<f64>() / n as f64;
+
+    Some(MetricsSample {
+        timestamp: start,
+        cpu: mean(|s| s.cpu as f64) as f32,
+        ram_used: mean(|s| s.ram_used as f64) as u64,
+        ram_total: last.ram_total,
+        load: mean(|s| s.load),
+        net_rx: mean(|s| s.net_rx as f64) as u64,
+        net_tx: mean(|s| s.net_tx as f64) as u64,
+        connections: in_bucket.iter().map(|s| s.connections).max().unwrap_or(0),
+        users: in_bucket.iter().map(|s| s.users).max().unwrap_or(0),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(timestamp: i64, cpu: f32) -> MetricsSample {
+        MetricsSample {
+            timestamp,
+            cpu,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn flushed_open_minute_survives_a_restart() {
+        let path = std::env::temp_dir()
+            .join(format!("metrics-history-{}.json", std::process::id()))
+            .to_string_lossy()
+            .into_owned();
+        let _ = fs::remove_file(&path);
+
+        let repo = FileMetricsHistoryRepository::new(&path).unwrap();
+        repo.record(sample(600, 10.0)).unwrap();
+        repo.record(sample(602, 30.0)).unwrap();
+        repo.flush().unwrap();
+        drop(repo);
+
+        let repo = FileMetricsHistoryRepository::new(&path).unwrap();
+        assert_eq!(repo.since(HistoryResolution::Raw, 0).len(), 2);
+
+        // The first sample after the restart closes the minute that was open at shutdown
+        repo.record(sample(660, 50.0)).unwrap();
+        let minutes = repo.since(HistoryResolution::Minute, 0);
+        assert_eq!(minutes.len(), 1);
+        assert_eq!(minutes[0].timestamp, 600);
+        assert_eq!(minutes[0].cpu, 20.0);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -1 +1,136 @@
+pub mod access_list_repository;
+pub mod alert_rule_repository;
+pub mod announcement_repository;
+pub mod audit_repository;
 pub mod log_repository;
+pub mod metrics_history_repository;
+pub mod vpn_peer_repository;
+pub mod webhook_delivery_repository;
+pub mod webhook_subscription_repository;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs;
+use std::io::ErrorKind;
+
+/// Loads the JSON file behind one of the write-through repositories. A missing
+/// file is an empty store. A file that can't be read or parsed is an error:
+/// starting empty would overwrite it, and everything in it, on the next write.
+pub(crate) fn load_json<T: DeserializeOwned + Default>(
+    path: &str,
+) -> Result<T, Box<dyn Error + Send + Sync>> {
+    match fs::read_to_string(path) {
+        Ok(content) => serde_json::from_str(&content)
+            .map_err(|e| format!("{} is not valid JSON ({}); fix or move it aside", path, e).into()),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(T::default()),
+        Err(e) => Err(format!("Failed to read {}: {}", path, e).into()),
+    }
+}
+
+/// File contents of a repository that numbers its entries. The next id is
+/// stored rather than derived from the entries, so removing the newest entry
+/// never lets its id be handed out again.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct Numbered<T> {
+    pub next_id: u64,
+    pub entries: Vec<T>,
+}
+
+impl<T> Numbered<T> {
+    pub fn take_id(&mut self) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+}
+
+// Files written before the counter was stored are a bare array of entries
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum NumberedFile<T> {
+    Numbered(Numbered<T>),
+    Legacy(Vec<T>),
+}
+
+/// `load_json` for a numbered repository, upgrading a bare array of entries.
+pub(crate) fn load_numbered<T: DeserializeOwned>(
+    path: &str,
+    id: impl Fn(&T) -> u64,
+) -> Result<Numbered<T>, Box<dyn Error + Send + Sync>> {
+    let (next_id, entries) = match load_json(path)? {
+        Some(NumberedFile::Numbered(file)) => (file.next_id, file.entries),
+        Some(NumberedFile::Legacy(entries)) => (0, entries),
+        None => (0, Vec::new()),
+    };
+    let after_last = entries.iter().map(id).max().unwrap_or(0) + 1;
+
+    Ok(Numbered {
+        next_id: next_id.max(after_last),
+        entries,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> String {
+        std::env::temp_dir()
+            .join(format!("load-json-{}-{}.json", name, std::process::id()))
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    #[test]
+    fn missing_file_is_an_empty_store() {
+        let path = temp_path("missing");
+        let _ = fs::remove_file(&path);
+
+        let loaded: Vec<u64> = load_json(&path).unwrap();
+        assert!(loaded.is_empty());
+    }
+
+    #[test]
+    fn invalid_json_is_an_error_and_left_alone() {
+        let path = temp_path("invalid");
+        fs::write(&path, "[1, 2,").unwrap();
+
+        let err = load_json::<Vec<u64>>(&path).unwrap_err().to_string();
+        assert!(err.contains("is not valid JSON"), "{}", err);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "[1, 2,");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn valid_json_is_loaded() {
+        let path = temp_path("valid");
+        fs::write(&path, "[1, 2]").unwrap();
+
+        let loaded: Vec<u64> = load_json(&path).unwrap();
+        assert_eq!(loaded, [1, 2]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn numbered_store_keeps_ids_of_removed_entries() {
+        let path = temp_path("numbered");
+        fs::write(&path, r#"{"next_id": 7, "entries": [1, 2]}"#).unwrap();
+
+        let mut store: Numbered<u64> = load_numbered(&path, |n| *n).unwrap();
+        assert_eq!(store.take_id(), 7);
+        assert_eq!(store.take_id(), 8);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn bare_array_is_upgraded_to_a_numbered_store() {
+        let path = temp_path("legacy");
+        fs::write(&path, "[1, 5, 3]").unwrap();
+
+        let mut store: Numbered<u64> = load_numbered(&path, |n| *n).unwrap();
+        assert_eq!(store.entries, [1, 5, 3]);
+        assert_eq!(store.take_id(), 6);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,196 @@
+use crate::domain::repositories::VpnPeerRepository;
+use crate::domain::{NewVpnPeer, VpnPeer};
+use base64::prelude::*;
+use ipnet::IpNet;
+use super::Numbered;
+use std::error::Error;
+use std::fs;
+use std::net::IpAddr;
+use std::sync::RwLock;
+
+/// VPN peers kept in memory and written through to a JSON file on every change.
+/// Overlay addresses are handed out from `network`.
+pub struct FileVpnPeerRepository {
+    path: String,
+    network: IpNet,
+    peers: RwLock<Numbered<VpnPeer>>,
+}
+
+impl FileVpnPeerRepository {
+    pub fn new(path: &str, network: IpNet) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let peers = super::load_numbered(path, |p: &VpnPeer| p.id)?;
+
+        Ok(Self {
+            path: path.to_string(),
+            network,
+            peers: RwLock::new(peers),
+        })
+    }
+
+    fn persist(&self, peers: &Numbered<VpnPeer>) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let tmp = format!("{}.tmp", self.path);
+        fs::write(&tmp, serde_json::to_string_pretty(peers)?)?;
+        fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+
+    // Whether `ip` is one of the addresses `hosts()` hands out, which leaves
+    // out the network and broadcast addresses of an IPv4 overlay
+    fn is_host(&self, ip: IpAddr) -> bool {
+        let mut hosts = self.network.hosts();
+        match (hosts.next(), hosts.next_back()) {
+            (Some(first), Some(last)) => first <= ip && ip <= last,
+            (Some(only), None) => only == ip,
+            _ => false,
+        }
+    }
+
+    // Validates `peer` against the other peers and fills in defaults. `existing`
+    // is the entry being updated; it keeps its id, times and (by default) address.
+    // A new peer's id is left for `add` to assign.
+    fn build(
+        &self,
+        peer: NewVpnPeer,
+        peers: &[VpnPeer],
+        existing: Option<&VpnPeer>,
+    ) -> Result<VpnPeer, Box<dyn Error + Send + Sync>> {
+        let others = || {
+            peers
+                .iter()
+                .filter(move |p| existing.is_none_or(|e| e.id != p.id))
+        };
+
+        let device_id = peer.device_id.trim().to_string();
+        if device_id.is_empty() {
+            return Err("device_id is required".into());
+        }
+        if others().any(|p| p.device_id == device_id) {
+            return Err(format!("{} is already a peer", device_id).into());
+        }
+
+        let public_key = peer.public_key.trim().to_string();
+        if !BASE64_STANDARD
+            .decode(&public_key)
+            .is_ok_and(|key| key.len() == 32)
+        {
+            return Err("Public key must be a base64 WireGuard key".into());
+        }
+        if others().any(|p| p.public_key == public_key) {
+            return Err("Public key is already in use".into());
+        }
+
+        let overlay_ip = match peer.overlay_ip.or(existing.map(|e| e.overlay_ip)) {
+            Some(ip) => {
+                if !self.is_host(ip) {
+                    return Err(format!("{} is not a host address in the overlay network {}", ip, self.network).into());
+                }
+                if others().any(|p| p.overlay_ip == ip) {
+                    return Err(format!("{} is already assigned", ip).into());
+                }
+                ip
+            }
+            None => self
+                .network
+                .hosts()
+                .find(|ip| !others().any(|p| p.overlay_ip == *ip))
+                .ok_or("No free address left in the overlay network")?,
+        };
+
+        let mut allowed_ips = Vec::new();
+        for entry in peer.allowed_ips.iter().map(|s| s.trim()).filter(|s| !s.is_empty()) {
+            let net = crate::utils::parse_network(entry)
+                .ok_or_else(|| format!("Invalid allowed IP: {}", entry))?;
+            allowed_ips.push(net.to_string());
+        }
+        if allowed_ips.is_empty() {
+            allowed_ips.push(IpNet::from(overlay_ip).to_string());
+        }
+
+        let mut tags: Vec<String> = Vec::new();
+        for tag in peer.tags.iter().map(|t| t.trim()).filter(|t| !t.is_empty()) {
+            if !tags.iter().any(|t| t == tag) {
+                tags.push(tag.to_string());
+            }
+        }
+
+        let name = match peer.name.trim() {
+            "" => device_id.clone(),
+            name => name.to_string(),
+        };
+
+        Ok(VpnPeer {
+            id: existing.map_or(0, |e| e.id),
+            device_id,
+            name,
+            owner: peer.owner.trim().to_string(),
+            public_key,
+            overlay_ip,
+            allowed_ips,
+            tags,
+            created_at: existing.map_or_else(|| chrono::Utc::now().timestamp(), |e| e.created_at),
+            last_seen: existing.and_then(|e| e.last_seen),
+            last_handshake: existing.and_then(|e| e.last_handshake),
+        })
+    }
+}
+
+impl VpnPeerRepository for FileVpnPeerRepository {
+    fn list(&self) -> Vec<VpnPeer> {
+        self.peers.read().unwrap().entries.clone()
+    }
+
+    fn add(&self, peer: NewVpnPeer) -> Result<VpnPeer, Box<dyn Error + Send + Sync>> {
+        let mut peers = self.peers.write().unwrap();
+        let mut entry = self.build(peer, &peers.entries, None)?;
+        entry.id = peers.take_id();
+        peers.entries.push(entry.clone());
+        self.persist(&peers)?;
+
+        Ok(entry)
+    }
+
+    fn update(
+        &self,
+        id: u64,
+        peer: NewVpnPeer,
+    ) -> Result<Option<VpnPeer>, Box<dyn Error + Send + Sync>> {
+        let mut peers = self.peers.write().unwrap();
+        let Some(index) = peers.entries.iter().position(|p| p.id == id) else {
+            return Ok(None);
+        };
+        let entry = self.build(peer, &peers.entries, Some(&peers.entries[index]))?;
+        peers.entries[index] = entry.clone();
+        self.persist(&peers)?;
+
+        Ok(Some(entry))
+    }
+
+    fn remove(&self, id: u64) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let mut peers = self.peers.write().unwrap();
+        let before = peers.entries.len();
+        peers.entries.retain(|p| p.id != id);
+        if peers.entries.len() == before {
+            return Ok(false);
+        }
+        self.persist(&peers)?;
+        Ok(true)
+    }
+
+    fn record_presence(
+        &self,
+        device_id: &str,
+        at: i64,
+        handshake: bool,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut peers = self.peers.write().unwrap();
+        // Most connections aren't peers; those cost no write
+        let Some(peer) = peers.entries.iter_mut().find(|p| p.device_id == device_id) else {
+            return Ok(());
+        };
+        peer.last_seen = Some(peer.last_seen.map_or(at, |seen| seen.max(at)));
+        if handshake {
+            peer.last_handshake = Some(at);
+        }
+        self.persist(&peers)
+    }
+}
@@ -0,0 +1,140 @@
+use crate::domain::repositories::WebhookDeliveryRepository;
+use crate::domain::{DeliveryStatus, WebhookDelivery, WebhookEvent, WebhookSubscription};
+use super::Numbered;
+use std::error::Error;
+use std::fs;
+use std::sync::RwLock;
+
+// Finished deliveries kept as history
+const HISTORY_LIMIT: usize = 500;
+// Pending deliveries per subscription. Past this its endpoint is taken to be
+// down, and new events for it are dropped rather than growing the queue file.
+const PENDING_LIMIT: usize = 1000;
+
+/// Delivery queue and history in one JSON file, rewritten whenever a delivery
+/// is queued, claimed or completed. Only the dispatcher writes to it, from the
+/// blocking thread pool.
+pub struct FileWebhookDeliveryRepository {
+    path: String,
+    // Oldest first
+    deliveries: RwLock<Numbered<WebhookDelivery>>,
+}
+
+impl FileWebhookDeliveryRepository {
+    pub fn new(path: &str) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let deliveries = super::load_numbered(path, |d: &WebhookDelivery| d.id)?;
+
+        Ok(Self {
+            path: path.to_string(),
+            deliveries: RwLock::new(deliveries),
+        })
+    }
+
+    fn persist(&self, deliveries: &Numbered<WebhookDelivery>) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let tmp = format!("{}.tmp", self.path);
+        fs::write(&tmp, serde_json::to_string(deliveries)?)?;
+        fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+}
+
+impl WebhookDeliveryRepository for FileWebhookDeliveryRepository {
+    fn enqueue(
+        &self,
+        event: WebhookEvent,
+        body: &str,
+        subscriptions: &[WebhookSubscription],
+    ) -> Result<usize, Box<dyn Error + Send + Sync>> {
+        let now = chrono::Utc::now().timestamp();
+        let mut store = self.deliveries.write().unwrap();
+        let mut dropped = 0;
+
+        for subscription in subscriptions {
+            let pending = store
+                .entries
+                .iter()
+                .filter(|d| d.subscription_id == subscription.id && d.status == DeliveryStatus::Pending)
+                .count();
+            if pending >= PENDING_LIMIT {
+                dropped += 1;
+                continue;
+            }
+
+            let id = store.take_id();
+            store.entries.push(WebhookDelivery {
+                id,
+                subscription_id: subscription.id,
+                url: subscription.url.clone(),
+                event,
+                body: body.to_string(),
+                status: DeliveryStatus::Pending,
+                attempts: 0,
+                created_at: now,
+                next_attempt_at: now,
+                last_attempt_at: None,
+                response_status: None,
+                last_error: None,
+            });
+        }
+
+        // Trim the oldest finished deliveries beyond the history limit
+        let finished = store
+            .entries
+            .iter()
+            .filter(|d| d.status != DeliveryStatus::Pending)
+            .count();
+        let mut excess = finished.saturating_sub(HISTORY_LIMIT);
+        store.entries.retain(|d| {
+            if excess > 0 && d.status != DeliveryStatus::Pending {
+                excess -= 1;
+                return false;
+            }
+            true
+        });
+
+        if dropped < subscriptions.len() {
+            self.persist(&store)?;
+        }
+        Ok(dropped)
+    }
+
+    fn claim_due(
+        &self,
+        now: i64,
+        lease_secs: i64,
+    ) -> Result<Vec<WebhookDelivery>, Box<dyn Error + Send + Sync>> {
+        let mut store = self.deliveries.write().unwrap();
+        let mut due = Vec::new();
+        for delivery in store.entries.iter_mut() {
+            if delivery.status == DeliveryStatus::Pending && delivery.next_attempt_at <= now {
+                delivery.next_attempt_at = now + lease_secs;
+                due.push(delivery.clone());
+            }
+        }
+        if !due.is_empty() {
+            self.persist(&store)?;
+        }
+        Ok(due)
+    }
+
+    fn update(&self, delivery: &WebhookDelivery) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut store = self.deliveries.write().unwrap();
+        if let Some(existing) = store.entries.iter_mut().find(|d| d.id == delivery.id) {
+            *existing = delivery.clone();
+            self.persist(&store)?;
+        }
+        Ok(())
+    }
+
+    fn recent(&self, limit: usize) -> Vec<WebhookDelivery> {
+        self.deliveries
+            .read()
+            .unwrap()
+            .entries
+            .iter()
+            .rev()
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+}
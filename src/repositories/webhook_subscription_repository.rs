@@ -0,0 +1,93 @@
+use crate::domain::repositories::WebhookSubscriptionRepository;
+use crate::domain::{NewWebhookSubscription, WebhookSubscription};
+use super::Numbered;
+use rand::RngCore;
+use std::error::Error;
+use std::fs;
+use std::sync::RwLock;
+
+/// Webhook subscriptions kept in memory and written through to a JSON file on every change.
+pub struct FileWebhookSubscriptionRepository {
+    path: String,
+    subscriptions: RwLock<Numbered<WebhookSubscription>>,
+}
+
+impl FileWebhookSubscriptionRepository {
+    pub fn new(path: &str) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let subscriptions = super::load_numbered(path, |s: &WebhookSubscription| s.id)?;
+
+        Ok(Self {
+            path: path.to_string(),
+            subscriptions: RwLock::new(subscriptions),
+        })
+    }
+
+    fn persist(
+        &self,
+        subscriptions: &Numbered<WebhookSubscription>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let tmp = format!("{}.tmp", self.path);
+        fs::write(&tmp, serde_json::to_string_pretty(subscriptions)?)?;
+        fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+}
+
+impl WebhookSubscriptionRepository for FileWebhookSubscriptionRepository {
+    fn list(&self) -> Vec<WebhookSubscription> {
+        self.subscriptions.read().unwrap().entries.clone()
+    }
+
+    fn add(
+        &self,
+        subscription: NewWebhookSubscription,
+    ) -> Result<WebhookSubscription, Box<dyn Error + Send + Sync>> {
+        let url = subscription.url.trim().to_string();
+        if !(url.starts_with("http://") || url.starts_with("https://")) {
+            return Err(format!("Invalid webhook URL: {}", url).into());
+        }
+        let mut events = Vec::new();
+        for event in subscription.events {
+            if !events.contains(&event) {
+                events.push(event);
+            }
+        }
+        if events.is_empty() {
+            return Err("Pick at least one event".into());
+        }
+        let secret = match subscription.secret.map(|s| s.trim().to_string()) {
+            Some(secret) if !secret.is_empty() => secret,
+            _ => new_secret(),
+        };
+
+        let mut subscriptions = self.subscriptions.write().unwrap();
+        let entry = WebhookSubscription {
+            id: subscriptions.take_id(),
+            url,
+            events,
+            secret,
+            created_at: chrono::Utc::now().timestamp(),
+        };
+        subscriptions.entries.push(entry.clone());
+        self.persist(&subscriptions)?;
+
+        Ok(entry)
+    }
+
+    fn remove(&self, id: u64) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        let mut subscriptions = self.subscriptions.write().unwrap();
+        let before = subscriptions.entries.len();
+        subscriptions.entries.retain(|s| s.id != id);
+        if subscriptions.entries.len() == before {
+            return Ok(false);
+        }
+        self.persist(&subscriptions)?;
+        Ok(true)
+    }
+}
+
+fn new_secret() -> String {
+    let mut bytes = [0u8; 32];
+    rand::rng().fill_bytes(&mut bytes);
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
@@ -0,0 +1,241 @@
+use crate::domain::{Alert, AlertMetric, AlertRule, AlertStatus};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::env;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// Window the per-IP connection count covers
+const CONNECT_WINDOW: Duration = Duration::from_secs(60);
+// Resolved alerts kept for the dashboard and API
+const RESOLVED_HISTORY: usize = 50;
+
+/// Readings one round of evaluation checks the rules against. The per-IP
+/// connection counts come from the engine itself.
+pub struct AlertInputs {
+    pub cpu_percent: f64,
+    pub active_users: f64,
+    pub wakatime_failures: f64,
+}
+
+// One rule (and IP, for per-IP rules) whose condition currently holds
+struct Tracker {
+    // When the condition started holding, unix seconds
+    since: i64,
+    // Set once it has held for the rule's duration
+    alert: Option<Alert>,
+    webhook_url: Option<String>,
+}
+
+/// Evaluates alert rules and sends a webhook when an alert starts firing and
+/// again when it resolves. Nothing is re-sent while an alert keeps firing.
+pub struct AlertEngine {
+    // Receive every notification, on top of each rule's own webhook
+    webhooks: Vec<String>,
+    client: reqwest::Client,
+    recent_connects: Mutex<VecDeque<(Instant, String)>>,
+    trackers: Mutex<HashMap<String, Tracker>>,
+    resolved: Mutex<VecDeque<Alert>>,
+}
+
+impl AlertEngine {
+    /// `ALERT_WEBHOOK_URLS` is a comma-separated list of URLs that receive
+    /// every alert.
+    pub fn from_env() -> Self {
+        let webhooks = env::var("ALERT_WEBHOOK_URLS")
+            .unwrap_or_default()
+            .split(',')
+            .map(|url| url.trim().to_string())
+            .filter(|url| !url.is_empty())
+            .collect();
+        Self::new(webhooks)
+    }
+
+    pub fn new(webhooks: Vec<String>) -> Self {
+        Self {
+            webhooks,
+            client: reqwest::Client::builder()
+                .timeout(Duration::from_secs(10))
+                .build()
+                .unwrap_or_default(),
+            recent_connects: Mutex::new(VecDeque::new()),
+            trackers: Mutex::new(HashMap::new()),
+            resolved: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Whether `ALERT_WEBHOOK_URLS` names any receivers.
+    pub fn has_global_webhooks(&self) -> bool {
+        !self.webhooks.is_empty()
+    }
+
+    /// Counts a client connection towards the per-IP rate.
+    pub fn connection_opened(&self, ip: &str) {
+        let mut recent = self.recent_connects.lock().unwrap();
+        recent.push_back((Instant::now(), ip.to_string()));
+        prune(&mut recent);
+    }
+
+    fn connects_per_ip(&self) -> HashMap<String, u32> {
+        let mut recent = self.recent_connects.lock().unwrap();
+        prune(&mut recent);
+        let mut counts = HashMap::new();
+        for (_, ip) in recent.iter() {
+            *counts.entry(ip.clone()).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    /// Runs every rule against `inputs` and notifies the webhooks of alerts
+    /// that started firing or resolved. Alerts of rules that no longer exist
+    /// resolve too. Returns the alerts that changed state.
+    pub fn evaluate(&self, rules: &[AlertRule], inputs: &AlertInputs) -> Vec<Alert> {
+        let now = chrono::Utc::now().timestamp();
+        let per_ip = self.connects_per_ip();
+
+        let mut readings: HashMap<String, f64> = HashMap::new();
+        let mut breached = HashSet::new();
+        let mut changed = Vec::new();
+        let mut trackers = self.trackers.lock().unwrap();
+
+        for rule in rules {
+            let values: Vec<(Option<String>, f64)> = match rule.metric {
+                AlertMetric::CpuPercent => vec![(None, inputs.cpu_percent)],
+                AlertMetric::ActiveUsers => vec![(None, inputs.active_users)],
+                AlertMetric::WakatimeFailures => vec![(None, inputs.wakatime_failures)],
+                AlertMetric::ConnectionsPerIp => per_ip
+                    .iter()
+                    .map(|(ip, count)| (Some(ip.clone()), *count as f64))
+                    .collect(),
+            };
+
+            for (subject, value) in values {
+                let fingerprint = match &subject {
+                    Some(subject) => format!("{}:{}", rule.id, subject),
+                    None => rule.id.to_string(),
+                };
+                readings.insert(fingerprint.clone(), value);
+                if !rule.comparison.breached(value, rule.threshold) {
+                    continue;
+                }
+                breached.insert(fingerprint.clone());
+
+                let tracker = trackers.entry(fingerprint.clone()).or_insert(Tracker {
+                    since: now,
+                    alert: None,
+                    webhook_url: rule.webhook_url.clone(),
+                });
+                match &mut tracker.alert {
+                    Some(alert) => alert.value = value,
+                    None if now - tracker.since >= rule.for_secs as i64 => {
+                        let alert = Alert {
+                            fingerprint,
+                            rule_id: rule.id,
+                            rule_name: rule.name.clone(),
+                            metric: rule.metric,
+                            subject,
+                            status: AlertStatus::Firing,
+                            value,
+                            threshold: rule.threshold,
+                            started_at: now,
+                            resolved_at: None,
+                        };
+                        tracker.alert = Some(alert.clone());
+                        self.notify(&alert, tracker.webhook_url.as_deref());
+                        changed.push(alert);
+                    }
+                    None => {}
+                }
+            }
+        }
+
+        // Conditions that stopped holding: pending ones are dropped, firing ones resolve
+        let cleared: Vec<String> = trackers
+            .keys()
+            .filter(|fingerprint| !breached.contains(*fingerprint))
+            .cloned()
+            .collect();
+        for fingerprint in cleared {
+            let Some(tracker) = trackers.remove(&fingerprint) else {
+                continue;
+            };
+            let Some(mut alert) = tracker.alert else {
+                continue;
+            };
+            alert.status = AlertStatus::Resolved;
+            alert.resolved_at = Some(now);
+            // An IP that left the window has no reading; it's down to zero
+            alert.value = match alert.subject {
+                Some(_) => readings.get(&fingerprint).copied().unwrap_or(0.0),
+                None => readings.get(&fingerprint).copied().unwrap_or(alert.value),
+            };
+            self.notify(&alert, tracker.webhook_url.as_deref());
+
+            let mut resolved = self.resolved.lock().unwrap();
+            resolved.push_front(alert.clone());
+            resolved.truncate(RESOLVED_HISTORY);
+            changed.push(alert);
+        }
+
+        changed
+    }
+
+    /// Alerts currently firing, oldest first
+    pub fn firing(&self) -> Vec<Alert> {
+        let mut firing: Vec<Alert> = self
+            .trackers
+            .lock()
+            .unwrap()
+            .values()
+            .filter_map(|t| t.alert.clone())
+            .collect();
+        firing.sort_by_key(|a| a.started_at);
+        firing
+    }
+
+    /// Recently resolved alerts, newest first
+    pub fn resolved(&self) -> Vec<Alert> {
+        self.resolved.lock().unwrap().iter().cloned().collect()
+    }
+
+    // Posts the alert as JSON to each webhook in the background; failures are only logged
+    fn notify(&self, alert: &Alert, webhook_url: Option<&str>) {
+        match alert.status {
+            AlertStatus::Firing => tracing::warn!(
+                rule = %alert.rule_name,
+                subject = alert.subject.as_deref(),
+                value = alert.value,
+                "Alert firing"
+            ),
+            AlertStatus::Resolved => tracing::info!(
+                rule = %alert.rule_name,
+                subject = alert.subject.as_deref(),
+                "Alert resolved"
+            ),
+        }
+
+        let mut targets: Vec<&str> = self.webhooks.iter().map(String::as_str).collect();
+        if let Some(url) = webhook_url
+            && !targets.contains(&url)
+        {
+            targets.push(url);
+        }
+        for url in targets {
+            let request = self.client.post(url).json(alert);
+            let url = url.to_string();
+            tokio::spawn(async move {
+                if let Err(e) = request.send().await.and_then(|r| r.error_for_status()) {
+                    tracing::warn!(url, error = %e, "Alert webhook delivery failed");
+                }
+            });
+        }
+    }
+}
+
+fn prune(recent: &mut VecDeque<(Instant, String)>) {
+    while recent
+        .front()
+        .is_some_and(|(at, _)| at.elapsed() > CONNECT_WINDOW)
+    {
+        recent.pop_front();
+    }
+}
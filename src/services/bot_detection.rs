@@ -0,0 +1,87 @@
+use crate::domain::DeviceClass;
+use crate::services::user_agent;
+use std::collections::HashMap;
+use std::env;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// Instant disconnects older than this are forgotten
+const STRIKE_WINDOW: Duration = Duration::from_secs(3600);
+
+/// Decides which connections are bots: crawler user agents, user agents on
+/// the `BOT_USER_AGENTS` list, and clients that keep disconnecting right after
+/// connecting (health checks, uptime monitors). A client here is an IP and
+/// user agent pair.
+pub struct BotDetector {
+    // Extra case-insensitive user agent substrings
+    patterns: Vec<String>,
+    // Sessions shorter than this are instant disconnects. Off by default, as
+    // users sharing an IP and browser (NAT, local testing) would be flagged together
+    instant_disconnect: Duration,
+    // Instant disconnects from one client before its connections count as bots
+    strike_limit: u32,
+    // COUNT_BOTS_AS_USERS: keep bots in the active user counts
+    pub count_as_users: bool,
+    // Per (ip, user agent): instant disconnects within the window, and the latest one
+    strikes: Mutex<HashMap<(String, String), (u32, Instant)>>,
+}
+
+impl BotDetector {
+    pub fn from_env() -> Self {
+        let patterns = env::var("BOT_USER_AGENTS")
+            .unwrap_or_default()
+            .split(',')
+            .map(|p| p.trim().to_lowercase())
+            .filter(|p| !p.is_empty())
+            .collect();
+        let instant_disconnect_secs = env::var("BOT_INSTANT_DISCONNECT_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0);
+        let strike_limit = env::var("BOT_INSTANT_DISCONNECT_LIMIT")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|n| *n > 0)
+            .unwrap_or(3);
+        let count_as_users = env::var("COUNT_BOTS_AS_USERS").is_ok_and(|v| v == "true" || v == "1");
+
+        Self {
+            patterns,
+            instant_disconnect: Duration::from_secs(instant_disconnect_secs),
+            strike_limit,
+            count_as_users,
+            strikes: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn is_bot(&self, ip: &str, user_agent: &str) -> bool {
+        if user_agent::parse(user_agent).device_class == DeviceClass::Bot {
+            return true;
+        }
+        let lower = user_agent.to_lowercase();
+        if self.patterns.iter().any(|p| lower.contains(p)) {
+            return true;
+        }
+
+        self.strikes
+            .lock()
+            .unwrap()
+            .get(&(ip.to_string(), user_agent.to_string()))
+            .is_some_and(|(count, last)| *count >= self.strike_limit && last.elapsed() < STRIKE_WINDOW)
+    }
+
+    /// Called when a session ends; short sessions count against the client.
+    pub fn record_session(&self, ip: &str, user_agent: &str, duration: Duration) {
+        if self.instant_disconnect.is_zero() || duration >= self.instant_disconnect {
+            return;
+        }
+
+        let mut strikes = self.strikes.lock().unwrap();
+        strikes.retain(|_, (_, last)| last.elapsed() < STRIKE_WINDOW);
+        let entry = strikes
+            .entry((ip.to_string(), user_agent.to_string()))
+            .or_insert((0, Instant::now()));
+        entry.0 += 1;
+        entry.1 = Instant::now();
+    }
+}
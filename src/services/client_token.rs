@@ -0,0 +1,136 @@
+use base64::prelude::*;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::env;
+use std::fmt;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// How `/client/ws` treats connections that don't present a signed token.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientAuthMode {
+    /// Trust the `device_id` query parameter (legacy behaviour)
+    Off,
+    /// Accept unsigned clients, but under a fresh anonymous id
+    Optional,
+    /// Reject unsigned clients with 401
+    Required,
+}
+
+impl ClientAuthMode {
+    pub fn from_env() -> Self {
+        match env::var("CLIENT_TOKEN_MODE")
+            .unwrap_or_default()
+            .to_lowercase()
+            .as_str()
+        {
+            "optional" => Self::Optional,
+            "required" => Self::Required,
+            _ => Self::Off,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ClientClaims {
+    // device_id the token was issued for
+    pub sub: String,
+    // expiry, unix seconds
+    pub exp: i64,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum TokenError {
+    Malformed,
+    BadSignature,
+    Expired,
+}
+
+impl fmt::Display for TokenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenError::Malformed => write!(f, "malformed token"),
+            TokenError::BadSignature => write!(f, "invalid token signature"),
+            TokenError::Expired => write!(f, "token expired"),
+        }
+    }
+}
+
+impl std::error::Error for TokenError {}
+
+/// Issues and verifies short-lived `<payload>.<hmac>` tokens binding a client to a device_id.
+pub struct ClientTokenService {
+    secret: Vec<u8>,
+    ttl_secs: i64,
+}
+
+impl ClientTokenService {
+    pub fn new(secret: Vec<u8>, ttl_secs: i64) -> Self {
+        Self { secret, ttl_secs }
+    }
+
+    /// Uses `CLIENT_TOKEN_SECRET` when set so tokens survive restarts and can be
+    /// minted by other services; otherwise falls back to `fallback_secret`.
+    pub fn from_env(fallback_secret: &[u8]) -> Self {
+        let secret = env::var("CLIENT_TOKEN_SECRET")
+            .ok()
+            .filter(|s| !s.is_empty())
+            .map(|s| s.into_bytes())
+            .unwrap_or_else(|| fallback_secret.to_vec());
+        let ttl_secs = env::var("CLIENT_TOKEN_TTL_SECS")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(300);
+
+        Self::new(secret, ttl_secs)
+    }
+
+    pub fn new_device_id() -> String {
+        let mut bytes = [0u8; 12];
+        rand::rng().fill_bytes(&mut bytes);
+        format!("dev-{}", BASE64_URL_SAFE_NO_PAD.encode(bytes))
+    }
+
+    fn sign(&self, payload: &str) -> HmacSha256 {
+        let mut mac =
+            HmacSha256::new_from_slice(&self.secret).expect("HMAC accepts keys of any length");
+        mac.update(payload.as_bytes());
+        mac
+    }
+
+    pub fn issue(&self, device_id: &str) -> (String, i64) {
+        let exp = chrono::Utc::now().timestamp() + self.ttl_secs;
+        let claims = ClientClaims {
+            sub: device_id.to_string(),
+            exp,
+        };
+        let payload = BASE64_URL_SAFE_NO_PAD.encode(serde_json::to_vec(&claims).unwrap());
+        let signature = BASE64_URL_SAFE_NO_PAD.encode(self.sign(&payload).finalize().into_bytes());
+
+        (format!("{}.{}", payload, signature), exp)
+    }
+
+    pub fn verify(&self, token: &str) -> Result<ClientClaims, TokenError> {
+        let (payload, signature) = token.split_once('.').ok_or(TokenError::Malformed)?;
+        let signature = BASE64_URL_SAFE_NO_PAD
+            .decode(signature)
+            .map_err(|_| TokenError::Malformed)?;
+
+        self.sign(payload)
+            .verify_slice(&signature)
+            .map_err(|_| TokenError::BadSignature)?;
+
+        let raw = BASE64_URL_SAFE_NO_PAD
+            .decode(payload)
+            .map_err(|_| TokenError::Malformed)?;
+        let claims: ClientClaims = serde_json::from_slice(&raw).map_err(|_| TokenError::Malformed)?;
+
+        if claims.exp < chrono::Utc::now().timestamp() {
+            return Err(TokenError::Expired);
+        }
+
+        Ok(claims)
+    }
+}
@@ -0,0 +1,209 @@
+use std::collections::HashMap;
+use std::env;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+// Per-IP buckets are pruned once this many are tracked
+const MAX_TRACKED_BUCKETS: usize = 10_000;
+
+/// Connection caps for the WebSocket endpoints. A limit of 0 disables that check.
+#[derive(Debug, Clone)]
+pub struct ConnectionLimits {
+    pub max_per_ip: usize,
+    pub max_per_device: usize,
+    pub max_total: usize,
+    // Token bucket per IP: sustained connects per second and burst size
+    pub connect_rate: f64,
+    pub connect_burst: f64,
+}
+
+impl ConnectionLimits {
+    pub fn from_env() -> Self {
+        fn var<T: std::str::FromStr>(name: &str, default: T) -> T {
+            env::var(name)
+                .ok()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(default)
+        }
+
+        Self {
+            max_per_ip: var("WS_MAX_PER_IP", 50),
+            max_per_device: var("WS_MAX_PER_DEVICE", 5),
+            max_total: var("WS_MAX_CONNECTIONS", 10_000),
+            connect_rate: var("WS_CONNECT_RATE", 5.0),
+            connect_burst: var("WS_CONNECT_BURST", 20.0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitRejection {
+    RateLimited,
+    TooManyForIp,
+    TooManyForDevice,
+    ServerFull,
+}
+
+impl LimitRejection {
+    pub const ALL: [LimitRejection; 4] = [
+        LimitRejection::RateLimited,
+        LimitRejection::TooManyForIp,
+        LimitRejection::TooManyForDevice,
+        LimitRejection::ServerFull,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LimitRejection::RateLimited => "rate_limited",
+            LimitRejection::TooManyForIp => "too_many_for_ip",
+            LimitRejection::TooManyForDevice => "too_many_for_device",
+            LimitRejection::ServerFull => "server_full",
+        }
+    }
+
+    /// WebSocket close code sent to the rejected client
+    pub fn close_code(&self) -> u16 {
+        match self {
+            // 1013 Try Again Later: transient, the client may retry with backoff
+            LimitRejection::RateLimited | LimitRejection::ServerFull => 1013,
+            // 1008 Policy Violation: retrying won't help until others disconnect
+            LimitRejection::TooManyForIp | LimitRejection::TooManyForDevice => 1008,
+        }
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+#[derive(Default)]
+struct Inner {
+    total: usize,
+    per_ip: HashMap<IpAddr, usize>,
+    per_device: HashMap<String, usize>,
+    buckets: HashMap<IpAddr, Bucket>,
+}
+
+pub struct ConnectionLimiter {
+    limits: ConnectionLimits,
+    inner: Mutex<Inner>,
+    rejections: [AtomicU64; 4],
+}
+
+impl ConnectionLimiter {
+    pub fn new(limits: ConnectionLimits) -> Self {
+        Self {
+            limits,
+            inner: Mutex::new(Inner::default()),
+            rejections: Default::default(),
+        }
+    }
+
+    /// Reserves a slot for a new connection. The slot is released when the
+    /// returned permit is dropped, i.e. when the socket task ends.
+    pub fn try_acquire(
+        self: &Arc<Self>,
+        ip: IpAddr,
+        device_id: &str,
+    ) -> Result<ConnectionPermit, LimitRejection> {
+        let result = self.check_and_reserve(ip, device_id);
+        if let Err(rejection) = result {
+            self.rejections[rejection as usize].fetch_add(1, Ordering::Relaxed);
+            return Err(rejection);
+        }
+
+        Ok(ConnectionPermit {
+            limiter: self.clone(),
+            ip,
+            device_id: device_id.to_string(),
+        })
+    }
+
+    fn check_and_reserve(&self, ip: IpAddr, device_id: &str) -> Result<(), LimitRejection> {
+        let limits = &self.limits;
+        let mut inner = self.inner.lock().unwrap();
+        let now = Instant::now();
+
+        if limits.max_total > 0 && inner.total >= limits.max_total {
+            return Err(LimitRejection::ServerFull);
+        }
+        if limits.max_per_ip > 0 && inner.per_ip.get(&ip).copied().unwrap_or(0) >= limits.max_per_ip {
+            return Err(LimitRejection::TooManyForIp);
+        }
+        if limits.max_per_device > 0
+            && inner.per_device.get(device_id).copied().unwrap_or(0) >= limits.max_per_device
+        {
+            return Err(LimitRejection::TooManyForDevice);
+        }
+
+        // Only admitted connections spend a token, so a client turned away for
+        // a cap keeps getting that reason instead of a misleading rate limit
+        if limits.connect_rate > 0.0 {
+            if inner.buckets.len() >= MAX_TRACKED_BUCKETS {
+                // Buckets that have refilled carry no state worth keeping
+                inner.buckets.retain(|_, b| {
+                    b.tokens + now.duration_since(b.updated).as_secs_f64() * limits.connect_rate
+                        < limits.connect_burst
+                });
+            }
+
+            let bucket = inner.buckets.entry(ip).or_insert(Bucket {
+                tokens: limits.connect_burst,
+                updated: now,
+            });
+            let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+            bucket.tokens = (bucket.tokens + elapsed * limits.connect_rate).min(limits.connect_burst);
+            bucket.updated = now;
+
+            if bucket.tokens < 1.0 {
+                return Err(LimitRejection::RateLimited);
+            }
+            bucket.tokens -= 1.0;
+        }
+
+        inner.total += 1;
+        *inner.per_ip.entry(ip).or_insert(0) += 1;
+        *inner.per_device.entry(device_id.to_string()).or_insert(0) += 1;
+        Ok(())
+    }
+
+    fn release(&self, ip: &IpAddr, device_id: &str) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.total = inner.total.saturating_sub(1);
+
+        if let Some(count) = inner.per_ip.get_mut(ip) {
+            *count -= 1;
+            if *count == 0 {
+                inner.per_ip.remove(ip);
+            }
+        }
+        if let Some(count) = inner.per_device.get_mut(device_id) {
+            *count -= 1;
+            if *count == 0 {
+                inner.per_device.remove(device_id);
+            }
+        }
+    }
+
+    pub fn rejection_counts(&self) -> Vec<(&'static str, u64)> {
+        LimitRejection::ALL
+            .iter()
+            .map(|r| (r.as_str(), self.rejections[*r as usize].load(Ordering::Relaxed)))
+            .collect()
+    }
+}
+
+pub struct ConnectionPermit {
+    limiter: Arc<ConnectionLimiter>,
+    ip: IpAddr,
+    device_id: String,
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        self.limiter.release(&self.ip, &self.device_id);
+    }
+}
@@ -0,0 +1,83 @@
+use crate::domain::GeoInfo;
+use maxminddb::Reader;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::net::IpAddr;
+
+/// Offline IP geolocation from local MaxMind-format databases: `GEOIP_DB`
+/// (GeoLite2/GeoIP2 City or Country) and optionally `GEOIP_ASN_DB`. Without
+/// either, lookups return empty results.
+pub struct GeoIp {
+    readers: Vec<Reader<Vec<u8>>>,
+}
+
+// The subset of City, Country and ASN records we use
+#[derive(Deserialize)]
+struct GeoRecord {
+    country: Option<Place>,
+    city: Option<Place>,
+    autonomous_system_number: Option<u32>,
+    autonomous_system_organization: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Place {
+    iso_code: Option<String>,
+    names: Option<BTreeMap<String, String>>,
+}
+
+impl GeoIp {
+    pub fn from_env() -> Self {
+        let mut readers = Vec::new();
+        for var in ["GEOIP_DB", "GEOIP_ASN_DB"] {
+            let Ok(path) = env::var(var) else {
+                continue;
+            };
+            match Reader::open_readfile(&path) {
+                Ok(reader) => {
+                    tracing::info!(path, database_type = %reader.metadata().database_type, "GeoIP database loaded");
+                    readers.push(reader);
+                }
+                Err(e) => tracing::error!(path, var, error = %e, "Failed to open GeoIP database"),
+            }
+        }
+        if readers.is_empty() {
+            tracing::warn!("GEOIP_DB not set. Events will not be geolocated.");
+        }
+        Self { readers }
+    }
+
+    /// Merges what every database knows about `ip`; earlier databases win.
+    pub fn lookup(&self, ip: &str) -> GeoInfo {
+        let mut info = GeoInfo::default();
+        let Ok(addr) = ip.parse::<IpAddr>() else {
+            return info;
+        };
+
+        for reader in &self.readers {
+            let Ok(Some(record)) = reader
+                .lookup(addr)
+                .and_then(|result| result.decode::<GeoRecord>())
+            else {
+                continue;
+            };
+
+            if info.country.is_empty()
+                && let Some(code) = record.country.and_then(|c| c.iso_code)
+            {
+                info.country = code;
+            }
+            if info.city.is_empty()
+                && let Some(name) = record.city.and_then(|c| c.names?.remove("en"))
+            {
+                info.city = name;
+            }
+            if info.asn.is_none() {
+                info.asn = record.autonomous_system_number;
+                info.as_org = record.autonomous_system_organization.unwrap_or_default();
+            }
+        }
+        info
+    }
+}
@@ -0,0 +1,20 @@
+// Shape of a hash-chained log line. Kept free of crate dependencies so the
+// migrate_logs binary can include it and agree with the verifier.
+
+// Fewest CSV columns a chained record has, the last one being the hash.
+// Records later gained five user-agent and then four GeoIP columns.
+const MIN_CHAINED_COLUMNS: usize = 9;
+
+/// Splits a chained log line into its body and hash. Returns None for lines
+/// written before chaining was introduced, which never end in a hash.
+///
+/// The column count is only a lower bound: records written before every field
+/// was sanitized may hold stray commas, and the hash still covers them as written.
+pub fn split_hash(line: &str) -> Option<(&str, &str)> {
+    if line.split(',').count() < MIN_CHAINED_COLUMNS {
+        return None;
+    }
+    let (body, hash) = line.rsplit_once(',')?;
+    let is_hash = hash.len() == 64 && hash.bytes().all(|b| b.is_ascii_hexdigit());
+    is_hash.then_some((body, hash))
+}
@@ -0,0 +1,344 @@
+use crate::domain::{BrokenLink, IntegrityReport};
+use base64::prelude::*;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+
+pub use super::log_chain::split_hash;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Previous-hash value for the very first chained record.
+pub const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// Hash of a record body, chained to the record before it.
+pub fn chain_hash(prev_hash: &str, body: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(prev_hash.as_bytes());
+    hasher.update(b"\n");
+    hasher.update(body.as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckpointKind {
+    // Written on a timer while the log grows
+    Periodic,
+    // Written just before the log is archived; the chain continues from its hash
+    Reset,
+    // Written just before records are erased and the rest re-chained from its hash
+    Redaction,
+}
+
+impl CheckpointKind {
+    /// Whether the live log was legitimately rewritten at this checkpoint
+    pub fn restarts_chain(&self) -> bool {
+        matches!(self, CheckpointKind::Reset | CheckpointKind::Redaction)
+    }
+}
+
+/// Record count and head hash of the log at a point in time, kept in a
+/// separate file so that truncating the log can be detected.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub kind: CheckpointKind,
+    // Chained records since the last reset
+    pub records: u64,
+    pub hash: String,
+    pub timestamp: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+}
+
+impl Checkpoint {
+    fn signed_payload(&self) -> String {
+        let kind = match self.kind {
+            CheckpointKind::Periodic => "periodic",
+            CheckpointKind::Reset => "reset",
+            CheckpointKind::Redaction => "redaction",
+        };
+        format!("{}|{}|{}|{}", kind, self.records, self.hash, self.timestamp)
+    }
+}
+
+/// Signs checkpoints with `LOG_CHECKPOINT_KEY`.
+pub struct CheckpointSigner {
+    key: Vec<u8>,
+}
+
+impl CheckpointSigner {
+    pub fn from_env() -> Option<Self> {
+        match env::var("LOG_CHECKPOINT_KEY") {
+            Ok(key) if !key.is_empty() => Some(Self {
+                key: key.into_bytes(),
+            }),
+            _ => {
+                tracing::warn!("LOG_CHECKPOINT_KEY not set. Log checkpoints will be unsigned.");
+                None
+            }
+        }
+    }
+
+    fn mac(&self, checkpoint: &Checkpoint) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.key).expect("HMAC accepts keys of any length");
+        mac.update(checkpoint.signed_payload().as_bytes());
+        mac
+    }
+
+    pub fn sign(&self, checkpoint: &mut Checkpoint) {
+        let signature = self.mac(checkpoint).finalize().into_bytes();
+        checkpoint.signature = Some(BASE64_URL_SAFE_NO_PAD.encode(signature));
+    }
+
+    pub fn verify(&self, checkpoint: &Checkpoint) -> bool {
+        checkpoint
+            .signature
+            .as_ref()
+            .and_then(|s| BASE64_URL_SAFE_NO_PAD.decode(s).ok())
+            .is_some_and(|raw| self.mac(checkpoint).verify_slice(&raw).is_ok())
+    }
+}
+
+pub fn checkpoints_path(log_path: &str) -> String {
+    format!("{}.checkpoints", log_path)
+}
+
+pub fn append_checkpoint(
+    path: &str,
+    mut checkpoint: Checkpoint,
+    signer: Option<&CheckpointSigner>,
+) -> std::io::Result<()> {
+    if let Some(signer) = signer {
+        signer.sign(&mut checkpoint);
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", serde_json::to_string(&checkpoint)?)
+}
+
+fn read_checkpoints(path: &str) -> Result<Vec<Checkpoint>, usize> {
+    let content = fs::read_to_string(path).unwrap_or_default();
+    content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| serde_json::from_str(line).map_err(|_| i + 1))
+        .collect()
+}
+
+/// Where appending should resume: the head hash and record count since the last reset.
+pub fn restore_head(log_path: &str) -> (String, u64) {
+    let checkpoints = read_checkpoints(&checkpoints_path(log_path)).unwrap_or_default();
+    let mut head = checkpoints
+        .iter()
+        .rev()
+        .find(|c| c.kind.restarts_chain())
+        .map(|c| c.hash.clone())
+        .unwrap_or_else(|| GENESIS_HASH.to_string());
+
+    let mut records = 0;
+    let content = fs::read_to_string(log_path).unwrap_or_default();
+    for (_, hash) in content.lines().filter_map(split_hash) {
+        head = hash.to_string();
+        records += 1;
+    }
+    (head, records)
+}
+
+/// Walks the hash chain of `log_path` and cross-checks it against the checkpoints.
+pub fn verify(log_path: &str, signer: Option<&CheckpointSigner>) -> IntegrityReport {
+    let mut report = IntegrityReport {
+        intact: true,
+        records: 0,
+        legacy_records: 0,
+        checkpoints: 0,
+        unsigned_checkpoints: 0,
+        first_broken: None,
+    };
+    fn fail(report: &mut IntegrityReport, line: usize, reason: String) {
+        report.intact = false;
+        report.first_broken = Some(BrokenLink { line, reason });
+    }
+
+    let checkpoints = match read_checkpoints(&checkpoints_path(log_path)) {
+        Ok(c) => c,
+        Err(line) => {
+            fail(&mut report, 0, format!("Checkpoint file is corrupt at line {}", line));
+            return report;
+        }
+    };
+
+    for checkpoint in &checkpoints {
+        match (&checkpoint.signature, signer) {
+            // With a key, an unsigned checkpoint that restarts the chain could
+            // have been forged to cover a rewritten log. Unsigned periodic ones
+            // (from before the key was set) only ever add constraints.
+            (None, Some(_)) if checkpoint.kind.restarts_chain() => {
+                fail(
+                    &mut report,
+                    0,
+                    format!("Checkpoint at {} restarts the chain but is unsigned", checkpoint.timestamp),
+                );
+                return report;
+            }
+            (None, _) | (Some(_), None) => report.unsigned_checkpoints += 1,
+            (Some(_), Some(signer)) if !signer.verify(checkpoint) => {
+                fail(
+                    &mut report,
+                    0,
+                    format!("Checkpoint at {} has an invalid signature", checkpoint.timestamp),
+                );
+                return report;
+            }
+            _ => {}
+        }
+    }
+
+    // Only checkpoints since the last reset describe the current file
+    let last_reset = checkpoints.iter().rposition(|c| c.kind.restarts_chain());
+    let (mut prev, current) = match last_reset {
+        Some(i) => (checkpoints[i].hash.clone(), &checkpoints[i + 1..]),
+        None => (GENESIS_HASH.to_string(), &checkpoints[..]),
+    };
+
+    // (line number, hash) of every chained record
+    let mut chain: Vec<(usize, String)> = Vec::new();
+    let content = fs::read_to_string(log_path).unwrap_or_default();
+    for (i, line) in content.lines().enumerate() {
+        let line_no = i + 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match split_hash(line) {
+            Some((body, hash)) => {
+                if chain_hash(&prev, body) != hash {
+                    let reason = if chain.is_empty() {
+                        "First record does not chain to the expected start of the log".to_string()
+                    } else {
+                        "Record was modified or does not follow the previous record".to_string()
+                    };
+                    fail(&mut report, line_no, reason);
+                    return report;
+                }
+                prev = hash.to_string();
+                chain.push((line_no, prev.clone()));
+                report.records += 1;
+            }
+            // Lines predating the chain are tolerated only at the very start
+            None if chain.is_empty() && last_reset.is_none() => report.legacy_records += 1,
+            None => {
+                fail(&mut report, line_no, "Record has no hash".to_string());
+                return report;
+            }
+        }
+    }
+
+    for checkpoint in current {
+        report.checkpoints += 1;
+        if checkpoint.records == 0 {
+            continue;
+        }
+        match chain.get(checkpoint.records as usize - 1) {
+            None => {
+                fail(
+                    &mut report,
+                    0,
+                    format!(
+                        "Log was truncated: checkpoint at {} covers {} records, only {} present",
+                        checkpoint.timestamp,
+                        checkpoint.records,
+                        chain.len()
+                    ),
+                );
+                return report;
+            }
+            Some((line_no, hash)) if *hash != checkpoint.hash => {
+                fail(
+                    &mut report,
+                    *line_no,
+                    format!("Record does not match checkpoint at {}", checkpoint.timestamp),
+                );
+                return report;
+            }
+            _ => {}
+        }
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Fresh log path under the temp dir; the checkpoint file sits beside it
+    fn temp_log(name: &str) -> String {
+        let dir = env::temp_dir().join(format!("log-integrity-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("server.log").to_string_lossy().into_owned();
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(checkpoints_path(&path));
+        path
+    }
+
+    // Appends chained records and returns the new head hash
+    fn write_records(path: &str, mut prev: String, bodies: &[&str]) -> String {
+        let mut file = OpenOptions::new().create(true).append(true).open(path).unwrap();
+        for body in bodies {
+            prev = chain_hash(&prev, body);
+            writeln!(file, "{},{}", body, prev).unwrap();
+        }
+        prev
+    }
+
+    fn checkpoint(kind: CheckpointKind, records: u64, hash: &str) -> Checkpoint {
+        Checkpoint {
+            kind,
+            records,
+            hash: hash.to_string(),
+            timestamp: "2026-01-01 00:00:00 +0000".to_string(),
+            signature: None,
+        }
+    }
+
+    const BODY: &str = "2026-01-01 00:00:00 +0000,127.0.0.1,dev,id,CONNECTED,,,,";
+
+    #[test]
+    fn forged_unsigned_reset_fails_verification() {
+        let signer = CheckpointSigner { key: b"test-key".to_vec() };
+        let path = temp_log("forged-reset");
+        let head = write_records(&path, GENESIS_HASH.to_string(), &[BODY, BODY]);
+        let checkpoints = checkpoints_path(&path);
+        append_checkpoint(&checkpoints, checkpoint(CheckpointKind::Periodic, 2, &head), Some(&signer))
+            .unwrap();
+        assert!(verify(&path, Some(&signer)).intact);
+
+        // Rewrite the log from scratch behind an unsigned reset
+        fs::remove_file(&path).unwrap();
+        write_records(&path, GENESIS_HASH.to_string(), &[BODY]);
+        append_checkpoint(&checkpoints, checkpoint(CheckpointKind::Reset, 0, GENESIS_HASH), None)
+            .unwrap();
+
+        let report = verify(&path, Some(&signer));
+        assert!(!report.intact);
+        assert!(report.first_broken.unwrap().reason.contains("unsigned"));
+        // Without a key there is nothing to check the reset against
+        assert!(verify(&path, None).intact);
+    }
+
+    #[test]
+    fn unsigned_periodic_checkpoints_are_counted() {
+        let signer = CheckpointSigner { key: b"test-key".to_vec() };
+        let path = temp_log("unsigned-periodic");
+        let head = write_records(&path, GENESIS_HASH.to_string(), &[BODY]);
+        append_checkpoint(&checkpoints_path(&path), checkpoint(CheckpointKind::Periodic, 1, &head), None)
+            .unwrap();
+
+        let report = verify(&path, Some(&signer));
+        assert!(report.intact);
+        assert_eq!(report.unsigned_checkpoints, 1);
+    }
+}
@@ -0,0 +1,268 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+// Upper bounds (seconds) of the session duration histogram buckets
+const SESSION_BUCKETS: &[f64] = &[
+    1.0, 5.0, 15.0, 60.0, 300.0, 900.0, 1800.0, 3600.0, 4.0 * 3600.0, 24.0 * 3600.0,
+];
+
+/// Counters and histograms served on `/metrics`. Gauges such as active
+/// connections or memory are read from the app state at scrape time instead.
+pub struct Metrics {
+    connects: CounterVec,
+    disconnects: CounterVec,
+    session_duration: Histogram,
+    send_failures: CounterVec,
+    lagged_messages: CounterVec,
+    log_write_errors: AtomicU64,
+    wakatime_fetches: CounterVec,
+    // Failed WakaTime fetches since the last successful one
+    wakatime_failure_streak: AtomicU64,
+    webhook_deliveries: CounterVec,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self {
+            connects: CounterVec::default(),
+            disconnects: CounterVec::default(),
+            session_duration: Histogram::new(SESSION_BUCKETS),
+            send_failures: CounterVec::default(),
+            lagged_messages: CounterVec::default(),
+            log_write_errors: AtomicU64::new(0),
+            wakatime_fetches: CounterVec::default(),
+            wakatime_failure_streak: AtomicU64::new(0),
+            webhook_deliveries: CounterVec::default(),
+        }
+    }
+
+    pub fn connected(&self, endpoint: &str) {
+        self.connects.inc(&[endpoint], 1);
+    }
+
+    /// `action` is the logged event: DISCONNECTED or KICKED.
+    pub fn disconnected(&self, endpoint: &str, action: &str) {
+        self.disconnects.inc(&[endpoint, &action.to_lowercase()], 1);
+    }
+
+    pub fn session_ended(&self, duration: Duration) {
+        self.session_duration.observe(duration.as_secs_f64());
+    }
+
+    pub fn send_failed(&self, endpoint: &str) {
+        self.send_failures.inc(&[endpoint], 1);
+    }
+
+    /// A socket's broadcast receiver fell behind and skipped `skipped` messages.
+    pub fn lagged(&self, endpoint: &str, skipped: u64) {
+        self.lagged_messages.inc(&[endpoint], skipped);
+    }
+
+    pub fn log_write_failed(&self) {
+        self.log_write_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// `kind` is the WakaTime resource fetched: all_time or summaries.
+    pub fn wakatime_fetch(&self, kind: &str, ok: bool) {
+        let outcome = if ok { "success" } else { "error" };
+        self.wakatime_fetches.inc(&[kind, outcome], 1);
+        if ok {
+            self.wakatime_failure_streak.store(0, Ordering::Relaxed);
+        } else {
+            self.wakatime_failure_streak.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn wakatime_failure_streak(&self) -> u64 {
+        self.wakatime_failure_streak.load(Ordering::Relaxed)
+    }
+
+    /// `outcome` of one attempt: delivered, retry, failed or dropped.
+    pub fn webhook_delivery(&self, outcome: &str) {
+        self.webhook_deliveries.inc(&[outcome], 1);
+    }
+
+    pub fn write(&self, out: &mut Exposition) {
+        out.family("counter_connects_total", "counter", "WebSocket connections opened");
+        self.connects.write(out, "counter_connects_total", &["endpoint"]);
+
+        out.family("counter_disconnects_total", "counter", "WebSocket connections closed");
+        self.disconnects
+            .write(out, "counter_disconnects_total", &["endpoint", "reason"]);
+
+        out.family(
+            "counter_session_duration_seconds",
+            "histogram",
+            "Length of client sessions",
+        );
+        self.session_duration
+            .write(out, "counter_session_duration_seconds");
+
+        out.family(
+            "counter_ws_send_failures_total",
+            "counter",
+            "WebSocket messages that could not be sent",
+        );
+        self.send_failures
+            .write(out, "counter_ws_send_failures_total", &["endpoint"]);
+
+        out.family(
+            "counter_ws_lagged_messages_total",
+            "counter",
+            "Broadcast messages skipped by sockets that fell behind",
+        );
+        self.lagged_messages
+            .write(out, "counter_ws_lagged_messages_total", &["endpoint"]);
+
+        out.family(
+            "counter_log_write_errors_total",
+            "counter",
+            "Events that could not be written to the event log",
+        );
+        out.sample(
+            "counter_log_write_errors_total",
+            &[],
+            self.log_write_errors.load(Ordering::Relaxed),
+        );
+
+        out.family(
+            "counter_wakatime_fetches_total",
+            "counter",
+            "WakaTime API fetches by outcome",
+        );
+        self.wakatime_fetches
+            .write(out, "counter_wakatime_fetches_total", &["kind", "outcome"]);
+
+        out.family(
+            "counter_wakatime_failure_streak",
+            "gauge",
+            "WakaTime fetches that failed since the last successful one",
+        );
+        out.sample(
+            "counter_wakatime_failure_streak",
+            &[],
+            self.wakatime_failure_streak(),
+        );
+
+        out.family(
+            "counter_webhook_deliveries_total",
+            "counter",
+            "Webhook delivery attempts by outcome",
+        );
+        self.webhook_deliveries
+            .write(out, "counter_webhook_deliveries_total", &["outcome"]);
+    }
+}
+
+/// Prometheus text format (version 0.0.4) writer.
+#[derive(Default)]
+pub struct Exposition {
+    body: String,
+}
+
+impl Exposition {
+    pub const CONTENT_TYPE: &'static str = "text/plain; version=0.0.4; charset=utf-8";
+
+    pub fn family(&mut self, name: &str, kind: &str, help: &str) {
+        let _ = writeln!(self.body, "# HELP {} {}", name, help);
+        let _ = writeln!(self.body, "# TYPE {} {}", name, kind);
+    }
+
+    pub fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: impl std::fmt::Display) {
+        self.body.push_str(name);
+        if !labels.is_empty() {
+            let labels: Vec<String> = labels
+                .iter()
+                .map(|(k, v)| format!("{}=\"{}\"", k, escape(v)))
+                .collect();
+            let _ = write!(self.body, "{{{}}}", labels.join(","));
+        }
+        let _ = writeln!(self.body, " {}", value);
+    }
+
+    pub fn finish(self) -> String {
+        self.body
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+// Counter keyed by its label values, in the order given to `write`
+#[derive(Default)]
+struct CounterVec {
+    values: Mutex<BTreeMap<Vec<String>, u64>>,
+}
+
+impl CounterVec {
+    fn inc(&self, labels: &[&str], by: u64) {
+        let key = labels.iter().map(|l| l.to_string()).collect();
+        *self.values.lock().unwrap().entry(key).or_insert(0) += by;
+    }
+
+    fn write(&self, out: &mut Exposition, name: &str, label_names: &[&str]) {
+        for (values, count) in self.values.lock().unwrap().iter() {
+            let labels: Vec<(&str, &str)> = label_names
+                .iter()
+                .copied()
+                .zip(values.iter().map(String::as_str))
+                .collect();
+            out.sample(name, &labels, count);
+        }
+    }
+}
+
+struct Histogram {
+    bounds: &'static [f64],
+    // Per bucket (not cumulative), plus a final +Inf bucket
+    counts: Mutex<(Vec<u64>, f64)>,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Self {
+        Self {
+            bounds,
+            counts: Mutex::new((vec![0; bounds.len() + 1], 0.0)),
+        }
+    }
+
+    fn observe(&self, value: f64) {
+        let bucket = self
+            .bounds
+            .iter()
+            .position(|b| value <= *b)
+            .unwrap_or(self.bounds.len());
+        let mut counts = self.counts.lock().unwrap();
+        counts.0[bucket] += 1;
+        counts.1 += value;
+    }
+
+    fn write(&self, out: &mut Exposition, name: &str) {
+        let (counts, sum) = self.counts.lock().unwrap().clone();
+        let bucket_name = format!("{}_bucket", name);
+        let mut cumulative = 0;
+        for (i, count) in counts.iter().enumerate() {
+            cumulative += count;
+            let le = match self.bounds.get(i) {
+                Some(bound) => bound.to_string(),
+                None => "+Inf".to_string(),
+            };
+            out.sample(&bucket_name, &[("le", &le)], cumulative);
+        }
+        out.sample(&format!("{}_sum", name), &[], sum);
+        out.sample(&format!("{}_count", name), &[], cumulative);
+    }
+}
@@ -1,2 +1,13 @@
+pub mod alerting;
+pub mod bot_detection;
+pub mod client_token;
+pub mod connection_limiter;
+pub mod geoip;
+pub mod log_chain;
+pub mod log_integrity;
+pub mod metrics;
+pub mod privacy;
+pub mod system_sampler;
+pub mod user_agent;
 pub mod wakatime;
-
+pub mod webhooks;
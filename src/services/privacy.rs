@@ -0,0 +1,71 @@
+use hmac::{Hmac, Mac};
+use ipnet::{IpNet, Ipv4Net, Ipv6Net};
+use sha2::Sha256;
+use std::env;
+use std::net::IpAddr;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// How client IPs are stored in the event log, set by `IP_PRIVACY_MODE`.
+#[derive(Clone)]
+pub enum IpPrivacy {
+    // Full addresses (default)
+    Off,
+    // Zero the host part: IPv4 to /24, IPv6 to /48
+    Truncate,
+    // Keyed hash (`IP_HASH_KEY`): the same IP always maps to the same token,
+    // but the address can't be recovered without the key
+    Hash { key: Vec<u8> },
+}
+
+impl IpPrivacy {
+    pub fn from_env() -> Self {
+        match env::var("IP_PRIVACY_MODE").unwrap_or_default().as_str() {
+            "truncate" => IpPrivacy::Truncate,
+            "hash" => match env::var("IP_HASH_KEY") {
+                Ok(key) if !key.is_empty() => IpPrivacy::Hash {
+                    key: key.into_bytes(),
+                },
+                _ => {
+                    tracing::warn!("IP_PRIVACY_MODE=hash requires IP_HASH_KEY. Truncating IPs instead.");
+                    IpPrivacy::Truncate
+                }
+            },
+            _ => IpPrivacy::Off,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            IpPrivacy::Off => "off",
+            IpPrivacy::Truncate => "truncate",
+            IpPrivacy::Hash { .. } => "hash",
+        }
+    }
+
+    /// The form of `ip` that gets written to the log. Values that aren't IPs
+    /// (e.g. already anonymized ones) are returned unchanged.
+    pub fn apply(&self, ip: &str) -> String {
+        let Ok(addr) = ip.parse::<IpAddr>() else {
+            return ip.to_string();
+        };
+
+        match self {
+            IpPrivacy::Off => ip.to_string(),
+            IpPrivacy::Truncate => {
+                let net = match addr {
+                    IpAddr::V4(v4) => IpNet::V4(Ipv4Net::new(v4, 24).unwrap()),
+                    IpAddr::V6(v6) => IpNet::V6(Ipv6Net::new(v6, 48).unwrap()),
+                };
+                net.network().to_string()
+            }
+            IpPrivacy::Hash { key } => {
+                let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any length");
+                mac.update(addr.to_string().as_bytes());
+                let digest = mac.finalize().into_bytes();
+                let hex: String = digest[..16].iter().map(|b| format!("{:02x}", b)).collect();
+                format!("h-{}", hex)
+            }
+        }
+    }
+}
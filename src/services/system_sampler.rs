@@ -0,0 +1,134 @@
+use crate::domain::{DiskUsage, LoadAverage, ProcessUsage, SystemMetrics};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use sysinfo::{Disks, Networks, Pid, ProcessRefreshKind, ProcessesToUpdate, System};
+use tokio::sync::watch;
+
+/// Sole owner of the sysinfo handles. Once spawned it samples on its own
+/// thread and publishes each reading as an immutable snapshot; readers only
+/// ever look at the latest one, so nothing else locks or refreshes sysinfo.
+pub struct SystemSampler {
+    system: System,
+    networks: Networks,
+    disks: Disks,
+    pid: Option<Pid>,
+    started_at: Instant,
+    // Network counters cover the time since this
+    sampled_at: Instant,
+}
+
+impl Default for SystemSampler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SystemSampler {
+    pub fn new() -> Self {
+        let mut system = System::new_all();
+        system.refresh_all();
+        Self {
+            system,
+            networks: Networks::new_with_refreshed_list(),
+            disks: Disks::new_with_refreshed_list(),
+            pid: sysinfo::get_current_pid().ok(),
+            started_at: Instant::now(),
+            sampled_at: Instant::now(),
+        }
+    }
+
+    /// Publishes a first snapshot straight away, then a fresh one every
+    /// `interval` from a background thread. The thread stops once every
+    /// receiver is gone.
+    pub fn spawn(mut self, interval: Duration) -> watch::Receiver<Arc<SystemMetrics>> {
+        // CPU usage is measured between two refreshes; give the first one a baseline
+        std::thread::sleep(sysinfo::MINIMUM_CPU_UPDATE_INTERVAL);
+        let (tx, rx) = watch::channel(Arc::new(self.sample()));
+
+        std::thread::Builder::new()
+            .name("system-sampler".to_string())
+            .spawn(move || {
+                loop {
+                    std::thread::sleep(interval);
+                    if tx.send(Arc::new(self.sample())).is_err() {
+                        break;
+                    }
+                }
+            })
+            .expect("Failed to start the system sampler thread");
+
+        rx
+    }
+
+    fn sample(&mut self) -> SystemMetrics {
+        self.system.refresh_cpu_all();
+        self.system.refresh_memory();
+
+        let elapsed = self.sampled_at.elapsed().as_secs_f64().max(0.001);
+        self.sampled_at = Instant::now();
+        self.networks.refresh(true);
+        let (rx, tx) = self
+            .networks
+            .values()
+            .fold((0, 0), |(rx, tx), n| (rx + n.received(), tx + n.transmitted()));
+
+        self.disks.refresh(true);
+        let disks = self
+            .disks
+            .list()
+            .iter()
+            .map(|d| DiskUsage {
+                mount_point: d.mount_point().to_string_lossy().to_string(),
+                file_system: d.file_system().to_string_lossy().to_string(),
+                total: d.total_space(),
+                available: d.available_space(),
+            })
+            .collect();
+
+        let load = System::load_average();
+        SystemMetrics {
+            uptime_secs: self.started_at.elapsed().as_secs(),
+            cpu: self.system.global_cpu_usage(),
+            cpu_cores: self.system.cpus().iter().map(|c| c.cpu_usage()).collect(),
+            load: LoadAverage {
+                one: load.one,
+                five: load.five,
+                fifteen: load.fifteen,
+            },
+            ram_used: self.system.used_memory(),
+            ram_total: self.system.total_memory(),
+            swap_used: self.system.used_swap(),
+            swap_total: self.system.total_swap(),
+            disks,
+            net_rx: (rx as f64 / elapsed) as u64,
+            net_tx: (tx as f64 / elapsed) as u64,
+            process: self.process_usage(),
+        }
+    }
+
+    fn process_usage(&mut self) -> ProcessUsage {
+        let Some(pid) = self.pid else {
+            return ProcessUsage::default();
+        };
+        self.system.refresh_processes_specifics(
+            ProcessesToUpdate::Some(&[pid]),
+            true,
+            ProcessRefreshKind::nothing()
+                .with_cpu()
+                .with_memory()
+                .with_tasks(),
+        );
+
+        self.system
+            .process(pid)
+            .map(|process| ProcessUsage {
+                cpu_percent: process.cpu_usage(),
+                memory_bytes: process.memory(),
+                virtual_memory_bytes: process.virtual_memory(),
+                // The task list leaves out the main thread
+                threads: process.tasks().map(|tasks| tasks.len() + 1),
+                open_files: process.open_files(),
+            })
+            .unwrap_or_default()
+    }
+}
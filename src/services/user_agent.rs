@@ -0,0 +1,159 @@
+use crate::domain::{DeviceClass, UserAgentInfo};
+
+// Crawlers and scripted HTTP clients; matched case-insensitively
+const BOT_MARKERS: &[&str] = &[
+    "bot", "crawl", "spider", "slurp", "headless", "lighthouse", "facebookexternalhit",
+    "curl/", "wget/", "python-", "python/", "websockets/", "go-http-client", "okhttp", "java/",
+    "libwww", "httpclient",
+];
+
+// Checked in order: several browsers also advertise Chrome or Safari
+const BROWSERS: &[(&str, &str)] = &[
+    ("Edg/", "Edge"),
+    ("EdgA/", "Edge"),
+    ("EdgiOS/", "Edge"),
+    ("OPR/", "Opera"),
+    ("SamsungBrowser/", "Samsung Internet"),
+    ("Firefox/", "Firefox"),
+    ("FxiOS/", "Firefox"),
+    ("CriOS/", "Chrome"),
+    ("Chromium/", "Chromium"),
+    ("Chrome/", "Chrome"),
+    ("curl/", "curl"),
+    ("Wget/", "Wget"),
+    ("python-requests/", "Python Requests"),
+    ("Go-http-client/", "Go HTTP Client"),
+    ("okhttp/", "OkHttp"),
+];
+
+/// Parses a raw `User-Agent` header. Also accepts the shortened device
+/// strings of older log records, which usually still name the OS.
+pub fn parse(user_agent: &str) -> UserAgentInfo {
+    let lower = user_agent.to_lowercase();
+    let is_bot = BOT_MARKERS.iter().any(|marker| lower.contains(marker));
+
+    let (browser, browser_version) = browser(user_agent, is_bot);
+    let (os, os_version) = os(user_agent);
+
+    let device_class = if is_bot {
+        DeviceClass::Bot
+    } else if user_agent.contains("iPad")
+        || user_agent.contains("Tablet")
+        // Android tablets omit "Mobile"; shortened legacy strings can't tell
+        || (os == "Android" && user_agent.contains("Safari/") && !user_agent.contains("Mobile"))
+    {
+        DeviceClass::Tablet
+    } else if user_agent.contains("Mobi") || user_agent.contains("iPhone") || os == "Android" {
+        DeviceClass::Mobile
+    } else if matches!(os, "Windows" | "macOS" | "Linux" | "ChromeOS") {
+        DeviceClass::Desktop
+    } else {
+        DeviceClass::Unknown
+    };
+
+    UserAgentInfo {
+        browser: browser.to_string(),
+        browser_version,
+        os: os.to_string(),
+        os_version,
+        device_class,
+    }
+}
+
+fn browser(ua: &str, is_bot: bool) -> (String, String) {
+    for (token, family) in BROWSERS {
+        if let Some(version) = version_after(ua, token) {
+            return (family.to_string(), major(&version));
+        }
+    }
+    if ua.contains("Safari/") && !ua.contains("Android") {
+        let version = version_after(ua, "Version/").unwrap_or_default();
+        return ("Safari".to_string(), major(&version));
+    }
+    if ua.contains("Trident/") || ua.contains("MSIE ") {
+        return ("Internet Explorer".to_string(), String::new());
+    }
+    if is_bot && let Some((name, version)) = bot_name(ua) {
+        return (name, major(&version));
+    }
+    ("Other".to_string(), String::new())
+}
+
+fn os(ua: &str) -> (&'static str, String) {
+    if let Some(nt) = version_after(ua, "Windows NT ") {
+        let version = match nt.as_str() {
+            "10.0" => "10",
+            "6.3" => "8.1",
+            "6.2" => "8",
+            "6.1" => "7",
+            "6.0" => "Vista",
+            "5.1" | "5.2" => "XP",
+            _ => "",
+        };
+        return ("Windows", version.to_string());
+    }
+    if ua.contains("Windows") {
+        return ("Windows", String::new());
+    }
+    for token in ["iPhone OS ", "CPU OS "] {
+        if let Some(version) = version_after(ua, token) {
+            return ("iOS", version);
+        }
+    }
+    if ua.contains("iPhone") || ua.contains("iPad") {
+        return ("iOS", String::new());
+    }
+    if let Some(version) = version_after(ua, "Android ") {
+        return ("Android", major(&version));
+    }
+    if ua.contains("Android") {
+        return ("Android", String::new());
+    }
+    if ua.contains("CrOS") {
+        return ("ChromeOS", String::new());
+    }
+    if let Some(version) = version_after(ua, "Mac OS X ") {
+        let minor: Vec<&str> = version.split('.').take(2).collect();
+        return ("macOS", minor.join("."));
+    }
+    if ua.contains("Macintosh") || ua.contains("Mac OS X") {
+        return ("macOS", String::new());
+    }
+    if ua.contains("Linux") || ua.contains("X11") {
+        return ("Linux", String::new());
+    }
+    ("Other", String::new())
+}
+
+// The dotted version right after `token`; underscores (as in iOS versions) become dots
+fn version_after(ua: &str, token: &str) -> Option<String> {
+    let start = ua.find(token)? + token.len();
+    let version: String = ua[start..]
+        .chars()
+        .take_while(|c| c.is_ascii_digit() || matches!(c, '.' | '_'))
+        .map(|c| if c == '_' { '.' } else { c })
+        .collect();
+    let version = version.trim_end_matches('.').to_string();
+    (!version.is_empty()).then_some(version)
+}
+
+fn major(version: &str) -> String {
+    version.split('.').next().unwrap_or_default().to_string()
+}
+
+// "Googlebot/2.1" style product tokens in crawler user agents
+fn bot_name(ua: &str) -> Option<(String, String)> {
+    ua.split(|c: char| c.is_whitespace() || matches!(c, ';' | '(' | ')' | ','))
+        .filter_map(|token| token.split_once('/'))
+        .find(|(name, _)| {
+            let name = name.to_lowercase();
+            ["bot", "crawl", "spider", "slurp"].iter().any(|m| name.contains(m))
+        })
+        .map(|(name, version)| {
+            let name = name
+                .chars()
+                .filter(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+                .collect();
+            (name, version_after(&format!("/{}", version), "/").unwrap_or_default())
+        })
+}
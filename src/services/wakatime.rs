@@ -45,12 +45,13 @@ impl WakatimeService {
 
     // ... existing fetch_all_time_stats() ...
 
+    #[tracing::instrument(name = "wakatime.fetch_summaries", skip_all, err(level = "warn", Display))]
     pub async fn fetch_summaries(&self) -> Result<Vec<Summary>, Box<dyn Error + Send + Sync>> {
         let cache_file = "wakatime_summaries_cache.json";
         let cache_duration = Duration::from_secs(3600); // 1 hour
 
         if let Some(resp) = Self::load_from_cache::<SummariesResponse>(cache_file, cache_duration) {
-             println!("Loaded WakaTime summaries from cache");
+             tracing::debug!("Loaded WakaTime summaries from cache");
              return Ok(resp.data);
         }
 
@@ -68,7 +69,7 @@ impl WakatimeService {
         
         let api_key_auth = BASE64_STANDARD.encode(format!("{}:", self.api_key));
         
-        println!("Fetching WakaTime summaries from API...");
+        tracing::info!("Fetching WakaTime summaries from API");
         let resp = self.client
             .get(&url)
             .header("Authorization", format!("Basic {}", api_key_auth))
@@ -83,7 +84,7 @@ impl WakatimeService {
         
         // Save to cache
         if let Err(e) = fs::write(cache_file, &text) {
-             println!("Failed to write summaries cache: {}", e);
+             tracing::warn!(error = %e, "Failed to write summaries cache");
         }
 
         let response = serde_json::from_str::<SummariesResponse>(&text)?;
@@ -168,12 +169,13 @@ impl WakatimeService {
         serde_json::from_str(&contents).ok()
     }
 
+    #[tracing::instrument(name = "wakatime.fetch_all_time_stats", skip_all, err(level = "warn", Display))]
     pub async fn fetch_all_time_stats(&self) -> Result<AllTimeStats, Box<dyn Error + Send + Sync>> {
         let cache_file = "wakatime_cache.json";
         let cache_duration = Duration::from_secs(3600); // 1 hour
 
         if let Some(stats) = Self::load_from_cache(cache_file, cache_duration) {
-            println!("Loaded WakaTime stats from cache");
+            tracing::debug!("Loaded WakaTime stats from cache");
             return Ok(stats);
         }
 
@@ -185,7 +187,7 @@ impl WakatimeService {
         
         let api_key_auth = BASE64_STANDARD.encode(format!("{}:", self.api_key));
         
-        println!("Fetching WakaTime stats from API...");
+        tracing::info!("Fetching WakaTime stats from API");
         let resp = self.client
             .get(&url)
             .header("Authorization", format!("Basic {}", api_key_auth))
@@ -200,7 +202,7 @@ impl WakatimeService {
         
         // Save to cache
         if let Err(e) = fs::write(cache_file, &text) {
-             println!("Failed to write cache: {}", e);
+             tracing::warn!(error = %e, "Failed to write cache");
         }
 
         let stats = serde_json::from_str(&text)?;
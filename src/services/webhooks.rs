@@ -0,0 +1,218 @@
+use crate::domain::repositories::{WebhookDeliveryRepository, WebhookSubscriptionRepository};
+use crate::domain::{DeliveryStatus, WebhookDelivery, WebhookEvent, WebhookSubscription};
+use crate::services::metrics::Metrics;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+type HmacSha256 = Hmac<Sha256>;
+
+// Seconds before each retry; the delivery fails after the last one
+const RETRY_DELAYS: &[i64] = &[5, 30, 120, 600, 1800, 3600];
+// A claimed delivery is retried after this if its attempt never reported back.
+// Must be longer than the request timeout.
+const LEASE_SECS: i64 = 60;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+// Queue poll interval when nothing wakes the worker
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Works through the persistent delivery queue. Each request carries:
+/// - `X-Counter-Event` and `X-Counter-Delivery` (the delivery id, stable across retries)
+/// - `X-Counter-Timestamp`, unix seconds of this attempt
+/// - `X-Counter-Signature: sha256=<hex>`, an HMAC-SHA256 keyed with the
+///   subscription secret over `<timestamp>.<body>`
+pub struct WebhookDispatcher {
+    client: reqwest::Client,
+    subscriptions: Arc<dyn WebhookSubscriptionRepository>,
+    deliveries: Arc<dyn WebhookDeliveryRepository>,
+    metrics: Arc<Metrics>,
+    // Events waiting to be written to the delivery queue by `run`
+    published_tx: mpsc::UnboundedSender<Published>,
+    published_rx: Mutex<Option<mpsc::UnboundedReceiver<Published>>>,
+}
+
+struct Published {
+    event: WebhookEvent,
+    body: String,
+    subscriptions: Vec<WebhookSubscription>,
+}
+
+impl WebhookDispatcher {
+    pub fn new(
+        subscriptions: Arc<dyn WebhookSubscriptionRepository>,
+        deliveries: Arc<dyn WebhookDeliveryRepository>,
+        metrics: Arc<Metrics>,
+    ) -> Self {
+        let (published_tx, published_rx) = mpsc::unbounded_channel();
+        Self {
+            client: reqwest::Client::builder()
+                .timeout(REQUEST_TIMEOUT)
+                .build()
+                .unwrap_or_default(),
+            subscriptions,
+            deliveries,
+            metrics,
+            published_tx,
+            published_rx: Mutex::new(Some(published_rx)),
+        }
+    }
+
+    /// Hands `body` over for delivery to each of `subscriptions`. It is queued
+    /// (and persisted) by `run`, so the caller never waits on the disk.
+    pub fn publish(&self, event: WebhookEvent, body: String, subscriptions: Vec<WebhookSubscription>) {
+        let _ = self.published_tx.send(Published {
+            event,
+            body,
+            subscriptions,
+        });
+    }
+
+    /// Runs forever, queueing published events and sending each due delivery
+    /// in its own task.
+    pub async fn run(self: Arc<Self>) {
+        let mut published = self
+            .published_rx
+            .lock()
+            .unwrap()
+            .take()
+            .expect("Webhook dispatcher is already running");
+
+        loop {
+            while let Ok(item) = published.try_recv() {
+                self.enqueue(item).await;
+            }
+
+            let now = chrono::Utc::now().timestamp();
+            match self.queue(move |q| q.claim_due(now, LEASE_SECS)).await {
+                Ok(due) => {
+                    for delivery in due {
+                        let dispatcher = self.clone();
+                        tokio::spawn(async move { dispatcher.attempt(delivery).await });
+                    }
+                }
+                Err(e) => tracing::error!(error = %e, "Failed to claim webhook deliveries"),
+            }
+
+            tokio::select! {
+                Some(item) = published.recv() => self.enqueue(item).await,
+                _ = tokio::time::sleep(POLL_INTERVAL) => {}
+            }
+        }
+    }
+
+    // Every queue change rewrites the queue file, so it runs on the blocking
+    // pool rather than stalling the runtime thread it was called from
+    async fn queue<T, F>(&self, f: F) -> Result<T, Box<dyn Error + Send + Sync>>
+    where
+        T: Send + 'static,
+        F: FnOnce(&dyn WebhookDeliveryRepository) -> Result<T, Box<dyn Error + Send + Sync>>
+            + Send
+            + 'static,
+    {
+        let deliveries = self.deliveries.clone();
+        tokio::task::spawn_blocking(move || f(deliveries.as_ref())).await?
+    }
+
+    async fn enqueue(&self, item: Published) {
+        let Published {
+            event,
+            body,
+            subscriptions,
+        } = item;
+        match self
+            .queue(move |q| q.enqueue(event, &body, &subscriptions))
+            .await
+        {
+            Ok(0) => {}
+            Ok(dropped) => {
+                tracing::warn!(event = event.as_str(), dropped, "Webhook queue full; dropping deliveries");
+                for _ in 0..dropped {
+                    self.metrics.webhook_delivery("dropped");
+                }
+            }
+            Err(e) => tracing::error!(event = event.as_str(), error = %e, "Failed to queue webhook"),
+        }
+    }
+
+    #[tracing::instrument(
+        name = "webhook.deliver",
+        skip_all,
+        fields(delivery = delivery.id, event = delivery.event.as_str(), attempt = delivery.attempts + 1)
+    )]
+    async fn attempt(&self, mut delivery: WebhookDelivery) {
+        let now = chrono::Utc::now().timestamp();
+        delivery.attempts += 1;
+        delivery.last_attempt_at = Some(now);
+
+        let Some(subscription) = self.subscriptions.get(delivery.subscription_id) else {
+            delivery.status = DeliveryStatus::Failed;
+            delivery.last_error = Some("Subscription removed".to_string());
+            self.finish(delivery, "dropped").await;
+            return;
+        };
+
+        let timestamp = now.to_string();
+        let result = self
+            .client
+            .post(&delivery.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header("X-Counter-Event", delivery.event.as_str())
+            .header("X-Counter-Delivery", delivery.id.to_string())
+            .header("X-Counter-Timestamp", &timestamp)
+            .header(
+                "X-Counter-Signature",
+                signature(&subscription.secret, &timestamp, &delivery.body),
+            )
+            .body(delivery.body.clone())
+            .send()
+            .await;
+
+        let delivered = match result {
+            Ok(response) => {
+                let status = response.status();
+                delivery.response_status = Some(status.as_u16());
+                delivery.last_error = (!status.is_success()).then(|| format!("HTTP {}", status));
+                status.is_success()
+            }
+            Err(e) => {
+                delivery.response_status = None;
+                delivery.last_error = Some(e.to_string());
+                false
+            }
+        };
+
+        let outcome = if delivered {
+            delivery.status = DeliveryStatus::Delivered;
+            "delivered"
+        } else if let Some(delay) = RETRY_DELAYS.get(delivery.attempts as usize - 1) {
+            delivery.next_attempt_at = now + delay;
+            tracing::warn!(error = delivery.last_error.as_deref(), retry_in = delay, "Webhook delivery failed");
+            "retry"
+        } else {
+            delivery.status = DeliveryStatus::Failed;
+            tracing::warn!(error = delivery.last_error.as_deref(), "Webhook delivery failed; giving up");
+            "failed"
+        };
+        self.finish(delivery, outcome).await;
+    }
+
+    async fn finish(&self, delivery: WebhookDelivery, outcome: &str) {
+        self.metrics.webhook_delivery(outcome);
+        if let Err(e) = self.queue(move |q| q.update(&delivery)).await {
+            tracing::error!(error = %e, "Failed to save webhook delivery");
+        }
+    }
+}
+
+/// `sha256=<hex>` HMAC of `<timestamp>.<body>`, as sent in `X-Counter-Signature`.
+pub fn signature(secret: &str, timestamp: &str, body: &str) -> String {
+    let mut mac =
+        HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(timestamp.as_bytes());
+    mac.update(b".");
+    mac.update(body.as_bytes());
+    format!("sha256={:x}", mac.finalize().into_bytes())
+}
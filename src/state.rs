@@ -1,99 +1,614 @@
 use crate::domain::logger::EventLogger; // Import trait
-use crate::domain::repositories::LogRepository;
+use crate::domain::repositories::{
+    AccessListRepository, AlertRuleRepository, AnnouncementRepository, AuditRepository,
+    LogRepository, MetricsHistoryRepository, VpnPeerRepository, WebhookDeliveryRepository,
+    WebhookSubscriptionRepository,
+};
+use crate::domain::{
+    AlertsResponse, Announcement, AuditEntry, HistoryRange, LogFilter,
+    MetricsHistoryResponse, MetricsSample, NewAnnouncement, SystemMetrics, VpnPeer, VpnPeerStatus,
+    WebhookEvent,
+};
+use serde_json::json;
+use std::error::Error;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex, RwLock};
-use std::time::Instant;
-use sysinfo::System; // Ensure trait is imported for refresh methods
-use tokio::sync::broadcast;
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, mpsc, watch};
+use crate::services::alerting::{AlertEngine, AlertInputs};
+use crate::services::bot_detection::BotDetector;
+use crate::services::client_token::{ClientAuthMode, ClientTokenService};
+use crate::services::connection_limiter::{
+    ConnectionLimiter, ConnectionLimits, ConnectionPermit, LimitRejection,
+};
+use crate::services::metrics::Metrics;
+use crate::services::privacy::IpPrivacy;
 use crate::services::wakatime::WakatimeData;
+use crate::services::webhooks::WebhookDispatcher;
 
 use axum::extract::FromRef;
+use crate::api::client_ip::TrustedProxies;
 use axum_extra::extract::cookie::Key;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 #[derive(Clone, Debug)]
 pub struct ActiveConnection {
     pub ip: String,
     pub device: String,
     pub device_id: String,
+    // The device_id was proven with a signed client token
+    pub verified: bool,
+    // Announcement room the client joined, if any
+    pub room: Option<String>,
+    // Classified as a bot on join; kept out of the user counts
+    pub is_bot: bool,
     pub connected_at: Instant,
+    // Distinguishes reconnects that reuse the same device_id
+    pub connection_id: u64,
+    // Admin -> socket task channel; None for connections that can't be commanded
+    pub commands: Option<mpsc::UnboundedSender<ConnectionCommand>>,
 }
 
+/// Instructions pushed from the admin UI to a live client socket.
+#[derive(Clone, Debug)]
+pub enum ConnectionCommand {
+    Notice { message: String },
+    Kick { reason: String },
+}
+
+impl ConnectionCommand {
+    /// The JSON message a client receives for this command, on transports
+    /// without close frames.
+    pub fn to_message(&self) -> serde_json::Value {
+        match self {
+            ConnectionCommand::Notice { message } => json!({"type": "notice", "message": message}),
+            ConnectionCommand::Kick { reason } => json!({"type": "kicked", "reason": reason}),
+        }
+    }
+}
+
+/// Handed to a socket task on `join`: its connection id and command inbox.
+pub struct Presence {
+    pub connection_id: u64,
+    pub commands: mpsc::UnboundedReceiver<ConnectionCommand>,
+}
+
+// Presence kept alive by /client/heartbeat instead of an open connection
+struct HeartbeatPresence {
+    connection_id: u64,
+    ip: String,
+    device: String,
+    last_seen: Instant,
+    // Commands wait here until the next heartbeat collects them
+    commands: mpsc::UnboundedReceiver<ConnectionCommand>,
+    // Counts against the connection limits like a socket would
+    _permit: ConnectionPermit,
+}
+
+// A join that has been registered but not yet logged or announced
+struct Joined {
+    ip: String,
+    device: String,
+    device_id: String,
+    verified: bool,
+    room: Option<String>,
+    is_bot: bool,
+    // Took over the device_id from an older connection
+    replaced: bool,
+    count: u32,
+}
+
+// A departure that has been unregistered but not yet logged or announced
+struct Departed {
+    ip: String,
+    device: String,
+    device_id: String,
+    action: &'static str,
+    note: Option<String>,
+    ended_at: Instant,
+    // The connection and its length in seconds, unless a newer one had taken over
+    ended: Option<(ActiveConnection, u64)>,
+    count: u32,
+}
+
+static CONNECTION_SEQ: AtomicU64 = AtomicU64::new(1);
+
 #[derive(Clone)]
 pub struct AppState {
     pub active_connections: Arc<Mutex<HashMap<String, ActiveConnection>>>,
-    pub system_tx: broadcast::Sender<crate::domain::SystemMetrics>,
+    // Heartbeat-held entries of `active_connections`, by device_id
+    heartbeats: Arc<Mutex<HashMap<String, HeartbeatPresence>>>,
+    pub heartbeat_ttl: Duration,
+    // Rooms labelled by name on /metrics (`METRICS_ROOMS`). Rooms are
+    // client-chosen, so any other room is counted as "other".
+    pub metrics_rooms: Arc<HashSet<String>>,
+    // Admin dashboard sockets, keyed by connection id; never counted as users
+    pub admin_connections: Arc<Mutex<HashMap<String, ActiveConnection>>>,
     pub users_tx: broadcast::Sender<crate::domain::UserMetrics>,
+    pub announcements_tx: broadcast::Sender<Announcement>,
+    // Ids of announcements clients were sent but not yet told have ended
+    live_announcements: Arc<Mutex<HashSet<u64>>>,
     pub logger: Arc<dyn EventLogger + Send + Sync>,
     pub log_repository: Arc<dyn LogRepository>,
-    pub system: Arc<Mutex<System>>,
-    pub start_time: Instant,
+    pub access_list: Arc<dyn AccessListRepository>,
+    pub announcements: Arc<dyn AnnouncementRepository>,
+    pub audit_log: Arc<dyn AuditRepository>,
+    pub metrics_history: Arc<dyn MetricsHistoryRepository>,
+    pub alert_rules: Arc<dyn AlertRuleRepository>,
+    pub alerts: Arc<AlertEngine>,
+    pub webhook_subscriptions: Arc<dyn WebhookSubscriptionRepository>,
+    pub webhook_deliveries: Arc<dyn WebhookDeliveryRepository>,
+    pub webhooks: Arc<WebhookDispatcher>,
+    pub vpn_peers: Arc<dyn VpnPeerRepository>,
+    // Latest host and process reading, published by the system sampler
+    pub system: watch::Receiver<Arc<SystemMetrics>>,
     pub key: Key,
     pub wakatime_data: Arc<RwLock<Option<WakatimeData>>>,
+    pub client_tokens: Arc<ClientTokenService>,
+    pub client_auth_mode: ClientAuthMode,
+    pub trusted_proxies: Arc<TrustedProxies>,
+    pub connection_limiter: Arc<ConnectionLimiter>,
+    pub ip_privacy: IpPrivacy,
+    pub bots: Arc<BotDetector>,
+    pub metrics: Arc<Metrics>,
 }
 
 impl AppState {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
+        system: watch::Receiver<Arc<SystemMetrics>>,
         logger: Arc<dyn EventLogger + Send + Sync>,
         log_repository: Arc<dyn LogRepository>,
+        access_list: Arc<dyn AccessListRepository>,
+        announcements: Arc<dyn AnnouncementRepository>,
+        audit_log: Arc<dyn AuditRepository>,
+        metrics_history: Arc<dyn MetricsHistoryRepository>,
+        alert_rules: Arc<dyn AlertRuleRepository>,
+        webhook_subscriptions: Arc<dyn WebhookSubscriptionRepository>,
+        webhook_deliveries: Arc<dyn WebhookDeliveryRepository>,
+        vpn_peers: Arc<dyn VpnPeerRepository>,
+        ip_privacy: IpPrivacy,
+        bots: Arc<BotDetector>,
+        metrics: Arc<Metrics>,
     ) -> Self {
-        let (system_tx, _) = broadcast::channel(100);
         let (users_tx, _) = broadcast::channel(100);
+        let (announcements_tx, _) = broadcast::channel(100);
+        let live_announcements = announcements.active().iter().map(|a| a.id).collect();
 
-        let mut sys = System::new_all();
-        sys.refresh_all();
+        let webhooks = Arc::new(WebhookDispatcher::new(
+            webhook_subscriptions.clone(),
+            webhook_deliveries.clone(),
+            metrics.clone(),
+        ));
+
+        let key = Key::generate();
+        let client_tokens = Arc::new(ClientTokenService::from_env(key.signing()));
 
         Self {
             active_connections: Arc::new(Mutex::new(HashMap::new())),
-            system_tx,
+            heartbeats: Arc::new(Mutex::new(HashMap::new())),
+            heartbeat_ttl: Duration::from_secs(
+                std::env::var("HEARTBEAT_TTL_SECS")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .unwrap_or(60),
+            ),
+            metrics_rooms: Arc::new(
+                std::env::var("METRICS_ROOMS")
+                    .unwrap_or_default()
+                    .split(',')
+                    .map(|r| r.trim().to_string())
+                    .filter(|r| !r.is_empty())
+                    .collect(),
+            ),
+            admin_connections: Arc::new(Mutex::new(HashMap::new())),
             users_tx,
+            announcements_tx,
+            live_announcements: Arc::new(Mutex::new(live_announcements)),
             logger,
             log_repository,
-            system: Arc::new(Mutex::new(sys)),
-            start_time: Instant::now(),
-            key: Key::generate(),
+            access_list,
+            announcements,
+            audit_log,
+            metrics_history,
+            alert_rules,
+            alerts: Arc::new(AlertEngine::from_env()),
+            webhook_subscriptions,
+            webhook_deliveries,
+            webhooks,
+            vpn_peers,
+            system,
+            key,
             wakatime_data: Arc::new(RwLock::new(None)),
+            client_tokens,
+            client_auth_mode: ClientAuthMode::from_env(),
+            trusted_proxies: Arc::new(TrustedProxies::from_env()),
+            connection_limiter: Arc::new(ConnectionLimiter::new(ConnectionLimits::from_env())),
+            ip_privacy,
+            bots,
+            metrics,
         }
     }
 
-    pub fn join(&self, ip: &str, device: &str, device_id: &str) -> u32 {
+    pub fn join(
+        &self,
+        ip: &str,
+        device: &str,
+        device_id: &str,
+        verified: bool,
+        room: Option<&str>,
+    ) -> Presence {
+        let (presence, joined) = self.register(ip, device, device_id, verified, room);
+        self.announce_join(joined);
+        presence
+    }
+
+    // The in-memory half of a join, cheap enough to run under other locks.
+    // The caller must pass the result to `announce_join`.
+    fn register(
+        &self,
+        ip: &str,
+        device: &str,
+        device_id: &str,
+        verified: bool,
+        room: Option<&str>,
+    ) -> (Presence, Joined) {
+        let connection_id = CONNECTION_SEQ.fetch_add(1, Ordering::Relaxed);
+        let (commands_tx, commands_rx) = mpsc::unbounded_channel();
+        let is_bot = self.bots.is_bot(ip, device);
+
         let mut conn_map = self.active_connections.lock().unwrap();
-        conn_map.insert(
+        let replaced = conn_map.insert(
             device_id.to_string(),
             ActiveConnection {
                 ip: ip.to_string(),
                 device: device.to_string(),
                 device_id: device_id.to_string(),
+                verified,
+                room: room.map(str::to_string),
+                is_bot,
                 connected_at: Instant::now(),
+                connection_id,
+                commands: Some(commands_tx),
             },
         );
-        let count = conn_map.len() as u32;
+        let count = self.user_count(&conn_map);
         drop(conn_map);
 
+        let presence = Presence {
+            connection_id,
+            commands: commands_rx,
+        };
+        let joined = Joined {
+            ip: ip.to_string(),
+            device: device.to_string(),
+            device_id: device_id.to_string(),
+            verified,
+            room: room.map(str::to_string),
+            is_bot,
+            replaced: replaced.is_some(),
+            count,
+        };
+        (presence, joined)
+    }
+
+    // Logs a registered join and tells everyone who follows presence. This is
+    // where the disk writes happen, so it runs with no locks held.
+    fn announce_join(&self, joined: Joined) {
+        let Joined {
+            ip,
+            device,
+            device_id,
+            verified,
+            room,
+            is_bot,
+            replaced,
+            count,
+        } = joined;
+
+        self.metrics.connected("client");
+        // Grouped by the logged form of the IP, so alerts don't reveal more than the log
+        self.alerts.connection_opened(&self.ip_privacy.apply(&ip));
         self.logger
-            .log(ip, device, device_id, "CONNECTED", count, None);
+            .log(&ip, &device, &device_id, "CONNECTED", count, None);
+        if verified {
+            self.record_peer_presence(&device_id, chrono::Utc::now().timestamp(), true);
+        }
+        // Taking over from an older connection keeps the device online, just as
+        // that connection ending afterwards sends no disconnect
+        if !replaced {
+            self.publish_webhook(
+                WebhookEvent::Connected,
+                json!({
+                    "device_id": device_id,
+                    "device": device,
+                    "ip": self.ip_privacy.apply(&ip),
+                    "room": room,
+                    "is_bot": is_bot,
+                    "active_users": count,
+                }),
+            );
+        }
 
         // Notify user stream
         let _ = self.users_tx.send(self.get_user_metrics());
+    }
 
-        count
+    pub fn leave(&self, ip: &str, device: &str, device_id: &str, connection_id: u64) -> u32 {
+        let now = Instant::now();
+        let departed = self.unregister(ip, device, device_id, connection_id, "DISCONNECTED", None, now);
+        self.announce_departure(departed)
+    }
+
+    // Like `leave`, but the session was ended by an admin
+    pub fn kicked(
+        &self,
+        ip: &str,
+        device: &str,
+        device_id: &str,
+        connection_id: u64,
+        reason: &str,
+    ) -> u32 {
+        let now = Instant::now();
+        let departed = self.unregister(ip, device, device_id, connection_id, "KICKED", Some(reason), now);
+        self.announce_departure(departed)
+    }
+
+    /// Keeps `device_id` online for another `heartbeat_ttl`, joining on the
+    /// first beat. A device already connected over a socket is left to that
+    /// socket, so it is never counted twice. Returns the commands queued since
+    /// the last beat; after a kick the presence is gone.
+    pub fn heartbeat(
+        &self,
+        peer_ip: IpAddr,
+        ip: &str,
+        device: &str,
+        device_id: &str,
+        verified: bool,
+        room: Option<&str>,
+    ) -> Result<Vec<ConnectionCommand>, LimitRejection> {
+        let mut departures = Vec::new();
+        let mut joined = None;
+        let result = {
+            // Presence bookkeeping only, so a beat can't race the expiry sweep;
+            // the announcements below write to disk and run after it is released
+            let mut heartbeats = self.heartbeats.lock().unwrap();
+            self.beat(
+                &mut heartbeats,
+                peer_ip,
+                ip,
+                device,
+                device_id,
+                verified,
+                room,
+                &mut departures,
+                &mut joined,
+            )
+        };
+
+        for departed in departures {
+            self.announce_departure(departed);
+        }
+        if let Some(joined) = joined {
+            self.announce_join(joined);
+        }
+        result
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn beat(
+        &self,
+        heartbeats: &mut HashMap<String, HeartbeatPresence>,
+        peer_ip: IpAddr,
+        ip: &str,
+        device: &str,
+        device_id: &str,
+        verified: bool,
+        room: Option<&str>,
+        departures: &mut Vec<Departed>,
+        joined: &mut Option<Joined>,
+    ) -> Result<Vec<ConnectionCommand>, LimitRejection> {
+        if let Some(presence) = heartbeats.get_mut(device_id)
+            && self.is_current(device_id, presence.connection_id)
+        {
+            presence.last_seen = Instant::now();
+            let mut commands = Vec::new();
+            while let Ok(command) = presence.commands.try_recv() {
+                let kicked = matches!(command, ConnectionCommand::Kick { .. });
+                commands.push(command);
+                if kicked {
+                    break;
+                }
+            }
+            if let Some(ConnectionCommand::Kick { reason }) = commands.last()
+                && let Some(presence) = heartbeats.remove(device_id)
+            {
+                departures.push(self.unregister(
+                    &presence.ip,
+                    &presence.device,
+                    device_id,
+                    presence.connection_id,
+                    "KICKED",
+                    Some(reason),
+                    Instant::now(),
+                ));
+            }
+            return Ok(commands);
+        }
+
+        if self.active_connections.lock().unwrap().contains_key(device_id) {
+            return Ok(Vec::new());
+        }
+        // Taken over by a socket that has since gone; close out the old presence
+        if let Some(stale) = heartbeats.remove(device_id) {
+            departures.push(self.unregister(
+                &stale.ip,
+                &stale.device,
+                device_id,
+                stale.connection_id,
+                "DISCONNECTED",
+                None,
+                Instant::now(),
+            ));
+        }
+
+        let permit = self.connection_limiter.try_acquire(peer_ip, device_id)?;
+        let (
+            Presence {
+                connection_id,
+                commands,
+            },
+            registered,
+        ) = self.register(ip, device, device_id, verified, room);
+        *joined = Some(registered);
+        heartbeats.insert(
+            device_id.to_string(),
+            HeartbeatPresence {
+                connection_id,
+                ip: ip.to_string(),
+                device: device.to_string(),
+                last_seen: Instant::now(),
+                commands,
+                _permit: permit,
+            },
+        );
+        Ok(Vec::new())
     }
 
-    pub fn leave(&self, ip: &str, device: &str, device_id: &str) -> u32 {
+    /// Disconnects heartbeat presences that missed their TTL. The session is
+    /// taken to have ended at the last beat, not when the sweep noticed.
+    pub fn expire_heartbeats(&self) {
+        let departures: Vec<Departed> = {
+            let mut heartbeats = self.heartbeats.lock().unwrap();
+            let ended: Vec<String> = heartbeats
+                .iter()
+                .filter(|(device_id, presence)| {
+                    presence.last_seen.elapsed() >= self.heartbeat_ttl
+                        || !self.is_current(device_id, presence.connection_id)
+                })
+                .map(|(device_id, _)| device_id.clone())
+                .collect();
+
+            ended
+                .into_iter()
+                .filter_map(|device_id| {
+                    let presence = heartbeats.remove(&device_id)?;
+                    Some(self.unregister(
+                        &presence.ip,
+                        &presence.device,
+                        &device_id,
+                        presence.connection_id,
+                        "DISCONNECTED",
+                        None,
+                        presence.last_seen,
+                    ))
+                })
+                .collect()
+        };
+
+        for departed in departures {
+            self.announce_departure(departed);
+        }
+    }
+
+    fn is_current(&self, device_id: &str, connection_id: u64) -> bool {
+        self.active_connections
+            .lock()
+            .unwrap()
+            .get(device_id)
+            .is_some_and(|conn| conn.connection_id == connection_id)
+    }
+
+    // The in-memory half of a departure; the caller must pass the result to
+    // `announce_departure`.
+    #[allow(clippy::too_many_arguments)]
+    fn unregister(
+        &self,
+        ip: &str,
+        device: &str,
+        device_id: &str,
+        connection_id: u64,
+        action: &'static str,
+        note: Option<&str>,
+        // When the session ended, for its duration
+        ended_at: Instant,
+    ) -> Departed {
         let mut conn_map = self.active_connections.lock().unwrap();
-        let mut duration_str = None;
+        let mut ended = None;
 
-        if let Some(conn) = conn_map.remove(device_id) {
-            let duration = conn.connected_at.elapsed();
-            let secs = duration.as_secs();
-            let formatted = crate::utils::format_duration(secs);
-            duration_str = Some(formatted);
+        // A newer socket may have taken over this device_id; leave its entry alone
+        let is_current = conn_map
+            .get(device_id)
+            .is_some_and(|conn| conn.connection_id == connection_id);
+        if is_current && let Some(conn) = conn_map.remove(device_id) {
+            let duration = ended_at.saturating_duration_since(conn.connected_at);
+            self.bots.record_session(ip, device, duration);
+            self.metrics.session_ended(duration);
+            ended = Some((conn, duration.as_secs()));
         }
 
-        let count = conn_map.len() as u32;
+        let count = self.user_count(&conn_map);
         drop(conn_map);
 
-        self.logger
-            .log(ip, device, device_id, "DISCONNECTED", count, duration_str);
+        Departed {
+            ip: ip.to_string(),
+            device: device.to_string(),
+            device_id: device_id.to_string(),
+            action,
+            note: note.map(str::to_string),
+            ended_at,
+            ended,
+            count,
+        }
+    }
+
+    // Logs a departure and tells everyone who follows presence. Runs with no
+    // locks held, like `announce_join`. Returns the remaining user count.
+    fn announce_departure(&self, departed: Departed) -> u32 {
+        let Departed {
+            ip,
+            device,
+            device_id,
+            action,
+            note,
+            ended_at,
+            ended,
+            count,
+        } = departed;
+        let duration_str = ended
+            .as_ref()
+            .map(|(_, secs)| crate::utils::format_duration(*secs));
+
+        self.metrics.disconnected("client", action);
+        match note {
+            Some(note) => self
+                .logger
+                .log_with_note(&ip, &device, &device_id, action, count, duration_str, &note),
+            None => self
+                .logger
+                .log(&ip, &device, &device_id, action, count, duration_str),
+        }
+        // A device whose socket was replaced by a newer one hasn't gone offline
+        if let Some((conn, duration_secs)) = ended {
+            if conn.verified {
+                let ended_ts = chrono::Utc::now().timestamp() - ended_at.elapsed().as_secs() as i64;
+                self.record_peer_presence(&device_id, ended_ts, false);
+            }
+            self.publish_webhook(
+                WebhookEvent::Disconnected,
+                json!({
+                    "device_id": device_id,
+                    "device": device,
+                    "ip": self.ip_privacy.apply(&ip),
+                    "room": conn.room,
+                    "is_bot": conn.is_bot,
+                    "reason": action.to_lowercase(),
+                    "duration_secs": duration_secs,
+                    "active_users": count,
+                }),
+            );
+        }
 
         // Notify user stream
         let _ = self.users_tx.send(self.get_user_metrics());
@@ -101,80 +616,241 @@ impl AppState {
         count
     }
 
-    // Helper to get current count without modifying state
-    pub fn get_active_count(&self) -> u32 {
-        self.active_connections.lock().unwrap().len() as u32
+    // Keeps the VPN peer registry's last-seen times in step with presence
+    fn record_peer_presence(&self, device_id: &str, at: i64, handshake: bool) {
+        if let Err(e) = self.vpn_peers.record_presence(device_id, at, handshake) {
+            tracing::error!(device_id, error = %e, "Failed to update VPN peer presence");
+        }
+    }
+
+    /// Registered VPN peers, each marked online while its device is connected
+    /// with a client token. Anyone can claim a device_id without one, so such
+    /// connections never vouch for a peer.
+    pub fn get_vpn_peers(&self) -> Vec<VpnPeerStatus> {
+        let conn_map = self.active_connections.lock().unwrap();
+        self.vpn_peers
+            .list()
+            .into_iter()
+            .map(|peer| VpnPeerStatus {
+                online: Self::peer_online(&conn_map, &peer),
+                peer,
+            })
+            .collect()
     }
 
-    pub fn get_dashboard_stats(&self) -> crate::domain::DashboardStats {
-        let mut sys = self.system.lock().unwrap();
-        sys.refresh_cpu_all();
-        sys.refresh_memory();
+    pub fn vpn_peer_status(&self, peer: VpnPeer) -> VpnPeerStatus {
+        let online = Self::peer_online(&self.active_connections.lock().unwrap(), &peer);
+        VpnPeerStatus { peer, online }
+    }
 
-        let uptime_sec = self.start_time.elapsed().as_secs();
-        let hrs = uptime_sec / 3600;
-        let mins = (uptime_sec % 3600) / 60;
-        let secs = uptime_sec % 60;
-        let uptime = format!("{}h {}m {}s", hrs, mins, secs);
+    fn peer_online(conn_map: &HashMap<String, ActiveConnection>, peer: &VpnPeer) -> bool {
+        conn_map.get(&peer.device_id).is_some_and(|conn| conn.verified)
+    }
 
-        let cpu = format!("{:.1}", sys.global_cpu_usage());
-        let ram = format!(
-            "{}MB / {}MB",
-            sys.used_memory() / 1024 / 1024,
-            sys.total_memory() / 1024 / 1024
-        );
+    /// Queues `data` for every webhook subscribed to `event`. The dispatcher
+    /// writes it to the delivery queue, so the connection never waits on disk.
+    fn publish_webhook(&self, event: WebhookEvent, data: serde_json::Value) {
+        let subscriptions: Vec<_> = self
+            .webhook_subscriptions
+            .list()
+            .into_iter()
+            .filter(|s| s.wants(event))
+            .collect();
+        if subscriptions.is_empty() {
+            return;
+        }
 
-        // Active users (exclude admin dashboard)
-        let active_users = self
-            .active_connections
+        let body = json!({
+            "id": format!("{:032x}", rand::random::<u128>()),
+            "event": event.as_str(),
+            "timestamp": chrono::Utc::now().to_rfc3339(),
+            "data": data,
+        })
+        .to_string();
+        self.webhooks.publish(event, body, subscriptions);
+    }
+
+    /// Delivers a command to the live socket for `device_id`. Returns false if
+    /// the device isn't connected (or its socket already went away).
+    pub fn send_command(&self, device_id: &str, command: ConnectionCommand) -> bool {
+        self.active_connections
             .lock()
             .unwrap()
-            .values()
-            .filter(|c| c.device_id != "admin-dashboard")
-            .count() as u32;
+            .get(device_id)
+            .and_then(|conn| conn.commands.as_ref())
+            .is_some_and(|tx| tx.send(command).is_ok())
+    }
 
-        crate::domain::DashboardStats {
-            active_users,
-            total_users: active_users,
-            uptime,
-            cpu,
-            ram,
+    /// Stores an announcement and pushes it to every connected client it targets.
+    pub fn announce(
+        &self,
+        announcement: NewAnnouncement,
+    ) -> Result<Announcement, Box<dyn Error + Send + Sync>> {
+        let announcement = self.announcements.add(announcement)?;
+        self.live_announcements.lock().unwrap().insert(announcement.id);
+        let _ = self.announcements_tx.send(announcement.clone());
+        Ok(announcement)
+    }
+
+    /// Expires an announcement early; clients still showing it are sent the
+    /// ended announcement.
+    pub fn end_announcement(
+        &self,
+        id: u64,
+    ) -> Result<Option<Announcement>, Box<dyn Error + Send + Sync>> {
+        let ended = self.announcements.end(id)?;
+        if let Some(announcement) = &ended
+            && self.live_announcements.lock().unwrap().remove(&id)
+        {
+            let _ = self.announcements_tx.send(announcement.clone());
         }
+        Ok(ended)
     }
 
-    pub fn get_system_metrics(&self) -> crate::domain::SystemMetrics {
-        let mut sys = self.system.lock().unwrap();
-        sys.refresh_cpu_all();
-        sys.refresh_memory();
+    /// Sends the ended announcement for any whose `expires_at` has passed
+    /// since the last sweep, so clients take them down without reconnecting.
+    pub fn expire_announcements(&self) {
+        let now = chrono::Utc::now().timestamp();
+        let ended: Vec<Announcement> = {
+            let mut live = self.live_announcements.lock().unwrap();
+            if live.is_empty() {
+                return;
+            }
+            self.announcements
+                .history()
+                .into_iter()
+                .filter(|a| a.is_expired(now) && live.remove(&a.id))
+                .collect()
+        };
 
-        let uptime_sec = self.start_time.elapsed().as_secs();
-        let hrs = uptime_sec / 3600;
-        let mins = (uptime_sec % 3600) / 60;
-        let secs = uptime_sec % 60;
-        let uptime = format!("{}h {}m {}s", hrs, mins, secs);
+        for announcement in ended {
+            tracing::info!(id = announcement.id, "Announcement expired");
+            let _ = self.announcements_tx.send(announcement);
+        }
+    }
 
-        let cpu = format!("{:.1}", sys.global_cpu_usage());
-        let ram = format!(
-            "{}MB / {}MB",
-            sys.used_memory() / 1024 / 1024,
-            sys.total_memory() / 1024 / 1024
+    /// Appends to the audit trail. Failures are reported but never block the action.
+    pub fn audit(&self, actor: &str, ip: &str, action: &str, details: serde_json::Value) {
+        let entry = AuditEntry {
+            timestamp: chrono::Local::now().format("%Y-%m-%d %H:%M:%S %z").to_string(),
+            actor: actor.to_string(),
+            ip: ip.to_string(),
+            action: action.to_string(),
+            details,
+        };
+        if let Err(e) = self.audit_log.record(&entry) {
+            tracing::error!(action, error = %e, "Failed to write audit entry");
+        }
+    }
+
+    /// Extends a subject filter so a raw IP also matches its anonymized log form.
+    pub fn subject_filter(&self, filter: LogFilter) -> LogFilter {
+        let trim = |v: Option<String>| v.map(|s| s.trim().to_string()).filter(|s| !s.is_empty());
+        let ip = trim(filter.ip);
+        LogFilter {
+            device_id: trim(filter.device_id),
+            ip_alias: ip.as_deref().map(|ip| self.ip_privacy.apply(ip)),
+            ip,
+        }
+    }
+
+    pub fn admin_join(&self, ip: &str, device: &str, connection_id: &str) {
+        self.metrics.connected("admin");
+        self.admin_connections.lock().unwrap().insert(
+            connection_id.to_string(),
+            ActiveConnection {
+                ip: ip.to_string(),
+                device: device.to_string(),
+                device_id: connection_id.to_string(),
+                verified: false,
+                room: None,
+                is_bot: false,
+                connected_at: Instant::now(),
+                connection_id: 0,
+                commands: None,
+            },
         );
+    }
 
-        crate::domain::SystemMetrics { uptime, cpu, ram }
+    pub fn admin_leave(&self, connection_id: &str) {
+        if self.admin_connections.lock().unwrap().remove(connection_id).is_some() {
+            self.metrics.disconnected("admin", "DISCONNECTED");
+        }
     }
 
-    pub fn get_user_metrics(&self) -> crate::domain::UserMetrics {
-        let active_users = self
-            .active_connections
-            .lock()
-            .unwrap()
+    // Helper to get current count without modifying state
+    pub fn get_active_count(&self) -> u32 {
+        self.user_count(&self.active_connections.lock().unwrap())
+    }
+
+    pub fn get_bot_count(&self) -> u32 {
+        let conn_map = self.active_connections.lock().unwrap();
+        conn_map.values().filter(|c| c.is_bot).count() as u32
+    }
+
+    // Connections counted as users; bots only with COUNT_BOTS_AS_USERS
+    fn user_count(&self, conn_map: &HashMap<String, ActiveConnection>) -> u32 {
+        conn_map
             .values()
-            .filter(|c| c.device_id != "admin-dashboard")
-            .count() as u32;
+            .filter(|c| self.bots.count_as_users || !c.is_bot)
+            .count() as u32
+    }
+
+    /// The latest reading from the system sampler. Never blocks on sysinfo.
+    pub fn system_snapshot(&self) -> Arc<SystemMetrics> {
+        self.system.borrow().clone()
+    }
+
+    /// Checks the alert rules against a fresh reading and the current presence.
+    pub fn evaluate_alerts(&self, metrics: &SystemMetrics) {
+        let inputs = AlertInputs {
+            cpu_percent: metrics.cpu as f64,
+            active_users: self.get_active_count() as f64,
+            wakatime_failures: self.metrics.wakatime_failure_streak() as f64,
+        };
+        self.alerts.evaluate(&self.alert_rules.list(), &inputs);
+    }
+
+    pub fn get_alerts(&self) -> AlertsResponse {
+        AlertsResponse {
+            firing: self.alerts.firing(),
+            resolved: self.alerts.resolved(),
+        }
+    }
+
+    /// The history point for a fresh reading, with the current connection counts.
+    pub fn history_sample(&self, metrics: &SystemMetrics) -> MetricsSample {
+        let conn_map = self.active_connections.lock().unwrap();
+        MetricsSample {
+            timestamp: chrono::Utc::now().timestamp(),
+            cpu: metrics.cpu,
+            ram_used: metrics.ram_used,
+            ram_total: metrics.ram_total,
+            load: metrics.load.one,
+            net_rx: metrics.net_rx,
+            net_tx: metrics.net_tx,
+            connections: conn_map.len() as u32,
+            users: self.user_count(&conn_map),
+        }
+    }
+
+    pub fn get_metrics_history(&self, range: HistoryRange) -> MetricsHistoryResponse {
+        let resolution = range.resolution();
+        let since = chrono::Utc::now().timestamp() - range.seconds();
+        MetricsHistoryResponse {
+            range: range.as_str(),
+            resolution_secs: resolution.seconds(),
+            samples: self.metrics_history.since(resolution, since),
+        }
+    }
+
+    pub fn get_user_metrics(&self) -> crate::domain::UserMetrics {
+        let active_users = self.get_active_count();
 
         crate::domain::UserMetrics {
             active_users,
             total_users: active_users,
+            active_bots: self.get_bot_count(),
         }
     }
 
@@ -183,7 +859,6 @@ impl AppState {
             .lock()
             .unwrap()
             .values()
-            .filter(|c| c.device_id != "admin-dashboard")
             .cloned()
             .collect()
     }
@@ -1,3 +1,6 @@
+use ipnet::IpNet;
+use std::net::IpAddr;
+
 pub fn format_duration(secs: u64) -> String {
     if secs < 60 {
         format!("{}s", secs)
@@ -7,3 +10,35 @@ pub fn format_duration(secs: u64) -> String {
         format!("{}h {}m", secs / 3600, (secs % 3600) / 60)
     }
 }
+
+/// Makes free text safe for a single CSV column of the event log.
+pub fn sanitize_csv_field(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| if c == ',' || c.is_control() { ' ' } else { c })
+        .collect::<String>()
+        .trim()
+        .to_string()
+}
+
+/// Parses a CIDR or a bare IP (as a single-host network).
+pub fn parse_network(s: &str) -> Option<IpNet> {
+    let s = s.trim();
+    s.parse::<IpNet>()
+        .ok()
+        .or_else(|| s.parse::<IpAddr>().ok().map(IpNet::from))
+}
+
+/// Parses a comma separated list of CIDRs or bare IPs, e.g. `10.0.0.0/8,127.0.0.1`.
+pub fn parse_networks(list: &str) -> Vec<IpNet> {
+    list.split(',')
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+        .filter_map(|s| {
+            parse_network(s).or_else(|| {
+                tracing::warn!(network = s, "Ignoring invalid network in list");
+                None
+            })
+        })
+        .collect()
+}
@@ -1,4 +1,7 @@
-use tokio_tungstenite::{connect_async, tungstenite::protocol::Message};
+use tokio_tungstenite::{
+    connect_async,
+    tungstenite::{client::IntoClientRequest, protocol::Message},
+};
 
 #[tokio::test]
 async fn test_health_check() {
@@ -14,6 +17,20 @@ async fn test_health_check() {
     assert_eq!(text, "OK");
 }
 
+#[tokio::test]
+async fn test_login_requires_csrf_token() {
+    let client = reqwest::Client::new();
+    let res = client
+        .post("http://localhost:3000/login")
+        .header("content-type", "application/x-www-form-urlencoded")
+        .body("username=admin&password=admin")
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    assert_eq!(res.status(), 403);
+}
+
 #[tokio::test]
 async fn test_client_ws_connection() {
     let url = "ws://localhost:3000/client/ws?device_id=test-client";
@@ -34,10 +51,133 @@ async fn test_client_ws_connection() {
     }
 }
 
+#[tokio::test]
+async fn test_client_token_issue_and_connect() {
+    let client = reqwest::Client::new();
+    let body: serde_json::Value = client
+        .get("http://localhost:3000/client/token")
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .expect("Failed to parse token response");
+
+    let token = body["token"].as_str().expect("token missing");
+    assert!(body["device_id"].as_str().is_some());
+
+    let url = format!("ws://localhost:3000/client/ws?token={}", token);
+    let (_socket, response) = connect_async(url).await.expect("Failed to connect");
+    assert_eq!(response.status(), 101);
+}
+
+#[tokio::test]
+async fn test_client_token_for_chosen_device_requires_auth() {
+    let client = reqwest::Client::new();
+    let res = client
+        .get("http://localhost:3000/client/token?device_id=someone-else")
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    assert_eq!(res.status(), 401);
+}
+
+#[tokio::test]
+async fn test_client_ws_rejects_forged_token() {
+    let url = "ws://localhost:3000/client/ws?token=eyJzdWIiOiJ4IiwiZXhwIjo5OTk5OTk5OTk5fQ.AAAA";
+    match connect_async(url).await {
+        Err(tokio_tungstenite::tungstenite::Error::Http(response)) => {
+            assert_eq!(response.status(), 401);
+        }
+        _ => panic!("Expected 401 rejection"),
+    }
+}
+
+#[tokio::test]
+async fn test_client_ws_per_device_limit() {
+    // Server default WS_MAX_PER_DEVICE is 5
+    let url = "ws://localhost:3000/client/ws?device_id=test-limit";
+    let mut sockets = Vec::new();
+    for _ in 0..5 {
+        let (socket, _) = connect_async(url).await.expect("Failed to connect");
+        sockets.push(socket);
+    }
+
+    let (mut socket, _) = connect_async(url).await.expect("Failed to connect");
+    match socket.next().await {
+        Some(Ok(Message::Close(Some(frame)))) => assert_eq!(u16::from(frame.code), 1008),
+        other => panic!("Expected close frame, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn test_cap_rejections_do_not_spend_rate_budget() {
+    // Server defaults: WS_MAX_PER_DEVICE=5, WS_CONNECT_BURST=20. Its own
+    // address, so other tests don't share the bucket.
+    let url = format!("ws://localhost:3000/client/ws?device_id=rate-budget-{}", std::process::id());
+    let connect = || {
+        let mut request = url.as_str().into_client_request().unwrap();
+        request.headers_mut().insert("x-forwarded-for", "198.51.100.150".parse().unwrap());
+        connect_async(request)
+    };
+
+    let mut sockets = Vec::new();
+    for _ in 0..5 {
+        let (socket, _) = connect().await.expect("Failed to connect");
+        sockets.push(socket);
+    }
+
+    // Well past the burst: every attempt must still report the device cap
+    for attempt in 0..30 {
+        let (mut socket, _) = connect().await.expect("Failed to connect");
+        match socket.next().await {
+            Some(Ok(Message::Close(Some(frame)))) => {
+                assert_eq!(u16::from(frame.code), 1008, "attempt {}: {}", attempt, frame.reason)
+            }
+            other => panic!("Expected close frame, got {:?}", other),
+        }
+    }
+}
+
+// Must match the ADMIN_API_TOKEN the server under test was started with
+fn admin_api_token() -> String {
+    std::env::var("ADMIN_API_TOKEN").unwrap_or_else(|_| "test-admin-token".to_string())
+}
+
+#[tokio::test]
+async fn test_admin_ws_requires_auth() {
+    let url = "ws://localhost:3000/admin/ws";
+    match connect_async(url).await {
+        Err(tokio_tungstenite::tungstenite::Error::Http(response)) => {
+            assert_eq!(response.status(), 401);
+        }
+        _ => panic!("Expected 401 rejection"),
+    }
+}
+
+#[tokio::test]
+async fn test_wakatime_requires_auth() {
+    let client = reqwest::Client::new();
+    let res = client
+        .get("http://localhost:3000/api/wakatime")
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    assert_eq!(res.status(), 401);
+}
+
 #[tokio::test]
 async fn test_admin_ws_connection() {
-    let url = "ws://localhost:3000/admin/ws?device_id=test-admin";
-    let (mut socket, response) = connect_async(url).await.expect("Failed to connect");
+    let mut request = "ws://localhost:3000/admin/ws"
+        .into_client_request()
+        .unwrap();
+    request.headers_mut().insert(
+        "authorization",
+        format!("Bearer {}", admin_api_token()).parse().unwrap(),
+    );
+    let (mut socket, response) = connect_async(request).await.expect("Failed to connect");
 
     assert_eq!(response.status(), 101);
 
@@ -55,5 +195,1682 @@ async fn test_admin_ws_connection() {
     }
 }
 
+#[tokio::test]
+async fn test_admin_ws_structured_metrics() {
+    let mut request = "ws://localhost:3000/admin/ws"
+        .into_client_request()
+        .unwrap();
+    request.headers_mut().insert(
+        "authorization",
+        format!("Bearer {}", admin_api_token()).parse().unwrap(),
+    );
+    let (mut socket, _) = connect_async(request).await.expect("Failed to connect");
+
+    let Some(Ok(Message::Text(text))) = socket.next().await else {
+        panic!("Expected an initial metrics message");
+    };
+    let metrics: serde_json::Value = serde_json::from_str(&text).unwrap();
+    socket.close(None).await.ok();
+
+    // Raw numbers, formatted by the dashboard rather than the server
+    assert!(metrics["uptime_secs"].is_u64());
+    assert!(metrics["cpu"].is_number());
+    assert!(!metrics["cpu_cores"].as_array().unwrap().is_empty());
+    assert!(metrics["ram_total"].as_u64().unwrap() > 0);
+    assert!(metrics["load"]["one"].is_number());
+    assert!(metrics["disks"].is_array());
+    assert!(metrics["process"]["memory_bytes"].as_u64().unwrap() > 0);
+}
+
+#[tokio::test]
+async fn test_status_and_admin_ws_share_system_snapshot() {
+    let client = reqwest::Client::new();
+    let session = admin_session().await;
+
+    // A new snapshot may land between the two reads; then just read again
+    for _ in 0..3 {
+        let mut request = "ws://localhost:3000/admin/ws"
+            .into_client_request()
+            .unwrap();
+        request.headers_mut().insert(
+            "authorization",
+            format!("Bearer {}", admin_api_token()).parse().unwrap(),
+        );
+        let (mut socket, _) = connect_async(request).await.expect("Failed to connect");
+        let Some(Ok(Message::Text(text))) = socket.next().await else {
+            panic!("Expected an initial metrics message");
+        };
+        socket.close(None).await.ok();
+        let snapshot: serde_json::Value = serde_json::from_str(&text).unwrap();
+
+        let status: serde_json::Value = client
+            .get("http://localhost:3000/api/status")
+            .header("cookie", &session.cookies)
+            .send()
+            .await
+            .expect("Failed to send request")
+            .json()
+            .await
+            .unwrap();
+
+        if status["uptime_seconds"] != snapshot["uptime_secs"] {
+            continue;
+        }
+        // Same reading, not a second refresh of the CPU counters
+        assert_eq!(
+            status["cpu_usage_percent"].as_f64().unwrap() as f32,
+            snapshot["cpu"].as_f64().unwrap() as f32
+        );
+        assert_eq!(
+            status["memory_used_mb"].as_u64().unwrap(),
+            snapshot["ram_used"].as_u64().unwrap() / 1024 / 1024
+        );
+        return;
+    }
+    panic!("Never read the same snapshot twice in a row");
+}
+
+struct AdminSession {
+    cookies: String,
+    csrf_token: String,
+}
+
+// Logs in through the form and returns the session cookies and CSRF token.
+// Assumes the server runs with the default admin/admin credentials.
+async fn admin_session() -> AdminSession {
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+
+    let res = client
+        .get("http://localhost:3000/login")
+        .send()
+        .await
+        .expect("Failed to load login page");
+    let pre_session = cookie_pairs(&res).join("; ");
+    let page = res.text().await.unwrap();
+    let token = page
+        .split("name=\"csrf_token\" value=\"")
+        .nth(1)
+        .and_then(|rest| rest.split('"').next())
+        .expect("No CSRF token on login page")
+        .to_string();
+
+    let res = client
+        .post("http://localhost:3000/login")
+        .header("cookie", pre_session)
+        .header("content-type", "application/x-www-form-urlencoded")
+        .body(format!("csrf_token={}&username=admin&password=admin", token))
+        .send()
+        .await
+        .expect("Failed to log in");
+    assert_eq!(res.status(), 303);
+
+    let cookies = cookie_pairs(&res)
+        .into_iter()
+        .filter(|pair| !pair.ends_with('='))
+        .collect::<Vec<_>>()
+        .join("; ");
+
+    let dashboard = client
+        .get("http://localhost:3000/admin")
+        .header("cookie", &cookies)
+        .send()
+        .await
+        .expect("Failed to load dashboard")
+        .text()
+        .await
+        .unwrap();
+    let csrf_token = dashboard
+        .split("\"X-CSRF-Token\": \"")
+        .nth(1)
+        .and_then(|rest| rest.split('"').next())
+        .expect("No CSRF token on dashboard")
+        .to_string();
+
+    AdminSession {
+        cookies,
+        csrf_token,
+    }
+}
+
+fn cookie_pairs(res: &reqwest::Response) -> Vec<String> {
+    res.headers()
+        .get_all("set-cookie")
+        .iter()
+        .filter_map(|h| h.to_str().ok())
+        .filter_map(|c| c.split(';').next())
+        .map(|pair| pair.to_string())
+        .collect()
+}
+
+#[tokio::test]
+async fn test_login_is_audited() {
+    let session = admin_session().await;
+
+    let client = reqwest::Client::new();
+    let entries: Vec<serde_json::Value> = client
+        .get("http://localhost:3000/api/audit?limit=50")
+        .header("cookie", session.cookies)
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .expect("Failed to parse audit log");
+
+    assert!(
+        entries
+            .iter()
+            .any(|e| e["action"] == "login" && e["actor"] == "admin")
+    );
+}
+
+#[tokio::test]
+async fn test_logout_requires_post_with_csrf_token() {
+    let session = admin_session().await;
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+    let logout = |body: String| {
+        client
+            .post("http://localhost:3000/logout")
+            .header("cookie", &session.cookies)
+            .header("content-type", "application/x-www-form-urlencoded")
+            .body(body)
+            .send()
+    };
+
+    let res = client
+        .get("http://localhost:3000/logout")
+        .header("cookie", &session.cookies)
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(res.status(), 405);
+    let res = logout(String::new()).await.expect("Failed to send request");
+    assert_eq!(res.status(), 403);
+
+    let res = logout(format!("csrf_token={}", session.csrf_token))
+        .await
+        .expect("Failed to send request");
+    assert_eq!(res.status(), 303);
+    assert_eq!(res.headers()["location"], "/login");
+    assert!(cookie_pairs(&res).iter().any(|pair| pair == "auth_token="));
+}
+
+#[tokio::test]
+async fn test_event_log_chain_verifies() {
+    let session = admin_session().await;
+
+    let client = reqwest::Client::new();
+    let report: serde_json::Value = client
+        .get("http://localhost:3000/api/logs/verify")
+        .header("cookie", session.cookies)
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .expect("Failed to parse report");
+
+    assert_eq!(report["intact"], true, "{}", report);
+}
+
+#[tokio::test]
+async fn test_event_log_chain_survives_comma_in_device_id() {
+    let url = format!("ws://localhost:3000/client/ws?device_id=evil%2Cinjected-{}", std::process::id());
+    let (mut socket, _) = connect_async(url.as_str()).await.expect("Failed to connect");
+    socket.next().await;
+    socket.close(None).await.ok();
+
+    let session = admin_session().await;
+    let report: serde_json::Value = reqwest::Client::new()
+        .get("http://localhost:3000/api/logs/verify")
+        .header("cookie", session.cookies)
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .expect("Failed to parse report");
+
+    assert_eq!(report["intact"], true, "{}", report);
+    assert!(report["records"].as_u64().unwrap() > 0, "{}", report);
+
+    // The comma is logged as a space instead of shifting the columns
+    let device_id = format!("evil injected-{}", std::process::id());
+    let events = logged_actions(&device_id.replace(' ', "%20")).await;
+    assert!(events.iter().any(|e| e["action"] == "CONNECTED"), "{:?}", events);
+}
+
+#[tokio::test]
+async fn test_erase_events_for_device() {
+    let device_id = format!("erase-me-{}", std::process::id());
+    let url = format!("ws://localhost:3000/client/ws?device_id={}", device_id);
+    let (mut socket, _) = connect_async(url.as_str()).await.expect("Failed to connect");
+    socket.close(None).await.ok();
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+    let session = admin_session().await;
+    let client = reqwest::Client::new();
+    let body: serde_json::Value = client
+        .post("http://localhost:3000/api/logs/erase")
+        .header("cookie", &session.cookies)
+        .header("x-csrf-token", &session.csrf_token)
+        .json(&serde_json::json!({"device_id": device_id}))
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .expect("Failed to parse response");
+    assert!(body["removed"].as_u64().unwrap_or(0) >= 1, "{}", body);
+
+    // Erasure re-chains the log, so it must still verify
+    let report: serde_json::Value = client
+        .get("http://localhost:3000/api/logs/verify")
+        .header("cookie", &session.cookies)
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .expect("Failed to parse report");
+    assert_eq!(report["intact"], true, "{}", report);
+}
+
+#[tokio::test]
+async fn test_subject_search_by_device() {
+    let device_id = format!("subject-{}", std::process::id());
+    let url = format!("ws://localhost:3000/client/ws?device_id={}", device_id);
+    let (mut socket, _) = connect_async(url.as_str()).await.expect("Failed to connect");
+    socket.close(None).await.ok();
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+    let session = admin_session().await;
+    let events: Vec<serde_json::Value> = reqwest::Client::new()
+        .get(format!("http://localhost:3000/api/subjects?device_id={}", device_id))
+        .header("cookie", &session.cookies)
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .expect("Failed to parse response");
+
+    assert!(!events.is_empty());
+    assert!(events.iter().all(|e| e["device_id"] == device_id.as_str()));
+    assert!(events.iter().all(|e| e["source"] == "live"));
+}
+
+#[tokio::test]
+async fn test_user_agent_is_parsed() {
+    let device_id = format!("ua-{}", std::process::id());
+    let mut request = format!("ws://localhost:3000/client/ws?device_id={}", device_id)
+        .into_client_request()
+        .unwrap();
+    request.headers_mut().insert(
+        "user-agent",
+        "Mozilla/5.0 (iPhone; CPU iPhone OS 17_2 like Mac OS X) AppleWebKit/605.1.15 \
+         (KHTML, like Gecko) Version/17.2 Mobile/15E148 Safari/604.1"
+            .parse()
+            .unwrap(),
+    );
+    let (mut socket, _) = connect_async(request).await.expect("Failed to connect");
+    socket.close(None).await.ok();
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+    let session = admin_session().await;
+    let body: serde_json::Value = reqwest::Client::new()
+        .get(format!(
+            "http://localhost:3000/api/logs?q={}&device_class=mobile&os=ios",
+            device_id
+        ))
+        .header("cookie", &session.cookies)
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .expect("Failed to parse response");
+
+    let event = &body["data"][0];
+    assert_eq!(event["device_id"], device_id.as_str(), "{}", body);
+    assert_eq!(event["browser"], "Safari");
+    assert_eq!(event["os"], "iOS");
+    assert_eq!(event["os_version"], "17.2");
+}
+
+#[tokio::test]
+async fn test_bots_counted_separately() {
+    let mut request = "ws://localhost:3000/client/ws?device_id=test-crawler"
+        .into_client_request()
+        .unwrap();
+    request.headers_mut().insert(
+        "user-agent",
+        "Mozilla/5.0 (compatible; Googlebot/2.1; +http://www.google.com/bot.html)"
+            .parse()
+            .unwrap(),
+    );
+    let (mut socket, _) = connect_async(request).await.expect("Failed to connect");
+
+    match socket.next().await {
+        Some(Ok(Message::Text(text))) => {
+            let metrics: serde_json::Value = serde_json::from_str(&text).unwrap();
+            assert!(metrics["activeBots"].as_u64().unwrap_or(0) >= 1, "{}", text);
+        }
+        other => panic!("Expected metrics, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn test_events_are_geolocated() {
+    // Requires the server to run with GEOIP_DB=tests/fixtures/geoip-test.mmdb
+    let device_id = format!("geo-{}", std::process::id());
+    let url = format!("ws://localhost:3000/client/ws?device_id={}", device_id);
+    let (mut socket, _) = connect_async(url.as_str()).await.expect("Failed to connect");
+    socket.close(None).await.ok();
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+    let session = admin_session().await;
+    let body: serde_json::Value = reqwest::Client::new()
+        .get(format!("http://localhost:3000/api/logs?q={}&country=zz", device_id))
+        .header("cookie", &session.cookies)
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .expect("Failed to parse response");
+
+    let event = &body["data"][0];
+    assert_eq!(event["device_id"], device_id.as_str(), "{}", body);
+    assert_eq!(event["country"], "ZZ");
+    assert_eq!(event["city"], "Loopback City");
+    assert_eq!(event["asn"], 64512);
+}
+
+#[tokio::test]
+async fn test_prometheus_metrics() {
+    let url = "ws://localhost:3000/client/ws?device_id=test-metrics&room=metrics-room";
+    let (mut socket, _) = connect_async(url).await.expect("Failed to connect");
+    socket.next().await;
+    let url = "ws://localhost:3000/client/ws?device_id=test-metrics-unlisted&room=unlisted-room";
+    let (mut unlisted, _) = connect_async(url).await.expect("Failed to connect");
+    unlisted.next().await;
+
+    // Scraped while the sockets are still open, so their rooms show up
+    let res = reqwest::get("http://localhost:3000/metrics")
+        .await
+        .expect("Failed to send request");
+    assert_eq!(res.status(), 200);
+    assert!(
+        res.headers()["content-type"]
+            .to_str()
+            .unwrap()
+            .starts_with("text/plain; version=0.0.4")
+    );
+    let body = res.text().await.unwrap();
+    socket.close(None).await.ok();
+    unlisted.close(None).await.ok();
+
+    // The server lists metrics-room in METRICS_ROOMS; other rooms are pooled
+    assert!(
+        body.contains(r#"counter_active_connections{endpoint="client",room="metrics-room"} 1"#),
+        "{}",
+        body
+    );
+    assert!(body.contains(r#"counter_active_connections{endpoint="client",room="other"}"#), "{}", body);
+    assert!(!body.contains("unlisted-room"), "{}", body);
+    for name in [
+        "counter_connection_rejections_total{reason=\"too_many_for_device\"}",
+        "counter_connects_total{endpoint=\"client\"}",
+        "# TYPE counter_session_duration_seconds histogram",
+        "counter_log_write_errors_total",
+        "process_resident_memory_bytes",
+        "process_cpu_usage_percent",
+    ] {
+        assert!(body.contains(name), "missing {}", name);
+    }
+}
+
+#[tokio::test]
+async fn test_metrics_history() {
+    let session = admin_session().await;
+    let client = reqwest::Client::new();
+
+    // The sampler takes its first sample as the server starts
+    let res = client
+        .get("http://localhost:3000/api/metrics/history?range=15m")
+        .header("cookie", &session.cookies)
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(res.status(), 200);
+    let body: serde_json::Value = res.json().await.unwrap();
+    assert_eq!(body["range"], "15m");
+    assert_eq!(body["resolution_secs"], 2);
+    let sample = &body["samples"][0];
+    assert!(sample["ram_total"].as_u64().unwrap_or(0) > 0, "{}", body);
+    assert!(sample["cpu"].is_number() && sample["connections"].is_number());
+
+    let body: serde_json::Value = client
+        .get("http://localhost:3000/api/metrics/history?range=7d")
+        .header("cookie", &session.cookies)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(body["resolution_secs"], 3600);
+
+    let res = client
+        .get("http://localhost:3000/api/metrics/history?range=2y")
+        .header("cookie", &session.cookies)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 400);
+}
+
 // Helper to allow stream iteration
 use futures_util::StreamExt;
+
+// An HTTP request read off a test listener, not yet answered
+struct ReceivedRequest {
+    stream: tokio::net::TcpStream,
+    // Request line and headers
+    head: String,
+    body: Vec<u8>,
+}
+
+impl ReceivedRequest {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.head.lines().find_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            key.eq_ignore_ascii_case(name).then(|| value.trim())
+        })
+    }
+
+    fn json(&self) -> serde_json::Value {
+        serde_json::from_slice(&self.body).unwrap()
+    }
+
+    async fn respond(mut self, status: u16) {
+        use tokio::io::AsyncWriteExt;
+        let response = format!(
+            "HTTP/1.1 {} X\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+            status
+        );
+        self.stream.write_all(response.as_bytes()).await.unwrap();
+    }
+}
+
+async fn accept_request(listener: &tokio::net::TcpListener) -> ReceivedRequest {
+    use tokio::io::AsyncReadExt;
+
+    let (mut stream, _) = listener.accept().await.expect("Request never arrived");
+    let mut request = Vec::new();
+    let mut buf = [0u8; 4096];
+    loop {
+        let n = stream.read(&mut buf).await.unwrap();
+        assert!(n > 0, "Request ended early");
+        request.extend_from_slice(&buf[..n]);
+
+        let text = String::from_utf8_lossy(&request);
+        let Some(header_end) = text.find("\r\n\r\n") else {
+            continue;
+        };
+        let mut received = ReceivedRequest {
+            stream,
+            head: text[..header_end].to_string(),
+            body: Vec::new(),
+        };
+        let length: usize = received
+            .header("content-length")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0);
+        if request.len() >= header_end + 4 + length {
+            received.body = request[header_end + 4..][..length].to_vec();
+            return received;
+        }
+        stream = received.stream;
+    }
+}
+
+// Accepts one webhook POST, answers 200 and returns the JSON body
+async fn receive_webhook(listener: &tokio::net::TcpListener) -> serde_json::Value {
+    let request = accept_request(listener).await;
+    let body = request.json();
+    request.respond(200).await;
+    body
+}
+
+#[tokio::test]
+async fn test_alert_rule_ids_are_not_reused() {
+    let session = admin_session().await;
+    let client = reqwest::Client::new();
+    // Never breached, so no alert state is left behind
+    let add = || {
+        client
+            .post("http://localhost:3000/api/alerts/rules")
+            .header("cookie", &session.cookies)
+            .header("x-csrf-token", &session.csrf_token)
+            .json(&serde_json::json!({
+                "name": "test-alert-never",
+                "metric": "active_users",
+                "comparison": "above",
+                "threshold": 1_000_000,
+            }))
+            .send()
+    };
+    let remove = |id: u64| {
+        client
+            .delete(format!("http://localhost:3000/api/alerts/rules/{}", id))
+            .header("cookie", &session.cookies)
+            .header("x-csrf-token", &session.csrf_token)
+            .send()
+    };
+
+    let first: serde_json::Value = add().await.unwrap().json().await.unwrap();
+    let first = first["id"].as_u64().expect("Rule was not created");
+    assert_eq!(remove(first).await.unwrap().status(), 200);
+    let second: serde_json::Value = add().await.unwrap().json().await.unwrap();
+    let second = second["id"].as_u64().expect("Rule was not created");
+    remove(second).await.unwrap();
+    assert!(second > first, "id {} was handed out again", second);
+}
+
+#[tokio::test]
+async fn test_alert_fires_and_resolves_once() {
+    use std::time::Duration;
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let webhook_url = format!("http://{}/hook", listener.local_addr().unwrap());
+    let session = admin_session().await;
+    let client = reqwest::Client::new();
+
+    // Always breached, so it fires on the next evaluation
+    let rule: serde_json::Value = client
+        .post("http://localhost:3000/api/alerts/rules")
+        .header("cookie", &session.cookies)
+        .header("x-csrf-token", &session.csrf_token)
+        .json(&serde_json::json!({
+            "name": "test-alert-always",
+            "metric": "active_users",
+            "comparison": "below",
+            "threshold": 1_000_000,
+            "webhook_url": webhook_url,
+        }))
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .unwrap();
+    let id = rule["id"].as_u64().expect("Rule was not created");
+
+    let firing = tokio::time::timeout(Duration::from_secs(10), receive_webhook(&listener))
+        .await
+        .expect("No firing notification");
+    assert_eq!(firing["status"], "firing");
+    assert_eq!(firing["rule_name"], "test-alert-always");
+
+    let alerts: serde_json::Value = client
+        .get("http://localhost:3000/api/alerts")
+        .header("cookie", &session.cookies)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert!(
+        alerts["firing"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|a| a["fingerprint"] == firing["fingerprint"])
+    );
+
+    // Still firing a few evaluations later, but nothing is sent again
+    let repeat = tokio::time::timeout(Duration::from_secs(5), listener.accept()).await;
+    assert!(repeat.is_err(), "Alert was notified twice");
+
+    let res = client
+        .delete(format!("http://localhost:3000/api/alerts/rules/{}", id))
+        .header("cookie", &session.cookies)
+        .header("x-csrf-token", &session.csrf_token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+
+    let resolved = tokio::time::timeout(Duration::from_secs(10), receive_webhook(&listener))
+        .await
+        .expect("No resolved notification");
+    assert_eq!(resolved["status"], "resolved");
+    assert_eq!(resolved["fingerprint"], firing["fingerprint"]);
+}
+
+#[tokio::test]
+async fn test_webhook_signed_and_retried() {
+    use hmac::{Hmac, Mac};
+    use std::time::Duration;
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let session = admin_session().await;
+    let client = reqwest::Client::new();
+    let secret = "test-webhook-secret";
+
+    let subscription: serde_json::Value = client
+        .post("http://localhost:3000/api/webhooks")
+        .header("cookie", &session.cookies)
+        .header("x-csrf-token", &session.csrf_token)
+        .json(&serde_json::json!({
+            "url": format!("http://{}/presence", listener.local_addr().unwrap()),
+            "events": ["device.connected"],
+            "secret": secret,
+        }))
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .unwrap();
+    let id = subscription["id"].as_u64().expect("Subscription was not created");
+    assert_eq!(subscription["secret"], secret);
+
+    let url = "ws://localhost:3000/client/ws?device_id=test-webhook-device";
+    let (mut socket, _) = connect_async(url).await.expect("Failed to connect");
+    socket.next().await;
+    socket.close(None).await.ok();
+
+    // Other tests connect concurrently; their deliveries are just acknowledged.
+    // Ours is refused once and has to come back as a retry of the same delivery.
+    let mut refused = None;
+    let delivery_id = tokio::time::timeout(Duration::from_secs(20), async {
+        loop {
+            let request = accept_request(&listener).await;
+            if request.json()["data"]["device_id"] != "test-webhook-device" {
+                request.respond(200).await;
+                continue;
+            }
+            let delivery = request.header("x-counter-delivery").unwrap().to_string();
+            let Some(first) = &refused else {
+                refused = Some(delivery);
+                request.respond(500).await;
+                continue;
+            };
+            assert_eq!(&delivery, first);
+
+            let payload = request.json();
+            assert_eq!(payload["event"], "device.connected");
+            assert_eq!(request.header("x-counter-event"), Some("device.connected"));
+
+            let timestamp = request.header("x-counter-timestamp").unwrap();
+            let mut mac = Hmac::<sha2::Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+            mac.update(format!("{}.", timestamp).as_bytes());
+            mac.update(&request.body);
+            let expected = format!("sha256={:x}", mac.finalize().into_bytes());
+            assert_eq!(request.header("x-counter-signature"), Some(expected.as_str()));
+
+            request.respond(200).await;
+            return delivery.parse::<u64>().unwrap();
+        }
+    })
+    .await
+    .expect("Webhook was not retried");
+
+    // The outcome is recorded just after the response
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    let deliveries: Vec<serde_json::Value> = client
+        .get("http://localhost:3000/api/webhooks/deliveries?limit=500")
+        .header("cookie", &session.cookies)
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let delivery = deliveries
+        .iter()
+        .find(|d| d["id"] == delivery_id)
+        .expect("Delivery missing from history");
+    assert_eq!(delivery["status"], "delivered");
+    assert_eq!(delivery["attempts"], 2);
+
+    let res = client
+        .delete(format!("http://localhost:3000/api/webhooks/{}", id))
+        .header("cookie", &session.cookies)
+        .header("x-csrf-token", &session.csrf_token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+}
+
+#[tokio::test]
+async fn test_webhook_ids_are_not_reused() {
+    let session = admin_session().await;
+    let client = reqwest::Client::new();
+    let subscribe = || {
+        client
+            .post("http://localhost:3000/api/webhooks")
+            .header("cookie", &session.cookies)
+            .header("x-csrf-token", &session.csrf_token)
+            .json(&serde_json::json!({
+                "url": "http://127.0.0.1:9/unused",
+                "events": ["device.connected"],
+            }))
+            .send()
+    };
+    let unsubscribe = |id: u64| {
+        client
+            .delete(format!("http://localhost:3000/api/webhooks/{}", id))
+            .header("cookie", &session.cookies)
+            .header("x-csrf-token", &session.csrf_token)
+            .send()
+    };
+
+    let first: serde_json::Value = subscribe().await.unwrap().json().await.unwrap();
+    let first = first["id"].as_u64().expect("Subscription was not created");
+    assert_eq!(unsubscribe(first).await.unwrap().status(), 200);
+
+    // The newest subscription was just removed; its id must not come back
+    let second: serde_json::Value = subscribe().await.unwrap().json().await.unwrap();
+    let second = second["id"].as_u64().expect("Subscription was not created");
+    unsubscribe(second).await.unwrap();
+    assert!(second > first, "id {} was handed out again", second);
+}
+
+#[tokio::test]
+async fn test_webhook_takeover_sends_one_connect() {
+    use std::time::Duration;
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let session = admin_session().await;
+    let client = reqwest::Client::new();
+    let device_id = format!("webhook-takeover-{}", std::process::id());
+
+    let subscription: serde_json::Value = client
+        .post("http://localhost:3000/api/webhooks")
+        .header("cookie", &session.cookies)
+        .header("x-csrf-token", &session.csrf_token)
+        .json(&serde_json::json!({
+            "url": format!("http://{}/presence", listener.local_addr().unwrap()),
+            "events": ["device.connected", "device.disconnected"],
+        }))
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .unwrap();
+    let id = subscription["id"].as_u64().expect("Subscription was not created");
+
+    // A second socket takes the device over, then both go away
+    let url = format!("ws://localhost:3000/client/ws?device_id={}", device_id);
+    let (mut first, _) = connect_async(url.as_str()).await.expect("Failed to connect");
+    first.next().await;
+    let (mut second, _) = connect_async(url.as_str()).await.expect("Failed to connect");
+    second.next().await;
+    first.close(None).await.ok();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    second.close(None).await.ok();
+
+    // Collect our events until a quiet second after the disconnect
+    let mut events = Vec::new();
+    loop {
+        let wait = if events.contains(&"device.disconnected".to_string()) { 1 } else { 20 };
+        let Ok(request) = tokio::time::timeout(Duration::from_secs(wait), accept_request(&listener)).await else {
+            break;
+        };
+        let payload = request.json();
+        request.respond(200).await;
+        if payload["data"]["device_id"] == device_id.as_str() {
+            events.push(payload["event"].as_str().unwrap().to_string());
+        }
+    }
+
+    client
+        .delete(format!("http://localhost:3000/api/webhooks/{}", id))
+        .header("cookie", &session.cookies)
+        .header("x-csrf-token", &session.csrf_token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(events, ["device.connected", "device.disconnected"]);
+}
+
+// Reads a text/event-stream response one event at a time
+struct EventStream {
+    response: reqwest::Response,
+    buffer: String,
+}
+
+impl EventStream {
+    async fn open(request: reqwest::RequestBuilder) -> EventStream {
+        let response = request.send().await.expect("Failed to open event stream");
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()["content-type"], "text/event-stream");
+        EventStream {
+            response,
+            buffer: String::new(),
+        }
+    }
+
+    // (id, data) of the next event, skipping keep-alive comments
+    async fn next(&mut self) -> (String, serde_json::Value) {
+        loop {
+            if let Some(end) = self.buffer.find("\n\n") {
+                let block: String = self.buffer.drain(..end + 2).collect();
+                let field = |name: &str| {
+                    block
+                        .lines()
+                        .find_map(|line| line.strip_prefix(name))
+                        .map(|value| value.trim_start().to_string())
+                };
+                if let Some(data) = field("data:") {
+                    let id = field("id:").expect("Event without an id");
+                    return (id, serde_json::from_str(&data).unwrap());
+                }
+                continue;
+            }
+            let chunk = tokio::time::timeout(std::time::Duration::from_secs(5), self.response.chunk())
+                .await
+                .expect("Timed out waiting for an event")
+                .expect("Event stream failed")
+                .expect("Event stream ended");
+            self.buffer.push_str(std::str::from_utf8(&chunk).unwrap());
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_client_events_join_and_leave() {
+    let device_id = format!("sse-{}", std::process::id());
+    let client = reqwest::Client::new();
+    let mut events = EventStream::open(
+        client.get(format!("http://localhost:3000/client/events?device_id={}", device_id)),
+    )
+    .await;
+
+    let (id, metrics) = events.next().await;
+    assert!(id.parse::<i64>().is_ok());
+    assert!(metrics["activeUsers"].is_u64());
+    assert!(metrics["totalUsers"].is_u64());
+
+    // Closing the stream is the disconnect
+    drop(events);
+    tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+
+    let session = admin_session().await;
+    let logged: Vec<serde_json::Value> = client
+        .get(format!("http://localhost:3000/api/subjects?device_id={}", device_id))
+        .header("cookie", &session.cookies)
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .expect("Failed to parse response");
+    let actions: Vec<&str> = logged.iter().filter_map(|e| e["action"].as_str()).collect();
+    assert!(actions.contains(&"CONNECTED"), "{:?}", actions);
+    assert!(actions.contains(&"DISCONNECTED"), "{:?}", actions);
+}
+
+#[tokio::test]
+async fn test_client_events_resume_from_last_event_id() {
+    let room = format!("sse-room-{}", std::process::id());
+    let url = format!("http://localhost:3000/client/events?room={}", room);
+    let session = admin_session().await;
+    let client = reqwest::Client::new();
+
+    let announcement: serde_json::Value = client
+        .post("http://localhost:3000/api/announcements")
+        .header("cookie", &session.cookies)
+        .header("x-csrf-token", &session.csrf_token)
+        .json(&serde_json::json!({"message": "Resume test", "severity": "info", "room": room}))
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .unwrap();
+
+    // A fresh stream gets the active announcement after the metrics
+    let mut fresh = EventStream::open(client.get(&url)).await;
+    let (_, metrics) = fresh.next().await;
+    assert!(metrics["activeUsers"].is_u64());
+    let (_, replayed) = fresh.next().await;
+    assert_eq!(replayed["type"], "announcement");
+    assert_eq!(replayed["announcement"]["id"], announcement["id"]);
+    drop(fresh);
+
+    // Resuming from a later event skips it (announcement times are whole
+    // seconds, so let one pass), leaving the live end as the next event
+    tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+    let last_event_id = unix_millis().to_string();
+    let mut resumed = EventStream::open(client.get(&url).header("last-event-id", last_event_id)).await;
+    let (_, metrics) = resumed.next().await;
+    assert!(metrics["activeUsers"].is_u64());
+
+    let res = client
+        .delete(format!(
+            "http://localhost:3000/api/announcements/{}",
+            announcement["id"]
+        ))
+        .header("cookie", &session.cookies)
+        .header("x-csrf-token", &session.csrf_token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+
+    let (_, ended) = resumed.next().await;
+    assert_eq!(ended["type"], "announcement_ended");
+    assert_eq!(ended["announcement"]["id"], announcement["id"]);
+}
+
+fn unix_millis() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as i64
+}
+
+#[tokio::test]
+async fn test_admin_events_stream_system_metrics() {
+    let res = reqwest::get("http://localhost:3000/admin/events").await.unwrap();
+    assert_eq!(res.status(), 401);
+
+    let client = reqwest::Client::new();
+    let mut events = EventStream::open(
+        client
+            .get("http://localhost:3000/admin/events")
+            .bearer_auth(admin_api_token()),
+    )
+    .await;
+    let (_, first) = events.next().await;
+    assert!(first["uptime_secs"].is_u64());
+    assert!(first["ram_total"].as_u64().unwrap() > 0);
+
+    // The sampler publishes every couple of seconds
+    let (_, second) = events.next().await;
+    assert!(second["uptime_secs"].as_u64() >= first["uptime_secs"].as_u64());
+}
+
+async fn heartbeat(device_id: &str) -> serde_json::Value {
+    let res = reqwest::Client::new()
+        .post("http://localhost:3000/client/heartbeat")
+        .header("user-agent", "Mozilla/5.0 (X11; Linux x86_64; rv:120.0) Gecko/20100101 Firefox/120.0")
+        .json(&serde_json::json!({"device_id": device_id}))
+        .send()
+        .await
+        .expect("Failed to send heartbeat");
+    assert_eq!(res.status(), 200);
+    res.json().await.unwrap()
+}
+
+async fn logged_actions(device_id: &str) -> Vec<serde_json::Value> {
+    let session = admin_session().await;
+    reqwest::Client::new()
+        .get(format!("http://localhost:3000/api/subjects?device_id={}", device_id))
+        .header("cookie", &session.cookies)
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .expect("Failed to parse response")
+}
+
+#[tokio::test]
+async fn test_heartbeat_presence_expires() {
+    let device_id = format!("heartbeat-{}", std::process::id());
+    let first = heartbeat(&device_id).await;
+    let ttl = first["ttl_secs"].as_u64().unwrap();
+    assert!(ttl <= 5, "Start the server with HEARTBEAT_TTL_SECS=2 (got {})", ttl);
+    assert!(first["activeUsers"].as_u64().unwrap() >= 1);
+
+    tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+    heartbeat(&device_id).await;
+    tokio::time::sleep(std::time::Duration::from_millis(ttl * 1000 + 1500)).await;
+
+    let events = logged_actions(&device_id).await;
+    let actions: Vec<&str> = events.iter().filter_map(|e| e["action"].as_str()).collect();
+    assert_eq!(actions, ["CONNECTED", "DISCONNECTED"], "{:?}", events);
+    // Measured to the last beat, not to when the expiry was noticed
+    let disconnected = events.iter().find(|e| e["action"] == "DISCONNECTED").unwrap();
+    assert_eq!(disconnected["duration"], "1s");
+}
+
+#[tokio::test]
+async fn test_heartbeat_defers_to_open_socket() {
+    let device_id = format!("heartbeat-ws-{}", std::process::id());
+    let url = format!("ws://localhost:3000/client/ws?device_id={}", device_id);
+    let (mut socket, _) = connect_async(url.as_str()).await.expect("Failed to connect");
+    socket.next().await;
+
+    heartbeat(&device_id).await;
+    heartbeat(&device_id).await;
+    let events = logged_actions(&device_id).await;
+    let connects = events.iter().filter(|e| e["action"] == "CONNECTED").count();
+    assert_eq!(connects, 1, "{:?}", events);
+
+    socket.close(None).await.ok();
+}
+
+async fn vpn_peer(session: &AdminSession, id: u64) -> serde_json::Value {
+    reqwest::Client::new()
+        .get(format!("http://localhost:3000/api/vpn/peers/{}", id))
+        .header("cookie", &session.cookies)
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .expect("Failed to parse response")
+}
+
+#[tokio::test]
+async fn test_vpn_peer_registry_tracks_presence() {
+    use base64::prelude::*;
+    use std::time::Duration;
+
+    let session = admin_session().await;
+    let client = reqwest::Client::new();
+    let device_id = format!("vpn-peer-{}", std::process::id());
+    // Unique per run, since the registry outlives the server
+    let mut key = [0u8; 32];
+    key[..16].copy_from_slice(&(unix_millis() as u128 * 1000 + std::process::id() as u128).to_le_bytes());
+    let public_key = BASE64_STANDARD.encode(key);
+
+    let res = client
+        .post("http://localhost:3000/api/vpn/peers")
+        .header("cookie", &session.cookies)
+        .header("x-csrf-token", &session.csrf_token)
+        .json(&serde_json::json!({"device_id": device_id, "public_key": "not-a-key"}))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(res.status(), 400);
+
+    // The broadcast address is inside the overlay network but can't be a peer
+    let res = client
+        .post("http://localhost:3000/api/vpn/peers")
+        .header("cookie", &session.cookies)
+        .header("x-csrf-token", &session.csrf_token)
+        .json(&serde_json::json!({
+            "device_id": device_id,
+            "public_key": public_key,
+            "overlay_ip": "10.100.0.255",
+        }))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(res.status(), 400);
+
+    let res = client
+        .post("http://localhost:3000/api/vpn/peers")
+        .header("cookie", &session.cookies)
+        .header("x-csrf-token", &session.csrf_token)
+        .json(&serde_json::json!({
+            "device_id": device_id,
+            "owner": "tests",
+            "public_key": public_key,
+            "tags": ["ci", "ci"],
+        }))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(res.status(), 201);
+    let peer: serde_json::Value = res.json().await.unwrap();
+    let id = peer["id"].as_u64().expect("Peer was not created");
+    let overlay_ip = peer["overlay_ip"].as_str().expect("No overlay IP assigned").to_string();
+    assert_eq!(peer["allowed_ips"], serde_json::json!([format!("{}/32", overlay_ip)]));
+    assert_eq!(peer["tags"], serde_json::json!(["ci"]));
+    assert_eq!(peer["online"], false);
+
+    // Anyone can claim a device_id without a token; that must not vouch for the peer
+    let url = format!("ws://localhost:3000/client/ws?device_id={}", device_id);
+    let (mut socket, _) = connect_async(url.as_str()).await.expect("Failed to connect");
+    socket.next().await;
+    let peer = vpn_peer(&session, id).await;
+    assert_eq!(peer["online"], false);
+    assert!(peer["last_handshake"].is_null(), "{:?}", peer);
+    socket.close(None).await.ok();
+
+    let issued: serde_json::Value = client
+        .get(format!("http://localhost:3000/client/token?device_id={}", device_id))
+        .header("authorization", format!("Bearer {}", admin_api_token()))
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .expect("Failed to parse response");
+    let token = issued["token"].as_str().expect("No token issued");
+    let url = format!("ws://localhost:3000/client/ws?token={}", token);
+    let (mut socket, _) = connect_async(url.as_str()).await.expect("Failed to connect");
+    socket.next().await;
+
+    let peer = vpn_peer(&session, id).await;
+    assert_eq!(peer["online"], true);
+    assert!(peer["last_handshake"].is_i64(), "{:?}", peer);
+
+    socket.close(None).await.ok();
+    let mut peer = vpn_peer(&session, id).await;
+    for _ in 0..20 {
+        if peer["online"] == false {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        peer = vpn_peer(&session, id).await;
+    }
+    assert_eq!(peer["online"], false);
+    assert!(peer["last_seen"].is_i64(), "{:?}", peer);
+
+    let res = client
+        .put(format!("http://localhost:3000/api/vpn/peers/{}", id))
+        .header("cookie", &session.cookies)
+        .header("x-csrf-token", &session.csrf_token)
+        .json(&serde_json::json!({
+            "device_id": device_id,
+            "name": "Test peer",
+            "public_key": public_key,
+        }))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(res.status(), 200);
+    let updated: serde_json::Value = res.json().await.unwrap();
+    assert_eq!(updated["name"], "Test peer");
+    // Address and presence survive an edit that doesn't mention them
+    assert_eq!(updated["overlay_ip"], overlay_ip.as_str());
+    assert_eq!(updated["last_handshake"], peer["last_handshake"]);
+
+    let res = client
+        .delete(format!("http://localhost:3000/api/vpn/peers/{}", id))
+        .header("cookie", &session.cookies)
+        .header("x-csrf-token", &session.csrf_token)
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(res.status(), 200);
+    let res = client
+        .get(format!("http://localhost:3000/api/vpn/peers/{}", id))
+        .header("cookie", &session.cookies)
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(res.status(), 404);
+
+    // A peer registered after the newest one was removed doesn't inherit its id
+    let res = client
+        .post("http://localhost:3000/api/vpn/peers")
+        .header("cookie", &session.cookies)
+        .header("x-csrf-token", &session.csrf_token)
+        .json(&serde_json::json!({"device_id": device_id, "public_key": public_key}))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(res.status(), 201);
+    let again: serde_json::Value = res.json().await.unwrap();
+    let again = again["id"].as_u64().expect("Peer was not created");
+    client
+        .delete(format!("http://localhost:3000/api/vpn/peers/{}", again))
+        .header("cookie", &session.cookies)
+        .header("x-csrf-token", &session.csrf_token)
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert!(again > id, "id {} was handed out again", again);
+}
+
+// Connects with extra request headers and returns the IP the server logged for it
+async fn logged_client_ip(device_id: &str, headers: &[(&'static str, &str)]) -> String {
+    let url = format!("ws://localhost:3000/client/ws?device_id={}", device_id);
+    let mut request = url.into_client_request().unwrap();
+    for (name, value) in headers {
+        request.headers_mut().insert(*name, value.parse().unwrap());
+    }
+    let (mut socket, _) = connect_async(request).await.expect("Failed to connect");
+    socket.next().await;
+    socket.close(None).await.ok();
+
+    let events = logged_actions(device_id).await;
+    let connected = events
+        .iter()
+        .find(|e| e["action"] == "CONNECTED")
+        .unwrap_or_else(|| panic!("No CONNECTED event: {:?}", events));
+    connected["ip"].as_str().unwrap().to_string()
+}
+
+#[tokio::test]
+async fn test_untrusted_forwarding_headers_are_ignored() {
+    // The server trusts 127.0.0.1 for X-Forwarded-For only
+    let device_id = format!("proxy-spoof-{}", std::process::id());
+    let ip = logged_client_ip(
+        &device_id,
+        &[("forwarded", "for=198.51.100.9"), ("x-real-ip", "198.51.100.10")],
+    )
+    .await;
+    assert_eq!(ip, "127.0.0.1");
+}
+
+#[tokio::test]
+async fn test_forwarded_for_walks_past_trusted_hops() {
+    // A client-supplied hop on the left, the client itself, then a trusted proxy
+    let device_id = format!("proxy-chain-{}", std::process::id());
+    let ip = logged_client_ip(
+        &device_id,
+        &[
+            ("x-forwarded-for", "198.51.100.1, 203.0.113.7, 127.0.0.1"),
+            ("forwarded", "for=198.51.100.9"),
+        ],
+    )
+    .await;
+    assert_eq!(ip, "203.0.113.7");
+}
+
+#[tokio::test]
+async fn test_unreadable_forwarded_hop_is_rejected() {
+    let device_id = format!("proxy-junk-{}", std::process::id());
+    // Junk a trusted proxy passed on: the client can't be known
+    assert_eq!(connect_status_from("203.0.113.7, unknown", &device_id).await, 400);
+    assert_eq!(connect_status_from("not-an-ip", &device_id).await, 400);
+    // Junk the client added left of its own address is never reached
+    assert_eq!(connect_status_from("not-an-ip, 203.0.113.8", &device_id).await, 101);
+}
+
+// Connects as if forwarded from `ip` by the trusted local proxy; returns the
+// handshake status (101 when admitted)
+async fn connect_status_from(ip: &str, device_id: &str) -> u16 {
+    let url = format!("ws://localhost:3000/client/ws?device_id={}", device_id);
+    let mut request = url.into_client_request().unwrap();
+    request.headers_mut().insert("x-forwarded-for", ip.parse().unwrap());
+    match connect_async(request).await {
+        Ok((mut socket, response)) => {
+            socket.next().await;
+            socket.close(None).await.ok();
+            response.status().as_u16()
+        }
+        Err(tokio_tungstenite::tungstenite::Error::Http(response)) => response.status().as_u16(),
+        Err(e) => panic!("Failed to connect: {}", e),
+    }
+}
+
+async fn add_access_rule(session: &AdminSession, rule: serde_json::Value) -> u64 {
+    let res = reqwest::Client::new()
+        .post("http://localhost:3000/api/access-rules")
+        .header("cookie", &session.cookies)
+        .header("x-csrf-token", &session.csrf_token)
+        .json(&rule)
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(res.status(), 201);
+    let rule: serde_json::Value = res.json().await.unwrap();
+    rule["id"].as_u64().expect("Rule was not created")
+}
+
+async fn remove_access_rule(session: &AdminSession, id: u64) {
+    reqwest::Client::new()
+        .delete(format!("http://localhost:3000/api/access-rules/{}", id))
+        .header("cookie", &session.cookies)
+        .header("x-csrf-token", &session.csrf_token)
+        .send()
+        .await
+        .expect("Failed to send request");
+}
+
+#[tokio::test]
+async fn test_access_rules_deny_and_allow() {
+    let session = admin_session().await;
+    let device_id = format!("access-rules-{}", std::process::id());
+    let deny = add_access_rule(
+        &session,
+        serde_json::json!({"network": "198.51.100.64/28", "action": "deny", "reason": "test"}),
+    )
+    .await;
+    let allow = add_access_rule(
+        &session,
+        serde_json::json!({"network": "198.51.100.70", "action": "allow"}),
+    )
+    .await;
+
+    let denied = connect_status_from("198.51.100.65", &device_id).await;
+    // An allow rule wins over a deny rule covering the same address
+    let allowed = connect_status_from("198.51.100.70", &device_id).await;
+    remove_access_rule(&session, deny).await;
+    remove_access_rule(&session, allow).await;
+    assert_eq!(denied, 403);
+    assert_eq!(allowed, 101);
+
+    let events = logged_actions(&device_id).await;
+    let blocked: Vec<_> = events.iter().filter(|e| e["action"] == "BLOCKED").collect();
+    assert_eq!(blocked.len(), 1, "{:?}", events);
+    assert_eq!(blocked[0]["ip"], "198.51.100.65");
+}
+
+#[tokio::test]
+async fn test_access_rule_ids_are_not_reused() {
+    let session = admin_session().await;
+    let rule = serde_json::json!({"network": "198.51.100.200", "action": "deny"});
+    let first = add_access_rule(&session, rule.clone()).await;
+    remove_access_rule(&session, first).await;
+    let second = add_access_rule(&session, rule).await;
+    remove_access_rule(&session, second).await;
+    assert!(second > first, "id {} was handed out again", second);
+}
+
+#[tokio::test]
+async fn test_access_rule_expires() {
+    use std::time::Duration;
+
+    let session = admin_session().await;
+    let device_id = format!("access-expiry-{}", std::process::id());
+    let id = add_access_rule(
+        &session,
+        serde_json::json!({"network": "198.51.100.96/28", "action": "deny", "expires_in_minutes": 1}),
+    )
+    .await;
+    assert_eq!(connect_status_from("198.51.100.97", &device_id).await, 403);
+
+    // Rules expire on the minute; give it a little slack
+    let mut status = 403;
+    for _ in 0..70 {
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        let rules: Vec<serde_json::Value> = reqwest::Client::new()
+            .get("http://localhost:3000/api/access-rules")
+            .header("cookie", &session.cookies)
+            .send()
+            .await
+            .expect("Failed to send request")
+            .json()
+            .await
+            .unwrap();
+        if !rules.iter().any(|r| r["id"] == id) {
+            status = connect_status_from("198.51.100.97", &device_id).await;
+            break;
+        }
+    }
+    remove_access_rule(&session, id).await;
+    assert_eq!(status, 101, "Rule did not expire");
+}
+
+// Reads until the server closes the socket and returns its close frame
+async fn close_frame<S>(socket: &mut S) -> (u16, String)
+where
+    S: futures_util::Stream<Item = Result<Message, tokio_tungstenite::tungstenite::Error>> + Unpin,
+{
+    while let Some(Ok(msg)) = socket.next().await {
+        if let Message::Close(Some(frame)) = msg {
+            return (frame.code.into(), frame.reason.to_string());
+        }
+    }
+    panic!("Socket ended without a close frame");
+}
+
+#[tokio::test]
+async fn test_kick_closes_socket_with_policy_code() {
+    let session = admin_session().await;
+    let device_id = format!("kick-{}", std::process::id());
+    let url = format!("ws://localhost:3000/client/ws?device_id={}", device_id);
+    let (mut socket, _) = connect_async(url.as_str()).await.expect("Failed to connect");
+    socket.next().await;
+
+    let res = reqwest::Client::new()
+        .post(format!("http://localhost:3000/htmx/active-users/{}/kick", device_id))
+        .header("cookie", &session.cookies)
+        .header("x-csrf-token", &session.csrf_token)
+        .header("content-type", "application/x-www-form-urlencoded")
+        .body(serde_urlencoded::to_string([("prompt", "Kicked by tests")]).unwrap())
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(res.status(), 200);
+
+    let (code, reason) = close_frame(&mut socket).await;
+    assert_eq!(code, 1008);
+    assert_eq!(reason, "Kicked by tests");
+
+    let events = logged_actions(&device_id).await;
+    let kicked: Vec<_> = events.iter().filter(|e| e["action"] == "KICKED").collect();
+    assert_eq!(kicked.len(), 1, "{:?}", events);
+    assert!(!events.iter().any(|e| e["action"] == "DISCONNECTED"), "{:?}", events);
+}
+
+#[tokio::test]
+async fn test_notice_keeps_non_ascii_text() {
+    let session = admin_session().await;
+    let device_id = format!("notice-{}", std::process::id());
+    let url = format!("ws://localhost:3000/client/ws?device_id={}", device_id);
+    let (mut socket, _) = connect_async(url.as_str()).await.expect("Failed to connect");
+    socket.next().await;
+
+    let text = "Wartung um 18 Uhr – 再起動します 🔧";
+    let res = reqwest::Client::new()
+        .post(format!("http://localhost:3000/htmx/active-users/{}/message", device_id))
+        .header("cookie", &session.cookies)
+        .header("x-csrf-token", &session.csrf_token)
+        .header("content-type", "application/x-www-form-urlencoded")
+        .body(serde_urlencoded::to_string([("prompt", text)]).unwrap())
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(res.status(), 200);
+    assert!(res.text().await.unwrap().contains(&format!("Message sent to {}", device_id)));
+
+    let notice = loop {
+        let Some(Ok(Message::Text(msg))) = socket.next().await else {
+            panic!("Socket closed before the notice arrived");
+        };
+        let value: serde_json::Value = serde_json::from_str(&msg).unwrap();
+        if value["type"] == "notice" {
+            break value;
+        }
+    };
+    socket.close(None).await.ok();
+    assert_eq!(notice["message"], text);
+}
+
+#[tokio::test]
+async fn test_banned_device_is_refused_on_reconnect() {
+    let session = admin_session().await;
+    let device_id = format!("ban-{}", std::process::id());
+    let url = format!("ws://localhost:3000/client/ws?device_id={}", device_id);
+    let (mut socket, _) = connect_async(url.as_str()).await.expect("Failed to connect");
+    socket.next().await;
+
+    let res = reqwest::Client::new()
+        .post(format!(
+            "http://localhost:3000/htmx/active-users/{}/ban?target=device",
+            device_id
+        ))
+        .header("cookie", &session.cookies)
+        .header("x-csrf-token", &session.csrf_token)
+        .header("content-type", "application/x-www-form-urlencoded")
+        .body(serde_urlencoded::to_string([("prompt", "")]).unwrap())
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(res.status(), 200);
+    let (code, _) = close_frame(&mut socket).await;
+
+    let status = match connect_async(url.as_str()).await {
+        Err(tokio_tungstenite::tungstenite::Error::Http(response)) => response.status().as_u16(),
+        Ok((mut socket, response)) => {
+            socket.close(None).await.ok();
+            response.status().as_u16()
+        }
+        Err(e) => panic!("Failed to connect: {}", e),
+    };
+
+    let rules: Vec<serde_json::Value> = reqwest::Client::new()
+        .get("http://localhost:3000/api/access-rules")
+        .header("cookie", &session.cookies)
+        .send()
+        .await
+        .expect("Failed to send request")
+        .json()
+        .await
+        .unwrap();
+    let rule = rules
+        .iter()
+        .find(|r| r["device_id"] == device_id.as_str())
+        .expect("No device rule was added");
+    remove_access_rule(&session, rule["id"].as_u64().unwrap()).await;
+
+    assert_eq!(code, 1008);
+    assert_eq!(status, 403);
+    let events = logged_actions(&device_id).await;
+    let actions: Vec<&str> = events.iter().filter_map(|e| e["action"].as_str()).collect();
+    assert_eq!(actions, ["CONNECTED", "KICKED", "BLOCKED"], "{:?}", events);
+}
+
+// Next announcement message on a client socket, skipping metrics updates;
+// None if nothing arrives within `wait`
+async fn next_announcement<S>(socket: &mut S, wait: std::time::Duration) -> Option<serde_json::Value>
+where
+    S: futures_util::Stream<Item = Result<Message, tokio_tungstenite::tungstenite::Error>> + Unpin,
+{
+    let deadline = tokio::time::Instant::now() + wait;
+    loop {
+        let msg = tokio::time::timeout_at(deadline, socket.next()).await.ok()??;
+        if let Ok(Message::Text(text)) = msg {
+            let value: serde_json::Value = serde_json::from_str(&text).unwrap();
+            if value["type"].as_str().is_some_and(|t| t.starts_with("announcement")) {
+                return Some(value);
+            }
+        }
+    }
+}
+
+async fn post_announcement(session: &AdminSession, body: serde_json::Value) -> serde_json::Value {
+    let res = reqwest::Client::new()
+        .post("http://localhost:3000/api/announcements")
+        .header("cookie", &session.cookies)
+        .header("x-csrf-token", &session.csrf_token)
+        .json(&body)
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(res.status(), 201);
+    res.json().await.unwrap()
+}
+
+#[tokio::test]
+async fn test_announcements_reach_their_room_and_replay_on_connect() {
+    use std::time::Duration;
+
+    let session = admin_session().await;
+    let room = format!("ws-room-{}", std::process::id());
+    let socket_in = |device: &str, room: &str| {
+        format!("ws://localhost:3000/client/ws?device_id={}-{}&room={}", device, std::process::id(), room)
+    };
+    let (mut member, _) = connect_async(socket_in("member", &room)).await.expect("Failed to connect");
+    let (mut outsider, _) = connect_async(socket_in("outsider", "elsewhere"))
+        .await
+        .expect("Failed to connect");
+    member.next().await;
+    outsider.next().await;
+
+    let announcement = post_announcement(
+        &session,
+        serde_json::json!({"message": "Room test", "severity": "warning", "room": room}),
+    )
+    .await;
+
+    let delivered = next_announcement(&mut member, Duration::from_secs(5))
+        .await
+        .expect("Announcement was not delivered");
+    assert_eq!(delivered["type"], "announcement");
+    assert_eq!(delivered["announcement"]["id"], announcement["id"]);
+    assert_eq!(delivered["announcement"]["message"], "Room test");
+    assert!(next_announcement(&mut outsider, Duration::from_secs(1)).await.is_none());
+
+    // A client joining the room later is sent it straight away
+    let (mut late, _) = connect_async(socket_in("late", &room)).await.expect("Failed to connect");
+    let replayed = next_announcement(&mut late, Duration::from_secs(5))
+        .await
+        .expect("Announcement was not replayed");
+    assert_eq!(replayed["announcement"]["id"], announcement["id"]);
+
+    let res = reqwest::Client::new()
+        .delete(format!("http://localhost:3000/api/announcements/{}", announcement["id"]))
+        .header("cookie", &session.cookies)
+        .header("x-csrf-token", &session.csrf_token)
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(res.status(), 200);
+
+    let ended = next_announcement(&mut member, Duration::from_secs(5))
+        .await
+        .expect("End was not delivered");
+    assert_eq!(ended["type"], "announcement_ended");
+    assert_eq!(ended["announcement"]["id"], announcement["id"]);
+
+    // Ended announcements are not replayed
+    let (mut after, _) = connect_async(socket_in("after", &room)).await.expect("Failed to connect");
+    assert!(next_announcement(&mut after, Duration::from_secs(1)).await.is_none());
+
+    for socket in [&mut member, &mut outsider, &mut late, &mut after] {
+        socket.close(None).await.ok();
+    }
+}
+
+#[tokio::test]
+async fn test_announcement_end_is_sent_when_it_expires() {
+    use std::time::Duration;
+
+    let session = admin_session().await;
+    let room = format!("expiry-room-{}", std::process::id());
+    let url = format!("ws://localhost:3000/client/ws?device_id=expiry-{}&room={}", std::process::id(), room);
+    let (mut socket, _) = connect_async(url).await.expect("Failed to connect");
+    socket.next().await;
+
+    let announcement = post_announcement(
+        &session,
+        serde_json::json!({"message": "Expiry test", "severity": "info", "room": room, "expires_in_minutes": 1}),
+    )
+    .await;
+    let delivered = next_announcement(&mut socket, Duration::from_secs(5))
+        .await
+        .expect("Announcement was not delivered");
+    assert_eq!(delivered["type"], "announcement");
+
+    let ended = next_announcement(&mut socket, Duration::from_secs(70))
+        .await
+        .expect("Expiry was not delivered");
+    socket.close(None).await.ok();
+    assert_eq!(ended["type"], "announcement_ended");
+    assert_eq!(ended["announcement"]["id"], announcement["id"]);
+}
+
+// Run without ALLOWED_ORIGINS: only the client endpoints are cross-origin
+#[tokio::test]
+async fn test_default_cors_covers_only_client_routes() {
+    let client = reqwest::Client::new();
+    let preflight = |path: &str| {
+        client
+            .request(reqwest::Method::OPTIONS, format!("http://localhost:3000{}", path))
+            .header("origin", "https://elsewhere.example")
+            .header("access-control-request-method", "POST")
+            .send()
+    };
+
+    let res = preflight("/client/heartbeat").await.expect("Failed to send request");
+    assert_eq!(res.headers()["access-control-allow-origin"], "https://elsewhere.example");
+    assert!(!res.headers().contains_key("access-control-allow-credentials"));
+
+    let res = preflight("/api/logs").await.expect("Failed to send request");
+    assert!(!res.headers().contains_key("access-control-allow-origin"));
+}
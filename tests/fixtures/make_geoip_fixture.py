@@ -0,0 +1,153 @@
+#!/usr/bin/env python3
+"""
+Writes geoip-test.mmdb, a tiny MaxMind DB (format 2.0) with City and ASN
+style records for a handful of networks. Loopback maps to the made-up
+country "ZZ" so tests against a local server resolve a location.
+
+Run:
+    python3 tests/fixtures/make_geoip_fixture.py
+"""
+
+import ipaddress
+import struct
+from pathlib import Path
+
+NETWORKS = {
+    "127.0.0.0/8": ("ZZ", "Testland", "Loopback City", 64512, "Test Network"),
+    "::1/128": ("ZZ", "Testland", "Loopback City", 64512, "Test Network"),
+    "81.2.69.0/24": ("GB", "United Kingdom", "London", 20712, "Andrews & Arnold Ltd"),
+    "89.160.20.0/24": ("SE", "Sweden", "Linköping", 29518, "Bredband2 AB"),
+    "216.160.83.56/29": ("US", "United States", "Milton", 209, "CenturyLink"),
+    "2001:480::/32": ("US", "United States", "San Diego", 6939, "Hurricane Electric"),
+}
+
+RECORD_SIZE = 24
+# Fixed so regenerating gives an identical file
+BUILD_EPOCH = 1735689600
+
+
+def control(type_id, size):
+    """Control byte(s) for a field of `type_id` carrying `size`."""
+    if size < 29:
+        first, extra = size, b""
+    elif size < 285:
+        first, extra = 29, bytes([size - 29])
+    elif size < 65821:
+        first, extra = 30, struct.pack(">H", size - 285)
+    else:
+        first, extra = 31, struct.pack(">I", size - 65821)[1:]
+
+    if type_id <= 7:
+        return bytes([(type_id << 5) | first]) + extra
+    return bytes([first, type_id - 7]) + extra
+
+
+def encode(value, uint_type=6):
+    if isinstance(value, str):
+        data = value.encode("utf-8")
+        return control(2, len(data)) + data
+    if isinstance(value, dict):
+        out = control(7, len(value))
+        for key, item in value.items():
+            out += encode(key) + encode(item, uint_type)
+        return out
+    if isinstance(value, list):
+        out = control(11, len(value))
+        for item in value:
+            out += encode(item, uint_type)
+        return out
+    if isinstance(value, int):
+        data = value.to_bytes((value.bit_length() + 7) // 8, "big") if value else b""
+        return control(uint_type, len(data)) + data
+    raise TypeError(value)
+
+
+def record(country, country_name, city, asn, org):
+    return {
+        "city": {"names": {"en": city}},
+        "country": {"iso_code": country, "names": {"en": country_name}},
+        "autonomous_system_number": asn,
+        "autonomous_system_organization": org,
+    }
+
+
+def prefix_bits(cidr):
+    net = ipaddress.ip_network(cidr)
+    value = int(net.network_address)
+    width = net.max_prefixlen
+    bits = [(value >> (width - 1 - i)) & 1 for i in range(net.prefixlen)]
+    # IPv4 lives in the ::/96 subtree of an IPv6 database
+    return [0] * 96 + bits if net.version == 4 else bits
+
+
+def build():
+    # Data section: one record per network
+    data = b""
+    offsets = []
+    for fields in NETWORKS.values():
+        offsets.append(len(data))
+        data += encode(record(*fields))
+
+    # Search tree as nested nodes: [left, right], leaves are ("data", index)
+    root = [None, None]
+    for index, cidr in enumerate(NETWORKS):
+        node = root
+        bits = prefix_bits(cidr)
+        for bit in bits[:-1]:
+            if node[bit] is None:
+                node[bit] = [None, None]
+            node = node[bit]
+        node[bits[-1]] = ("data", index)
+
+    nodes = []
+
+    def number(node):
+        nodes.append(node)
+        for child in node:
+            if isinstance(child, list):
+                number(child)
+
+    number(root)
+    ids = {id(node): i for i, node in enumerate(nodes)}
+    node_count = len(nodes)
+
+    def value(child):
+        if child is None:
+            return node_count
+        if isinstance(child, tuple):
+            return node_count + 16 + offsets[child[1]]
+        return ids[id(child)]
+
+    tree = b""
+    for node in nodes:
+        for child in node:
+            tree += value(child).to_bytes(3, "big")
+
+    metadata = {
+        "binary_format_major_version": 2,
+        "binary_format_minor_version": 0,
+        "build_epoch": BUILD_EPOCH,
+        "database_type": "Counter-Test-City-ASN",
+        "description": {"en": "Fixture for the counter test suite"},
+        "ip_version": 6,
+        "languages": ["en"],
+        "node_count": node_count,
+        "record_size": RECORD_SIZE,
+    }
+    meta = control(7, len(metadata))
+    for key, item in metadata.items():
+        meta += encode(key)
+        if key in ("binary_format_major_version", "binary_format_minor_version", "ip_version", "record_size"):
+            meta += encode(item, uint_type=5)
+        elif key == "build_epoch":
+            meta += encode(item, uint_type=9)
+        else:
+            meta += encode(item)
+
+    return tree + b"\x00" * 16 + data + b"\xab\xcd\xefMaxMind.com" + meta
+
+
+if __name__ == "__main__":
+    target = Path(__file__).with_name("geoip-test.mmdb")
+    target.write_bytes(build())
+    print(f"Wrote {target}")
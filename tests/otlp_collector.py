@@ -0,0 +1,79 @@
+#!/usr/bin/env python3
+"""
+OTLP Collector Stand-in
+Accepts OTLP/HTTP protobuf trace exports and prints the span names it receives
+
+Run, then start the server with OTEL_EXPORTER_OTLP_ENDPOINT=http://127.0.0.1:4318:
+    python3 tests/otlp_collector.py [port]
+"""
+
+import sys
+from http.server import BaseHTTPRequestHandler, HTTPServer
+
+
+def fields(data):
+    """Yields (field number, wire type, value) for one protobuf message."""
+    pos = 0
+    while pos < len(data):
+        key, pos = varint(data, pos)
+        number, wire_type = key >> 3, key & 7
+        if wire_type == 0:
+            value, pos = varint(data, pos)
+        elif wire_type == 1:
+            value, pos = data[pos:pos + 8], pos + 8
+        elif wire_type == 2:
+            length, pos = varint(data, pos)
+            value, pos = data[pos:pos + length], pos + length
+        elif wire_type == 5:
+            value, pos = data[pos:pos + 4], pos + 4
+        else:
+            raise ValueError(f"unsupported wire type {wire_type}")
+        yield number, wire_type, value
+
+
+def varint(data, pos):
+    result = shift = 0
+    while True:
+        byte = data[pos]
+        pos += 1
+        result |= (byte & 0x7F) << shift
+        if not byte & 0x80:
+            return result, pos
+        shift += 7
+
+
+def span_names(request):
+    """ExportTraceServiceRequest -> ResourceSpans(1) -> ScopeSpans(2) -> Span(2).name(5)"""
+    for number, _, resource_spans in fields(request):
+        if number != 1:
+            continue
+        for number, _, scope_spans in fields(resource_spans):
+            if number != 2:
+                continue
+            for number, _, span in fields(scope_spans):
+                if number != 2:
+                    continue
+                for number, _, value in fields(span):
+                    if number == 5:
+                        yield value.decode("utf-8", "replace")
+
+
+class Collector(BaseHTTPRequestHandler):
+    def do_POST(self):
+        body = self.rfile.read(int(self.headers.get("Content-Length", 0)))
+        if self.path == "/v1/traces":
+            names = list(span_names(body))
+            print(f"{len(names)} spans: {', '.join(names)}", flush=True)
+        self.send_response(200)
+        self.send_header("Content-Type", "application/x-protobuf")
+        self.send_header("Content-Length", "0")
+        self.end_headers()
+
+    def log_message(self, *args):
+        pass
+
+
+if __name__ == "__main__":
+    port = int(sys.argv[1]) if len(sys.argv) > 1 else 4318
+    print(f"Listening for OTLP exports on 127.0.0.1:{port}", flush=True)
+    HTTPServer(("127.0.0.1", port), Collector).serve_forever()
@@ -0,0 +1,34 @@
+#!/usr/bin/env python3
+"""
+Webhook Receiver Stand-in
+Accepts webhook POSTs and prints each JSON payload it receives
+
+Run, then point a webhook at it, e.g. ALERT_WEBHOOK_URLS=http://127.0.0.1:9000/alerts:
+    python3 tests/webhook_receiver.py [port]
+"""
+
+import json
+import sys
+from http.server import BaseHTTPRequestHandler, HTTPServer
+
+
+class Receiver(BaseHTTPRequestHandler):
+    def do_POST(self):
+        body = self.rfile.read(int(self.headers.get("Content-Length", 0)))
+        try:
+            payload = json.dumps(json.loads(body), indent=2)
+        except ValueError:
+            payload = body.decode("utf-8", "replace")
+        print(f"POST {self.path}\n{payload}", flush=True)
+        self.send_response(200)
+        self.send_header("Content-Length", "0")
+        self.end_headers()
+
+    def log_message(self, *args):
+        pass
+
+
+if __name__ == "__main__":
+    port = int(sys.argv[1]) if len(sys.argv) > 1 else 9000
+    print(f"Listening for webhooks on 127.0.0.1:{port}", flush=True)
+    HTTPServer(("127.0.0.1", port), Receiver).serve_forever()